@@ -0,0 +1,17 @@
+-- Users table for the sql storage backend, mirrors the User model
+-- Written to run on both SQLite and Postgres
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY NOT NULL,
+    email TEXT NOT NULL,
+    username TEXT NOT NULL,
+    bio TEXT NOT NULL,
+    image TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    version BIGINT NOT NULL DEFAULT 0,
+    deleted_at TEXT
+);
+
+-- Emails and usernames are unique regardless of case, same as the DynamoDB uniqueness items
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_unique ON users (lower(email));
+CREATE UNIQUE INDEX IF NOT EXISTS users_username_unique ON users (lower(username));
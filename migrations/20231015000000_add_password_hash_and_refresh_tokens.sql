@@ -0,0 +1,12 @@
+-- Argon2id PHC string, users created without a password cannot log in with one
+ALTER TABLE users ADD COLUMN password_hash TEXT;
+
+-- One row per login, the family is rotated on every refresh and revoked on logout or reuse
+-- Mirrors the RefreshTokenFamily model
+CREATE TABLE IF NOT EXISTS refresh_token_families (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    token_hash TEXT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    revoked_at TEXT
+);
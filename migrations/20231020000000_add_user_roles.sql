@@ -0,0 +1,2 @@
+-- Role of the user, either user or admin, mirrors the Role enum
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
@@ -0,0 +1,13 @@
+-- Api keys for service to service callers, mirrors the ApiKey model
+-- Scopes are stored space separated e.g 'users:read users:admin'
+CREATE TABLE IF NOT EXISTS api_keys (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL,
+    scopes TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at BIGINT,
+    last_used_at BIGINT,
+    revoked_at TEXT
+);
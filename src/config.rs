@@ -23,4 +23,120 @@ pub struct AppConfig {
     /// The address to be generated in the openapi.json file
     #[clap(env)]
     pub openapi_server_address: Option<String>,
+    /// Seconds to keep serving while reporting unhealthy on shutdown, before we stop accepting connections
+    /// Should be longer than the load balancer health check interval, defaulted to 5 if not specified
+    #[clap(env)]
+    pub shutdown_readiness_delay: Option<u64>,
+    /// Seconds to wait for in-flight requests once we stop accepting connections, before exiting anyway
+    /// Defaulted to 30 if not specified
+    #[clap(env)]
+    pub shutdown_drain_timeout: Option<u64>,
+    /// Milliseconds each /health/ready dependency check may take before it is reported as timed out
+    /// Defaulted to 2000 if not specified
+    #[clap(env)]
+    pub health_check_timeout: Option<u64>,
+    /// Milliseconds /health/ready reuses the results of its dependency checks for
+    /// Defaulted to 5000 if not specified
+    #[clap(env)]
+    pub health_check_cache_ttl: Option<u64>,
+    /// Path to a PEM certificate chain, the server speaks HTTPS instead of HTTP when set
+    /// The certificate is reloaded whenever the file changes
+    #[clap(env)]
+    pub tls_cert_path: Option<String>,
+    /// Path to the PEM private key of the certificate, required when tls_cert_path is set
+    #[clap(env)]
+    pub tls_key_path: Option<String>,
+    /// Path to a PEM bundle of CAs, when set clients must present a certificate signed by one of them
+    #[clap(env)]
+    pub tls_client_ca_path: Option<String>,
+    /// Secret used to sign pagination cursors, must be the same across all instances
+    /// A random secret is generated on startup if not specified, which invalidates cursors on restart
+    #[clap(env)]
+    pub pagination_secret: Option<String>,
+    /// Name of the users table GSI keyed by email
+    /// Defaulted to email-index if not specified
+    #[clap(env)]
+    pub user_email_index: Option<String>,
+    /// Name of the users table GSI keyed by username
+    /// Defaulted to username-index if not specified
+    #[clap(env)]
+    pub user_username_index: Option<String>,
+    /// Where users are stored, either dynamodb or sql
+    /// sql requires the binary to be built with the sqlite or postgres feature
+    /// Defaulted to dynamodb if not specified
+    #[clap(env)]
+    pub storage: Option<String>,
+    /// Database url used when storage is sql e.g sqlite://users.db?mode=rwc or postgres://localhost/users
+    #[clap(env)]
+    pub database_url: Option<String>,
+
+    // Authentication related envs, at least one of jwt_secret or jwt_jwks_path has to be set
+    /// Secret used to verify HS256 signed bearer tokens
+    #[clap(env)]
+    pub jwt_secret: Option<String>,
+    /// Path to a JWKS file with the public keys used to verify RS256 and EdDSA signed bearer tokens
+    #[clap(env)]
+    pub jwt_jwks_path: Option<String>,
+    /// Expected iss claim of bearer tokens, not checked if not specified
+    #[clap(env)]
+    pub jwt_issuer: Option<String>,
+    /// Expected aud claim of bearer tokens, not checked if not specified
+    #[clap(env)]
+    pub jwt_audience: Option<String>,
+    /// Lifetime in seconds of the access tokens issued on login and refresh
+    /// Defaulted to 900 (15 minutes) if not specified
+    #[clap(env)]
+    pub jwt_access_token_ttl: Option<u64>,
+    /// Lifetime in seconds of a refresh token family, counted from login
+    /// Defaulted to 2592000 (30 days) if not specified
+    #[clap(env)]
+    pub jwt_refresh_token_ttl: Option<u64>,
+
+    // OIDC related envs, OIDC login is disabled unless oidc_issuer is set
+    /// Issuer url of the OpenID Connect provider, its discovery document is read from
+    /// <issuer>/.well-known/openid-configuration
+    #[clap(env)]
+    pub oidc_issuer: Option<String>,
+    /// Client id registered with the provider, required when oidc_issuer is set
+    #[clap(env)]
+    pub oidc_client_id: Option<String>,
+    /// Client secret registered with the provider, leave empty for public clients
+    #[clap(env)]
+    pub oidc_client_secret: Option<String>,
+    /// Redirect uri registered with the provider, pointing at /auth/oidc/callback
+    /// Required when oidc_issuer is set
+    #[clap(env)]
+    pub oidc_redirect_uri: Option<String>,
+
+    // Logging related envs
+    /// Format of the log lines, either text or json
+    /// Defaulted to text if not specified
+    #[clap(env)]
+    pub log_format: Option<String>,
+    /// Which log lines are written, with the directives of tracing_subscriber's EnvFilter
+    /// e.g info or warn,rust_axum_scaffold=debug, defaulted to info if not specified
+    #[clap(env)]
+    pub rust_log: Option<String>,
+
+    // Tracing related envs, spans are not exported unless otel_traces_exporter is set
+    /// Where spans are exported, one of none, otlp, stdout or file
+    /// stdout and file write a JSON line per span and are meant for local debugging
+    /// Defaulted to none if not specified
+    #[clap(env)]
+    pub otel_traces_exporter: Option<String>,
+    /// Base url of the OTLP/HTTP collector, spans are sent to <endpoint>/v1/traces
+    /// Defaulted to http://localhost:4318 if not specified
+    #[clap(env)]
+    pub otel_exporter_otlp_endpoint: Option<String>,
+    /// File the spans are appended to, required when otel_traces_exporter is file
+    #[clap(env)]
+    pub otel_traces_file_path: Option<String>,
+    /// Ratio of the traces started by this server that are sampled, between 0.0 and 1.0
+    /// Traces started by a caller follow the sampling decision of its traceparent header
+    /// Defaulted to 1.0 if not specified
+    #[clap(env)]
+    pub otel_traces_sampler_ratio: Option<f64>,
+    /// service.name reported on every span, defaulted to rust-axum-scaffold if not specified
+    #[clap(env)]
+    pub otel_service_name: Option<String>,
 }
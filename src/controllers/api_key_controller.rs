@@ -0,0 +1,216 @@
+// Admin endpoints to mint, list and revoke the api keys used by service to service callers
+// Callers send the minted key in the X-Api-Key header instead of a bearer token
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get},
+    Json, Router,
+};
+
+use crate::{
+    domain::api_key::view_models::{ApiKeyViewModel, CreateApiKeyViewModel, CreatedApiKeyViewModel},
+    errors::AppResult,
+    extractors::{
+        authorized::{Authorized, UsersAdmin},
+        validated::ValidatedJson,
+    },
+    services::{api_key_service::ApiKeyService, service_register::ServiceRegister},
+};
+
+pub fn router() -> Router<ServiceRegister> {
+    Router::new()
+        .route("/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/api-keys/:id", delete(revoke_api_key))
+}
+
+/// Mint an api key
+/// The key is only returned in this response, store it somewhere safe
+#[utoipa::path(
+    post,
+    path = "/api-keys",
+    request_body = CreateApiKeyViewModel,
+    responses(
+        (status = 201, description = "Successfully minted api key", body = CreatedApiKeyViewModel),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Missing the users:admin permission"),
+        (status = 422, description = "Missing name or no scopes were given"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:admin"]), ("api_key" = ["users:admin"])),
+    tag = "api-key",
+)]
+pub async fn create_api_key(
+    auth_user: Authorized<UsersAdmin>,
+    State(api_key_service): State<ApiKeyService>,
+    ValidatedJson(request): ValidatedJson<CreateApiKeyViewModel>,
+) -> AppResult<(StatusCode, Json<CreatedApiKeyViewModel>)> {
+    let created = api_key_service
+        .create_api_key(request, auth_user.id.clone())
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+/// List api keys
+/// Revoked keys are listed as well
+#[utoipa::path(
+    get,
+    path = "/api-keys",
+    responses(
+        (status = 200, description = "Successfully listed api keys", body = [ApiKeyViewModel]),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Missing the users:admin permission"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:admin"]), ("api_key" = ["users:admin"])),
+    tag = "api-key",
+)]
+pub async fn list_api_keys(
+    _auth_user: Authorized<UsersAdmin>,
+    State(api_key_service): State<ApiKeyService>,
+) -> AppResult<Json<Vec<ApiKeyViewModel>>> {
+    let api_keys = api_key_service.list_api_keys().await?;
+
+    Ok(Json(api_keys))
+}
+
+/// Revoke an api key
+/// Requests made with the key are rejected from now on, revoking a key twice does nothing
+#[utoipa::path(
+    delete,
+    path = "/api-keys/:id",
+    responses(
+        (status = 200, description = "Successfully revoked api key", body = ApiKeyViewModel),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Missing the users:admin permission"),
+        (status = 404, description = "Api key not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:admin"]), ("api_key" = ["users:admin"])),
+    tag = "api-key",
+)]
+pub async fn revoke_api_key(
+    _auth_user: Authorized<UsersAdmin>,
+    Path(id): Path<String>,
+    State(api_key_service): State<ApiKeyService>,
+) -> AppResult<Json<ApiKeyViewModel>> {
+    let revoked = api_key_service.revoke_api_key(id).await?;
+
+    Ok(Json(revoked))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use tower::ServiceExt;
+
+    use crate::{
+        controllers::{api_key_controller, user_controller},
+        domain::user::models::{Role, User},
+        repositories::{
+            in_memory_api_key_repository::InMemoryApiKeyRepository,
+            in_memory_user_repository::InMemoryUserRepository, user_store::UserStore,
+        },
+        services::{
+            api_key_service::ApiKeyService,
+            service_register::ServiceRegister,
+            token_service::{Claims, TokenService},
+            user_service::UserService,
+        },
+        utils::dynamodb_helpers::PageCursorCodec,
+    };
+
+    async fn get_service_register() -> ServiceRegister {
+        let user_repository = InMemoryUserRepository::new();
+        user_repository
+            .put_user(&User::new(
+                "ppId123".to_string(),
+                "pp@gmail.com".to_string(),
+                "pplogin".to_string(),
+                "I love to eat".to_string(),
+                None,
+            ))
+            .await
+            .unwrap();
+
+        ServiceRegister {
+            user_service: Some(UserService::new(user_repository, PageCursorCodec::new("secret"))),
+            token_service: Some(TokenService::new(Some(b"secret"), None)),
+            auth_service: None,
+            api_key_service: Some(ApiKeyService::new(InMemoryApiKeyRepository::new())),
+            oidc_service: None,
+            health_service: None,
+        }
+    }
+
+    fn admin_token() -> String {
+        let claims = Claims {
+            sub: "adminId123".to_string(),
+            exp: jsonwebtoken::get_current_timestamp() + 60,
+            nbf: None,
+            iss: None,
+            aud: None,
+            role: Role::Admin,
+        };
+
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap()
+    }
+
+    // Test success path, from minting a key to using and revoking it
+    #[tokio::test]
+    async fn minted_key_authenticates_until_revoked() {
+        // Arrange
+        let router = api_key_controller::router()
+            .merge(user_controller::router())
+            .with_state(get_service_register().await);
+        let mint = Request::builder()
+            .uri("/api-keys")
+            .method(Method::POST)
+            .header(header::AUTHORIZATION, format!("Bearer {}", admin_token()))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({ "name": "nightly-export", "scopes": ["users:admin"] }).to_string(),
+            ))
+            .unwrap();
+        let get_user = |key: &str| {
+            Request::builder()
+                .uri("/user/ppId123")
+                .header("x-api-key", key)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Act
+        let minted = router.clone().oneshot(mint).await.unwrap();
+        let minted_status = minted.status();
+        let body = axum::body::to_bytes(minted.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let key = created["key"].as_str().unwrap();
+        let before_revoke = router.clone().oneshot(get_user(key)).await.unwrap();
+        let revoke = Request::builder()
+            .uri(format!("/api-keys/{}", created["api_key"]["id"].as_str().unwrap()))
+            .method(Method::DELETE)
+            .header(header::AUTHORIZATION, format!("Bearer {}", admin_token()))
+            .body(Body::empty())
+            .unwrap();
+        let revoked = router.clone().oneshot(revoke).await.unwrap();
+        let after_revoke = router.oneshot(get_user(key)).await.unwrap();
+
+        // Assert
+        assert_eq!(minted_status, 201);
+        assert_eq!(before_revoke.status(), 200);
+        assert_eq!(revoked.status(), 200);
+        assert_eq!(after_revoke.status(), 401);
+    }
+}
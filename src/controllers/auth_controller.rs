@@ -0,0 +1,204 @@
+// Endpoints to trade credentials for tokens
+// These are the only endpoints besides POST /users that do not require a bearer token
+
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+
+use crate::{
+    domain::auth::view_models::{LoginViewModel, RefreshTokenViewModel, TokenViewModel},
+    errors::AppResult,
+    extractors::validated::ValidatedJson,
+    services::{auth_service::AuthService, service_register::ServiceRegister},
+};
+
+pub fn router() -> Router<ServiceRegister> {
+    Router::new()
+        .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout))
+}
+
+/// Log in
+/// Returns an access token and a refresh token for the given email and password
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    request_body = LoginViewModel,
+    responses(
+        (status = 200, description = "Successfully logged in", body = TokenViewModel),
+        (status = 401, description = "Wrong email or password"),
+        (status = 422, description = "Malformed email"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    tag = "auth",
+)]
+pub async fn login(
+    State(auth_service): State<AuthService>,
+    ValidatedJson(request): ValidatedJson<LoginViewModel>,
+) -> AppResult<Json<TokenViewModel>> {
+    let tokens = auth_service.login(request).await?;
+
+    Ok(Json(tokens))
+}
+
+/// Refresh tokens
+/// Trades a refresh token for a new token pair, each refresh token can only be used once
+/// Using a refresh token twice logs out every client that shares its login
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    request_body = RefreshTokenViewModel,
+    responses(
+        (status = 200, description = "Successfully refreshed tokens", body = TokenViewModel),
+        (status = 401, description = "Refresh token is invalid, expired, revoked or has already been used"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    tag = "auth",
+)]
+pub async fn refresh(
+    State(auth_service): State<AuthService>,
+    Json(request): Json<RefreshTokenViewModel>,
+) -> AppResult<Json<TokenViewModel>> {
+    let tokens = auth_service.refresh(request.refresh_token).await?;
+
+    Ok(Json(tokens))
+}
+
+/// Log out
+/// Revokes the refresh token and every token refreshed from the same login
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    request_body = RefreshTokenViewModel,
+    responses(
+        (status = 204, description = "Successfully logged out"),
+        (status = 401, description = "Refresh token is invalid"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    tag = "auth",
+)]
+pub async fn logout(
+    State(auth_service): State<AuthService>,
+    Json(request): Json<RefreshTokenViewModel>,
+) -> AppResult<StatusCode> {
+    auth_service.logout(request.refresh_token).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use tower::ServiceExt;
+
+    use crate::{
+        controllers::{auth_controller, user_controller},
+        repositories::{
+            in_memory_refresh_token_repository::InMemoryRefreshTokenRepository,
+            in_memory_user_repository::InMemoryUserRepository,
+        },
+        services::{
+            auth_service::AuthService, service_register::ServiceRegister,
+            token_service::TokenService, user_service::UserService,
+        },
+        utils::dynamodb_helpers::PageCursorCodec,
+    };
+
+    fn get_service_register() -> ServiceRegister {
+        let user_repository = InMemoryUserRepository::new();
+        let token_service = TokenService::new(Some(b"secret"), None);
+
+        ServiceRegister {
+            user_service: Some(UserService::new(
+                user_repository.clone(),
+                PageCursorCodec::new("secret"),
+            )),
+            auth_service: Some(AuthService::new(
+                user_repository,
+                InMemoryRefreshTokenRepository::new(),
+                token_service.clone(),
+            )),
+            token_service: Some(token_service),
+            api_key_service: None,
+            oidc_service: None,
+            health_service: None,
+        }
+    }
+
+    fn json_request(method: Method, uri: &str, body: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .method(method)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    // Test success path, from registration to using the access token
+    #[tokio::test]
+    async fn register_login_and_get_current_user() {
+        // Arrange
+        let router = auth_controller::router()
+            .merge(user_controller::router())
+            .with_state(get_service_register());
+        let register = json_request(
+            Method::POST,
+            "/users",
+            serde_json::json!({
+                "email": "pp@gmail.com",
+                "username": "pplogin",
+                "password": "password123",
+            }),
+        );
+        let login = json_request(
+            Method::POST,
+            "/auth/login",
+            serde_json::json!({ "email": "pp@gmail.com", "password": "password123" }),
+        );
+
+        // Act
+        let registered = router.clone().oneshot(register).await.unwrap();
+        let logged_in = router.clone().oneshot(login).await.unwrap();
+        let body = axum::body::to_bytes(logged_in.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let tokens: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let current_user = router
+            .oneshot(
+                Request::builder()
+                    .uri("/user")
+                    .header(
+                        header::AUTHORIZATION,
+                        format!("Bearer {}", tokens["access_token"].as_str().unwrap()),
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(registered.status(), 201);
+        assert_eq!(current_user.status(), 200);
+    }
+
+    // Test failure path
+    #[tokio::test]
+    async fn refresh_rejects_malformed_token() {
+        // Arrange
+        let router = auth_controller::router().with_state(get_service_register());
+        let request = json_request(
+            Method::POST,
+            "/auth/refresh",
+            serde_json::json!({ "refresh_token": "not-a-refresh-token" }),
+        );
+
+        // Act
+        let response = router.oneshot(request).await.unwrap();
+
+        // Assert
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -1,20 +1,143 @@
-use axum::{Router, routing::get, http::StatusCode};
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
 
-use crate::services::service_register::ServiceRegister;
+use crate::{
+    domain::health::view_models::{HealthStatus, HealthViewModel},
+    services::{health_service::HealthService, service_register::ServiceRegister},
+};
 
 pub fn router() -> Router<ServiceRegister> {
-    Router::new().route("/health", get(get_health_check))
+    Router::new()
+        .route("/health", get(get_health_check))
+        .route("/health/live", get(get_liveness))
+        .route("/health/ready", get(get_readiness))
 }
 
+/// Health check
+/// Same as /health/ready, kept for load balancers that are already configured with it
 #[utoipa::path(
     get,
     path = "/health",
     responses(
-        (status = 200, description = "Health check passed"),
-        (status = 500, description = "Internal Server Error"),
+        (status = 200, description = "Ready to receive traffic", body = HealthViewModel),
+        (status = 503, description = "A critical dependency is failing or the server is shutting down", body = HealthViewModel),
     ),
     tag = "health",
 )]
-async fn get_health_check() -> Result<StatusCode, StatusCode> {
-    Ok(StatusCode::OK)
-}
\ No newline at end of file
+async fn get_health_check(
+    State(health_service): State<HealthService>,
+) -> (StatusCode, Json<HealthViewModel>) {
+    get_readiness(State(health_service)).await
+}
+
+/// Liveness probe
+/// Passes as long as the process is up and serving requests, dependencies are not checked
+/// so that an outage of DynamoDB does not get every instance restarted
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    responses(
+        (status = 200, description = "Process is up"),
+    ),
+    tag = "health",
+)]
+async fn get_liveness() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Readiness probe
+/// Runs the dependency checks and lists the status and latency of each of them
+/// Fails when a critical check fails or once the server starts shutting down
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    responses(
+        (status = 200, description = "Ready to receive traffic, non critical checks may be failing", body = HealthViewModel),
+        (status = 503, description = "A critical dependency is failing or the server is shutting down", body = HealthViewModel),
+    ),
+    tag = "health",
+)]
+async fn get_readiness(
+    State(health_service): State<HealthService>,
+) -> (StatusCode, Json<HealthViewModel>) {
+    let health = health_service.readiness().await;
+    let status_code = match health.status {
+        HealthStatus::Ok | HealthStatus::Degraded => StatusCode::OK,
+        HealthStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status_code, Json(health))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{body::Body, http::Request, Router};
+    use tower::ServiceExt;
+
+    use crate::{
+        controllers::health,
+        services::{
+            health_service::{test::StubCheck, HealthService},
+            service_register::ServiceRegister,
+        },
+    };
+
+    fn get_router(health_service: HealthService) -> Router {
+        health::router().with_state(ServiceRegister {
+            user_service: None,
+            token_service: None,
+            auth_service: None,
+            api_key_service: None,
+            oidc_service: None,
+            health_service: Some(health_service),
+        })
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn health_check_fails_once_not_ready() {
+        // Arrange
+        let health_service = HealthService::new();
+        let router = get_router(health_service.clone());
+
+        // Act
+        let ready = router.clone().oneshot(get("/health")).await.unwrap();
+        health_service.mark_not_ready();
+        let draining = router.clone().oneshot(get("/health")).await.unwrap();
+        let live = router.oneshot(get("/health/live")).await.unwrap();
+
+        // Assert
+        assert_eq!(ready.status(), 200);
+        assert_eq!(draining.status(), 503);
+        assert_eq!(live.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn readiness_fails_when_a_critical_check_fails() {
+        // Arrange
+        let router = get_router(
+            HealthService::new()
+                .with_check(Arc::new(StubCheck::new("dynamodb:users", true, false)))
+                .with_check(Arc::new(StubCheck::new("cache", false, true))),
+        );
+
+        // Act
+        let response = router.oneshot(get("/health/ready")).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        // Assert
+        assert_eq!(status, 503);
+        assert_eq!(health["status"], "unavailable");
+        assert_eq!(health["components"][0]["name"], "dynamodb:users");
+        assert_eq!(health["components"][0]["status"], "failing");
+        assert_eq!(health["components"][1]["status"], "ok");
+    }
+}
@@ -0,0 +1,92 @@
+use axum::{http::header, response::IntoResponse, routing::get, Router};
+
+use crate::{services::service_register::ServiceRegister, utils::metrics};
+
+pub fn router() -> Router<ServiceRegister> {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+/// Prometheus metrics
+/// Request rate, errors and latency per route, and the same for DynamoDB calls per operation
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain", body = String),
+    ),
+    tag = "metrics",
+)]
+async fn get_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, http::Request, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use crate::{
+        controllers::metrics,
+        services::service_register::ServiceRegister,
+        utils::metrics::{track_metrics, track_unmatched_metrics},
+    };
+
+    fn router() -> Router {
+        Router::new()
+            .route("/widgets/:id", get(|| async { "widget" }))
+            .merge(metrics::router())
+            .route_layer(middleware::from_fn(track_metrics))
+            .layer(middleware::from_fn(track_unmatched_metrics))
+            .with_state(ServiceRegister {
+                user_service: None,
+                token_service: None,
+                auth_service: None,
+                api_key_service: None,
+                oidc_service: None,
+                health_service: None,
+            })
+    }
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    async fn render(router: Router) -> String {
+        let response = router.oneshot(request("/metrics")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_are_labeled_by_route_template() {
+        // Arrange
+        let router = router();
+
+        // Act
+        router.clone().oneshot(request("/widgets/42")).await.unwrap();
+        let body = render(router).await;
+
+        // Assert
+        assert!(body.contains(r#"http_requests_total{method="GET",route="/widgets/:id",status="200"} 1"#));
+        assert!(!body.contains("/widgets/42"));
+    }
+
+    #[tokio::test]
+    async fn requests_matching_no_route_are_labeled_unmatched() {
+        // Arrange
+        let router = router();
+
+        // Act
+        router.clone().oneshot(request("/gadgets/42")).await.unwrap();
+        let body = render(router).await;
+
+        // Assert
+        assert!(body.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+        assert!(!body.contains(r#"route="unmatched",status="200""#));
+    }
+}
@@ -1,2 +1,6 @@
+pub mod api_key_controller;
+pub mod auth_controller;
 pub mod health;
+pub mod metrics;
+pub mod oidc_controller;
 pub mod user_controller;
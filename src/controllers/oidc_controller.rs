@@ -0,0 +1,367 @@
+// Endpoints to log in through an external OpenID Connect provider
+// Only mounted when OIDC_ISSUER is set, see server.rs
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderName},
+    response::{IntoResponse, Redirect},
+    routing::get,
+    Json, Router,
+};
+use tracing::log::debug;
+
+use crate::{
+    domain::auth::view_models::{OidcCallbackQuery, TokenViewModel},
+    errors::{AppError, AppResult},
+    services::{
+        oidc_service::{OidcService, OIDC_STATE_COOKIE},
+        service_register::ServiceRegister,
+    },
+};
+
+pub fn router() -> Router<ServiceRegister> {
+    Router::new()
+        .route("/auth/oidc/login", get(oidc_login))
+        .route("/auth/oidc/callback", get(oidc_callback))
+}
+
+/// Log in with the identity provider
+/// Redirects to the provider, which redirects back to /auth/oidc/callback once the user has logged in
+#[utoipa::path(
+    get,
+    path = "/auth/oidc/login",
+    responses(
+        (status = 303, description = "Redirect to the identity provider",
+            headers(
+                ("Location" = String, description = "Authorization url of the identity provider"),
+                ("Set-Cookie" = String, description = "State cookie checked by the callback"),
+            )
+        ),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    tag = "auth",
+)]
+pub async fn oidc_login(State(oidc_service): State<OidcService>) -> AppResult<impl IntoResponse> {
+    let (url, state_cookie) = oidc_service.authorization_url().await?;
+
+    // Lax so that the cookie is sent along with the top level redirect back from the provider
+    let cookie = format!(
+        "{}={}; HttpOnly; Secure; SameSite=Lax; Path=/auth/oidc; Max-Age=600",
+        OIDC_STATE_COOKIE, state_cookie
+    );
+
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&url)))
+}
+
+/// Complete the identity provider login
+/// Links the user with the verified email returned by the provider, or registers a new one,
+/// and returns a token pair like POST /auth/login
+#[utoipa::path(
+    get,
+    path = "/auth/oidc/callback",
+    params(OidcCallbackQuery),
+    responses(
+        (status = 200, description = "Successfully logged in", body = TokenViewModel),
+        (status = 400, description = "The provider returned an error or no verified email"),
+        (status = 401, description = "Missing or mismatched state, or the code or ID token was rejected"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    tag = "auth",
+)]
+pub async fn oidc_callback(
+    State(oidc_service): State<OidcService>,
+    Query(query): Query<OidcCallbackQuery>,
+    headers: HeaderMap,
+) -> AppResult<([(HeaderName, String); 1], Json<TokenViewModel>)> {
+    if let Some(error) = query.error {
+        debug!("Identity provider returned an error: {}", error);
+        return Err(AppError::BadRequest(format!(
+            "The identity provider returned {}",
+            error
+        )));
+    }
+    let (code, state) = query
+        .code
+        .zip(query.state)
+        .ok_or_else(|| AppError::BadRequest("code and state are required".to_string()))?;
+
+    let tokens = oidc_service
+        .complete_login(code, state, state_cookie(&headers))
+        .await?;
+
+    // The state is single use, clear it now that the login is complete
+    let cleared = format!(
+        "{}=; HttpOnly; Secure; SameSite=Lax; Path=/auth/oidc; Max-Age=0",
+        OIDC_STATE_COOKIE
+    );
+
+    Ok(([(header::SET_COOKIE, cleared)], Json(tokens)))
+}
+
+fn state_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == OIDC_STATE_COOKIE)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Body,
+        extract::State,
+        http::{header, Request},
+        response::Redirect,
+        routing::{get, post},
+        Form, Json, Router,
+    };
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use reqwest::Url;
+    use sha2::{Digest, Sha256};
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    use crate::{
+        controllers::oidc_controller,
+        domain::user::models::User,
+        repositories::{
+            in_memory_refresh_token_repository::InMemoryRefreshTokenRepository,
+            in_memory_user_repository::InMemoryUserRepository, user_store::UserStore,
+        },
+        services::{
+            auth_service::AuthService, oidc_service::OidcService,
+            service_register::ServiceRegister, token_service::TokenService,
+            user_service::UserService,
+        },
+        utils::dynamodb_helpers::PageCursorCodec,
+    };
+
+    const CLIENT_ID: &str = "scaffold";
+    const CLIENT_SECRET: &str = "client-secret";
+    const REDIRECT_URI: &str = "http://localhost:5000/auth/oidc/callback";
+
+    /// Code challenge and nonce of each authorization code the stub has handed out
+    #[derive(Clone, Default)]
+    struct StubProvider {
+        issuer: String,
+        codes: Arc<Mutex<HashMap<String, (String, String)>>>,
+    }
+
+    // A local provider serving discovery, authorization and token endpoints so the flow runs without network
+    // The authorization endpoint logs the user in straight away
+    async fn start_stub_provider() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let stub = StubProvider {
+            issuer: issuer.clone(),
+            ..Default::default()
+        };
+
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .with_state(stub);
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        issuer
+    }
+
+    async fn discovery(State(stub): State<StubProvider>) -> Json<serde_json::Value> {
+        Json(serde_json::json!({
+            "issuer": stub.issuer,
+            "authorization_endpoint": format!("{}/authorize", stub.issuer),
+            "token_endpoint": format!("{}/token", stub.issuer),
+        }))
+    }
+
+    async fn authorize(
+        State(stub): State<StubProvider>,
+        axum::extract::Query(params): axum::extract::Query<HashMap<String, String>>,
+    ) -> Redirect {
+        let code = uuid::Uuid::new_v4().to_string();
+        stub.codes.lock().unwrap().insert(
+            code.clone(),
+            (params["code_challenge"].clone(), params["nonce"].clone()),
+        );
+
+        Redirect::to(&format!(
+            "{}?code={}&state={}",
+            params["redirect_uri"], code, params["state"]
+        ))
+    }
+
+    async fn token(
+        State(stub): State<StubProvider>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<serde_json::Value>, axum::http::StatusCode> {
+        let (code_challenge, nonce) = stub
+            .codes
+            .lock()
+            .unwrap()
+            .remove(&form["code"])
+            .ok_or(axum::http::StatusCode::BAD_REQUEST)?;
+        let verified = URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+        if verified != code_challenge || form["client_secret"] != CLIENT_SECRET {
+            return Err(axum::http::StatusCode::BAD_REQUEST);
+        }
+
+        let claims = serde_json::json!({
+            "iss": stub.issuer,
+            "aud": CLIENT_ID,
+            "sub": "provider-user-1",
+            "exp": get_current_timestamp() + 60,
+            "email": "pp@gmail.com",
+            "email_verified": true,
+            "nonce": nonce,
+        });
+        let id_token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(CLIENT_SECRET.as_bytes()),
+        )
+        .unwrap();
+
+        Ok(Json(serde_json::json!({ "id_token": id_token, "token_type": "Bearer" })))
+    }
+
+    async fn get_service_register(issuer: String) -> ServiceRegister {
+        let user_repository = InMemoryUserRepository::new();
+        user_repository
+            .put_user(&User::new(
+                "ppId123".to_string(),
+                "pp@gmail.com".to_string(),
+                "pplogin".to_string(),
+                "I love to eat".to_string(),
+                None,
+            ))
+            .await
+            .unwrap();
+        let token_service = TokenService::new(Some(b"secret"), None);
+        let user_service = UserService::new(user_repository.clone(), PageCursorCodec::new("secret"));
+        let auth_service = AuthService::new(
+            user_repository,
+            InMemoryRefreshTokenRepository::new(),
+            token_service.clone(),
+        );
+
+        ServiceRegister {
+            oidc_service: Some(OidcService::new(
+                issuer,
+                CLIENT_ID.to_string(),
+                Some(CLIENT_SECRET.to_string()),
+                REDIRECT_URI.to_string(),
+                user_service.clone(),
+                auth_service.clone(),
+            )),
+            user_service: Some(user_service),
+            token_service: Some(token_service),
+            auth_service: Some(auth_service),
+            api_key_service: None,
+            health_service: None,
+        }
+    }
+
+    /// Starts the login and follows the redirect to the stub provider
+    /// Returns the state cookie and the callback url the provider redirected back to
+    async fn login_at_provider(router: &Router) -> (String, Url) {
+        let response = router
+            .clone()
+            .oneshot(Request::builder().uri("/auth/oidc/login").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 303);
+        let cookie = response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let authorization_url = response.headers()[header::LOCATION].to_str().unwrap();
+
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let provider_response = client.get(authorization_url).send().await.unwrap();
+        let callback_url = provider_response.headers()[header::LOCATION.as_str()]
+            .to_str()
+            .unwrap();
+
+        (cookie, Url::parse(callback_url).unwrap())
+    }
+
+    // Test success path, the provider email matches an existing user
+    #[tokio::test]
+    async fn oidc_login_links_user_by_verified_email() {
+        // Arrange
+        let service_register = get_service_register(start_stub_provider().await).await;
+        let token_service = service_register.token_service.clone().unwrap();
+        let router = oidc_controller::router().with_state(service_register);
+        let (cookie, callback_url) = login_at_provider(&router).await;
+
+        // Act
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/auth/oidc/callback?{}", callback_url.query().unwrap()))
+                    .header(header::COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let tokens: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        // Assert
+        assert_eq!(status, 200);
+        let claims = token_service
+            .verify(tokens["access_token"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(claims.sub, "ppId123");
+    }
+
+    // Test failure path, the state does not match the cookie
+    #[tokio::test]
+    async fn oidc_callback_rejects_mismatched_state() {
+        // Arrange
+        let router = oidc_controller::router()
+            .with_state(get_service_register(start_stub_provider().await).await);
+        let (cookie, callback_url) = login_at_provider(&router).await;
+        let code = callback_url
+            .query_pairs()
+            .find(|(name, _)| name == "code")
+            .unwrap()
+            .1;
+
+        // Act
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/auth/oidc/callback?code={}&state=forged", code))
+                    .header(header::COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -1,21 +1,46 @@
 // Controller layer should simply act as the gateway
-// Input level validations should be done in the controller layer or by a middleware/extractor
-// e.g of input level validations are: Checking if a username should not be more than 20 characters and it should be unique
+// Input level validations are declared on the view models and run by the Validated extractors
+// e.g of input level validations are: Checking if a username should not be more than 32 characters
+// Checks needing the database, e.g that it is unique, belong to the service layer
 
 use axum::{
-    extract::{Path, State},
-    routing::get,
+    extract::{Path, Query, Request, State},
+    handler::Handler,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
     Json, Router,
 };
 
 use crate::{
-    domain::user::view_models::UserViewModel,
-    errors::AppResult,
+    domain::common::view_models::PageQuery,
+    domain::user::view_models::{
+        BatchGetUsersResultViewModel, BatchGetUsersViewModel, CreateUserViewModel, DeleteUserQuery,
+        UpdateUserViewModel, UserEmailPath, UserPage, UserUsernamePath, UserViewModel,
+    },
+    errors::{AppError, AppResult},
+    domain::auth::models::Permission,
+    extractors::{
+        authorized::{Authorized, UsersAdmin, UsersRead},
+        validated::{ValidatedJson, ValidatedPath, ValidatedQuery},
+    },
     services::{service_register::ServiceRegister, user_service::UserService},
 };
 
 pub fn router() -> Router<ServiceRegister> {
-    Router::new().route("/user/:id", get(get_current_user))
+    Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route("/users:method", post(users_custom_method))
+        .route("/user", get(get_current_user))
+        .route("/users/by-email/:email", get(get_user_by_email))
+        .route("/users/by-username/:username", get(get_user_by_username))
+        .route(
+            "/user/:id",
+            get(get_user)
+                .patch(update_user)
+                .delete(delete_user),
+        )
+        .route("/user/:id/restore", post(restore_user))
 }
 
 // Utoipa provides a macro to generate the openapi documentation for the handler
@@ -24,23 +49,338 @@ pub fn router() -> Router<ServiceRegister> {
 // Using rust's /// comments, we can add path level description into the openapi documentation
 
 /// Get current user
-/// This endpoint will return the current user
+/// This endpoint will return the user the bearer token was issued to
 #[utoipa::path(
     get,
-    path = "/user/:id",
+    path = "/user",
     responses(
-        (status = 200, description = "Successfully retrieved user", body = [UserViewModel]),
+        (status = 200, description = "Successfully retrieved user", body = UserViewModel,
+            headers(("ETag" = String, description = "Current version of the user"))),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Missing the users:read permission"),
         (status = 500, description = "Internal Server Error"),
     ),
+    security(("bearer_auth" = ["users:read"]), ("api_key" = ["users:read"])),
     tag = "user",
 )]
 pub async fn get_current_user(
+    auth_user: Authorized<UsersRead>,
+    State(user_service): State<UserService>,
+) -> AppResult<([(header::HeaderName, String); 1], Json<UserViewModel>)> {
+    let current_user = user_service.get_current_user(auth_user.id.clone()).await?;
+
+    Ok(([(header::ETAG, etag(current_user.version))], Json(current_user)))
+}
+
+/// Get user
+/// Users can read themselves, reading anyone else requires the users:admin permission
+#[utoipa::path(
+    get,
+    path = "/user/:id",
+    responses(
+        (status = 200, description = "Successfully retrieved user", body = UserViewModel,
+            headers(("ETag" = String, description = "Current version of the user"))),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Reading another user requires the users:admin permission"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:read"]), ("api_key" = ["users:read"])),
+    tag = "user",
+)]
+pub async fn get_user(
+    auth_user: Authorized<UsersRead>,
     Path(id): Path<String>,
     State(user_service): State<UserService>,
+) -> AppResult<([(header::HeaderName, String); 1], Json<UserViewModel>)> {
+    auth_user.require_self_or_admin(&id)?;
+
+    let user = user_service.get_current_user(id).await?;
+
+    Ok(([(header::ETAG, etag(user.version))], Json(user)))
+}
+
+/// Get user by email
+#[utoipa::path(
+    get,
+    path = "/users/by-email/:email",
+    params(UserEmailPath),
+    responses(
+        (status = 200, description = "Successfully retrieved user", body = UserViewModel),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Missing the users:admin permission"),
+        (status = 404, description = "User not found"),
+        (status = 422, description = "Malformed email"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:admin"]), ("api_key" = ["users:admin"])),
+    tag = "user",
+)]
+pub async fn get_user_by_email(
+    _auth_user: Authorized<UsersAdmin>,
+    ValidatedPath(path): ValidatedPath<UserEmailPath>,
+    State(user_service): State<UserService>,
+) -> AppResult<Json<UserViewModel>> {
+    let user = user_service.get_user_by_email(path.email).await?;
+
+    Ok(Json(user))
+}
+
+/// Get user by username
+#[utoipa::path(
+    get,
+    path = "/users/by-username/:username",
+    params(UserUsernamePath),
+    responses(
+        (status = 200, description = "Successfully retrieved user", body = UserViewModel),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Missing the users:admin permission"),
+        (status = 404, description = "User not found"),
+        (status = 422, description = "Malformed username"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:admin"]), ("api_key" = ["users:admin"])),
+    tag = "user",
+)]
+pub async fn get_user_by_username(
+    _auth_user: Authorized<UsersAdmin>,
+    ValidatedPath(path): ValidatedPath<UserUsernamePath>,
+    State(user_service): State<UserService>,
 ) -> AppResult<Json<UserViewModel>> {
-    let current_user = user_service.get_current_user(id).await?;
+    let user = user_service.get_user_by_username(path.username).await?;
+
+    Ok(Json(user))
+}
+
+/// List users
+/// Returns users a page at a time, pass `next_cursor` from the response as `cursor` to get the next page
+#[utoipa::path(
+    get,
+    path = "/users",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "Successfully listed users", body = UserPage),
+        (status = 400, description = "Invalid cursor"),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Missing the users:admin permission"),
+        (status = 422, description = "Limit out of range"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:admin"]), ("api_key" = ["users:admin"])),
+    tag = "user",
+)]
+pub async fn list_users(
+    _auth_user: Authorized<UsersAdmin>,
+    ValidatedQuery(query): ValidatedQuery<PageQuery>,
+    State(user_service): State<UserService>,
+) -> AppResult<Json<UserPage>> {
+    let users = user_service.list_users(query).await?;
+
+    Ok(Json(users))
+}
+
+/// Custom methods of the users collection such as `POST /users:batchGet`
+/// The router reads a colon as the start of a path parameter, so these share the `/users:method` route
+/// and are dispatched here on the method, which the parameter holds colon included
+async fn users_custom_method(
+    State(state): State<ServiceRegister>,
+    Path(method): Path<String>,
+    request: Request,
+) -> Response {
+    match method.as_str() {
+        ":batchGet" => batch_get_users.call(request, state).await,
+        _ => AppError::NotFound("Not found".to_string()).into_response(),
+    }
+}
+
+/// Get users by ids
+/// Looks up to 100 users at once, ids without a user are listed in `missing_ids`
+#[utoipa::path(
+    post,
+    path = "/users:batchGet",
+    request_body = BatchGetUsersViewModel,
+    responses(
+        (status = 200, description = "Successfully retrieved users", body = BatchGetUsersResultViewModel),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Missing the users:admin permission"),
+        (status = 422, description = "No ids or more than 100 ids"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:admin"]), ("api_key" = ["users:admin"])),
+    tag = "user",
+)]
+pub async fn batch_get_users(
+    _auth_user: Authorized<UsersAdmin>,
+    State(user_service): State<UserService>,
+    ValidatedJson(request): ValidatedJson<BatchGetUsersViewModel>,
+) -> AppResult<Json<BatchGetUsersResultViewModel>> {
+    let users = user_service.get_users_by_ids(request.ids).await?;
+
+    Ok(Json(users))
+}
+
+/// Register a new user
+/// Email and username must be unique, a 409 is returned if either of them is already taken
+#[utoipa::path(
+    post,
+    path = "/users",
+    request_body = CreateUserViewModel,
+    responses(
+        (status = 201, description = "Successfully created user", body = UserViewModel),
+        (status = 409, description = "Email or username is already taken"),
+        (status = 422, description = "Invalid fields, every failing field is listed in errors"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    tag = "user",
+)]
+pub async fn create_user(
+    State(user_service): State<UserService>,
+    ValidatedJson(request): ValidatedJson<CreateUserViewModel>,
+) -> AppResult<(StatusCode, Json<UserViewModel>)> {
+    let created_user = user_service.create_user(request).await?;
+
+    Ok((StatusCode::CREATED, Json(created_user)))
+}
+
+/// Update user
+/// Only the fields present in the body are updated
+/// Send the ETag from a previous response in the If-Match header to reject the update
+/// if someone else has modified the user in the meantime
+#[utoipa::path(
+    patch,
+    path = "/user/:id",
+    request_body = UpdateUserViewModel,
+    params(
+        ("If-Match" = Option<String>, Header, description = "ETag of the user version this update is based on"),
+    ),
+    responses(
+        (status = 200, description = "Successfully updated user", body = UserViewModel,
+            headers(("ETag" = String, description = "New version of the user"))),
+        (status = 400, description = "Malformed If-Match header"),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Updating another user or changing a role requires the users:admin permission"),
+        (status = 404, description = "User not found"),
+        (status = 409, description = "Email or username is already taken"),
+        (status = 412, description = "User was modified since the version in If-Match, or If-Match is a weak ETag"),
+        (status = 422, description = "Invalid fields, every failing field is listed in errors"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:read"]), ("api_key" = ["users:read"])),
+    tag = "user",
+)]
+pub async fn update_user(
+    auth_user: Authorized<UsersRead>,
+    Path(id): Path<String>,
+    State(user_service): State<UserService>,
+    headers: HeaderMap,
+    ValidatedJson(request): ValidatedJson<UpdateUserViewModel>,
+) -> AppResult<([(header::HeaderName, String); 1], Json<UserViewModel>)> {
+    auth_user.require_self_or_admin(&id)?;
+    if request.role.is_some() {
+        auth_user.require(Permission::UsersAdmin)?;
+    }
+
+    let expected_version = parse_if_match(&headers)?;
+    let updated_user = user_service
+        .update_user(id, request, expected_version)
+        .await?;
 
-    Ok(Json(current_user))
+    Ok(([(header::ETAG, etag(updated_user.version))], Json(updated_user)))
+}
+
+/// Delete user
+/// Users are soft deleted by default and can be brought back with the restore endpoint
+/// Pass `hard=true` to permanently remove the user, which requires the users:admin permission
+#[utoipa::path(
+    delete,
+    path = "/user/:id",
+    params(DeleteUserQuery),
+    responses(
+        (status = 204, description = "Successfully deleted user"),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Deleting another user or hard deleting requires the users:admin permission"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:read"]), ("api_key" = ["users:read"])),
+    tag = "user",
+)]
+pub async fn delete_user(
+    auth_user: Authorized<UsersRead>,
+    Path(id): Path<String>,
+    Query(query): Query<DeleteUserQuery>,
+    State(user_service): State<UserService>,
+) -> AppResult<StatusCode> {
+    let hard = query.hard.unwrap_or(false);
+    auth_user.require_self_or_admin(&id)?;
+    if hard {
+        auth_user.require(Permission::UsersAdmin)?;
+    }
+
+    user_service.delete_user(id, hard).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Restore user
+/// Undoes a soft delete
+#[utoipa::path(
+    post,
+    path = "/user/:id/restore",
+    responses(
+        (status = 200, description = "Successfully restored user", body = UserViewModel,
+            headers(("ETag" = String, description = "New version of the user"))),
+        (status = 401, description = "Missing or invalid bearer token or api key"),
+        (status = 403, description = "Restoring another user requires the users:admin permission"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal Server Error"),
+    ),
+    security(("bearer_auth" = ["users:read"]), ("api_key" = ["users:read"])),
+    tag = "user",
+)]
+pub async fn restore_user(
+    auth_user: Authorized<UsersRead>,
+    Path(id): Path<String>,
+    State(user_service): State<UserService>,
+) -> AppResult<([(header::HeaderName, String); 1], Json<UserViewModel>)> {
+    auth_user.require_self_or_admin(&id)?;
+
+    let restored_user = user_service.restore_user(id).await?;
+
+    Ok(([(header::ETAG, etag(restored_user.version))], Json(restored_user)))
+}
+
+fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Reads the version out of an If-Match header such as `"3"`
+/// `*` or a missing header means the caller does not care about the current version
+/// If-Match uses the strong comparison, a weak ETag such as `W/"3"` never matches (RFC 9110)
+fn parse_if_match(headers: &HeaderMap) -> AppResult<Option<u64>> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+
+    let value = value
+        .to_str()
+        .map_err(|_| AppError::BadRequest("Invalid If-Match header".to_string()))?
+        .trim();
+
+    if value == "*" {
+        return Ok(None);
+    }
+    if value.starts_with("W/") {
+        return Err(AppError::PreconditionFailed(
+            "Weak ETags cannot be used in If-Match".to_string(),
+        ));
+    }
+
+    value
+        .trim_matches('"')
+        .parse::<u64>()
+        .map(Some)
+        .map_err(|_| AppError::BadRequest("Invalid If-Match header".to_string()))
 }
 
 // For the endpoint tests, since we're doing integration tests using the generated openapi documentation
@@ -49,48 +389,78 @@ pub async fn get_current_user(
 // For this example, we will do a code level controller endpoint integration test here
 #[cfg(test)]
 mod test {
-    use std::sync::Arc;
-
     use axum::{
         body::Body,
-        http::{Method, Request},
+        http::{header, HeaderMap, Method, Request},
     };
+    use jsonwebtoken::{encode, EncodingKey, Header};
     use tower::ServiceExt;
 
     use crate::{
-        config::AppConfig,
-        controllers::user_controller,
-        get_app_config,
-        repositories::user_repository::UserRepository,
+        controllers::user_controller::{self, parse_if_match},
+        domain::user::models::{Role, User},
+        errors::AppError,
+        repositories::{in_memory_user_repository::InMemoryUserRepository, user_store::UserStore},
         services::{
-            service_register::{get_aws_shared_config, ServiceRegister},
+            service_register::ServiceRegister,
+            token_service::{Claims, TokenService},
             user_service::UserService,
         },
+        utils::dynamodb_helpers::PageCursorCodec,
     };
 
-    // We could use a mock database for testing, however for this example we will use the actual database
-    // Feel free to use a mock database if you want
-    // Using a real database here would make this an integration test
-    async fn get_service_register(app_config: Arc<AppConfig>) -> ServiceRegister {
-        let shared_config = get_aws_shared_config(app_config).await;
-        let user_repository = UserRepository::new(&shared_config, None).await;
-        let user_service = UserService::new(user_repository);
+    const JWT_SECRET: &[u8] = b"secret";
+
+    // We use the in memory repository here so that the tests can run without a database
+    // Swap it for UserRepository if you would rather run these as integration tests against DynamoDB
+    async fn get_service_register() -> ServiceRegister {
+        let user_repository = InMemoryUserRepository::new();
+        user_repository
+            .put_user(&User::new(
+                "ppId123".to_string(),
+                "pp@gmail.com".to_string(),
+                "pplogin".to_string(),
+                "I love to eat".to_string(),
+                None,
+            ))
+            .await
+            .unwrap();
+        let user_service = UserService::new(user_repository, PageCursorCodec::new("secret"));
 
         ServiceRegister {
             user_service: Some(user_service),
+            token_service: Some(TokenService::new(Some(JWT_SECRET), None)),
+            auth_service: None,
+            api_key_service: None,
+            oidc_service: None,
+            health_service: None,
         }
     }
 
+    fn bearer_token(sub: &str, role: Role) -> String {
+        let claims = Claims {
+            sub: sub.to_string(),
+            exp: jsonwebtoken::get_current_timestamp() + 60,
+            nbf: None,
+            iss: None,
+            aud: None,
+            role,
+        };
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(JWT_SECRET)).unwrap();
+
+        format!("Bearer {}", token)
+    }
+
     // Test success path
     #[tokio::test]
     async fn get_current_user_success() {
         // Arrange
-        let app_config = get_app_config();
-        let service_register = get_service_register(app_config).await;
+        let service_register = get_service_register().await;
         let router = user_controller::router().with_state(service_register);
         let request = Request::builder()
-            .uri("/user/ppId123")
+            .uri("/user")
             .method(Method::GET)
+            .header(header::AUTHORIZATION, bearer_token("ppId123", Role::User))
             .body(Body::empty())
             .unwrap();
 
@@ -101,4 +471,143 @@ mod test {
         // Assert
         assert_eq!(status, 200);
     }
+
+    // Test failure path
+    #[tokio::test]
+    async fn get_current_user_requires_valid_token() {
+        let service_register = get_service_register().await;
+        let router = user_controller::router().with_state(service_register);
+        let expired = Claims {
+            sub: "ppId123".to_string(),
+            exp: jsonwebtoken::get_current_timestamp() - 3600,
+            nbf: None,
+            iss: None,
+            aud: None,
+            role: Role::User,
+        };
+        let expired = encode(&Header::default(), &expired, &EncodingKey::from_secret(JWT_SECRET)).unwrap();
+
+        for authorization in [None, Some("Bearer not-a-jwt".to_string()), Some(format!("Bearer {}", expired))] {
+            let mut request = Request::builder().uri("/user").method(Method::GET);
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+
+            let response = router
+                .clone()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), 401);
+        }
+    }
+
+    #[tokio::test]
+    async fn create_user_lists_every_invalid_field() {
+        // Arrange
+        let service_register = get_service_register().await;
+        let router = user_controller::router().with_state(service_register);
+        let request = Request::builder()
+            .uri("/users")
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"email": "not-an-email", "username": "pp", "password": "pp"}"#,
+            ))
+            .unwrap();
+
+        // Act
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let fields: Vec<&str> = error["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["field"].as_str().unwrap())
+            .collect();
+
+        // Assert
+        assert_eq!(status, 422);
+        assert_eq!(error["error_code"], "validation_failed");
+        assert_eq!(fields, vec!["email", "password", "username", "password"]);
+    }
+
+    #[tokio::test]
+    async fn reading_another_user_requires_admin() {
+        // Arrange
+        let service_register = get_service_register().await;
+        let router = user_controller::router().with_state(service_register);
+        let get_user = |role: Role| {
+            Request::builder()
+                .uri("/user/ppId123")
+                .method(Method::GET)
+                .header(header::AUTHORIZATION, bearer_token("someoneElse", role))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Act
+        let as_user = router.clone().oneshot(get_user(Role::User)).await.unwrap();
+        let as_admin = router.oneshot(get_user(Role::Admin)).await.unwrap();
+
+        // Assert
+        assert_eq!(as_user.status(), 403);
+        assert_eq!(as_admin.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn batch_get_users_reports_missing_ids() {
+        // Arrange
+        let service_register = get_service_register().await;
+        let router = user_controller::router().with_state(service_register);
+        let post = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .method(Method::POST)
+                .header(header::AUTHORIZATION, bearer_token("ppId123", Role::Admin))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"ids": ["missingId", "ppId123"]}"#))
+                .unwrap()
+        };
+
+        // Act
+        let response = router
+            .clone()
+            .oneshot(post("/users:batchGet"))
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let result: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let unknown_method = router.oneshot(post("/users:batchDelete")).await.unwrap();
+
+        // Assert
+        assert_eq!(status, 200);
+        assert_eq!(result["users"][0]["id"], "ppId123");
+        assert_eq!(result["users"].as_array().unwrap().len(), 1);
+        assert_eq!(result["missing_ids"], serde_json::json!(["missingId"]));
+        assert_eq!(unknown_method.status(), 404);
+    }
+
+    #[test]
+    fn parse_if_match_rejects_weak_etags() {
+        let if_match = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_MATCH, value.parse().unwrap());
+            parse_if_match(&headers)
+        };
+
+        assert_eq!(parse_if_match(&HeaderMap::new()).unwrap(), None);
+        assert_eq!(if_match("*").unwrap(), None);
+        assert_eq!(if_match("\"3\"").unwrap(), Some(3));
+        assert!(matches!(if_match("W/\"3\""), Err(AppError::PreconditionFailed(_))));
+        assert!(if_match("\"three\"").is_err());
+    }
 }
@@ -0,0 +1,2 @@
+pub mod models;
+pub mod view_models;
\ No newline at end of file
@@ -0,0 +1,38 @@
+// Models are the defined structures of the data that will be stored in the database.
+use serde::{Deserialize, Serialize};
+
+use crate::domain::auth::models::Permission;
+
+/// A long lived key for service to service callers, sent in the X-Api-Key header
+/// Keys look like `sk_<12 hex characters>.<secret>`, the part before the dot is the id of the key
+/// and is used to look it up, only the SHA-256 of the secret is stored
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    /// Free text to tell keys apart e.g the name of the job using it
+    pub name: String,
+    pub key_hash: String,
+    /// Permissions granted to callers using this key
+    pub scopes: Vec<Permission>,
+    /// Id of the admin who minted the key
+    pub created_by: String,
+    pub created_at: String,
+    /// Unix timestamp after which the key is rejected, keys without one never expire
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Unix timestamp of the last request made with the key, only written once a minute at most
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<String>,
+}
+
+impl ApiKey {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
@@ -0,0 +1,72 @@
+// View models is where we define the data that will be returned to the client
+// This is also where we can define the data that will be accepted from the client
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{domain::auth::models::Permission, utils::validation::Validate};
+
+use super::models::ApiKey;
+
+/// Api key response view model, the key itself is only returned once when it is minted
+#[derive(Debug, Serialize, Deserialize, ToSchema, PartialEq)]
+pub struct ApiKeyViewModel {
+    /// Id of the key, also the part of the key before the dot
+    #[schema(example = "sk_1a2b3c4d5e6f")]
+    pub id: String,
+    #[schema(example = "nightly-export")]
+    pub name: String,
+    /// Permissions granted to callers using this key
+    pub scopes: Vec<Permission>,
+    /// Id of the admin who minted the key
+    #[schema(example = "ppId123")]
+    pub created_by: String,
+    #[schema(example = "2023-10-25T08:00:00+00:00")]
+    pub created_at: String,
+    /// Unix timestamp after which the key is rejected, absent if the key never expires
+    #[schema(example = 1700000000)]
+    pub expires_at: Option<u64>,
+    /// Unix timestamp of the last request made with the key, accurate to a minute
+    #[schema(example = 1698220800)]
+    pub last_used_at: Option<u64>,
+    /// Set once the key has been revoked
+    pub revoked_at: Option<String>,
+}
+
+impl From<ApiKey> for ApiKeyViewModel {
+    fn from(api_key: ApiKey) -> Self {
+        ApiKeyViewModel {
+            id: api_key.id,
+            name: api_key.name,
+            scopes: api_key.scopes,
+            created_by: api_key.created_by,
+            created_at: api_key.created_at,
+            expires_at: api_key.expires_at,
+            last_used_at: api_key.last_used_at,
+            revoked_at: api_key.revoked_at,
+        }
+    }
+}
+
+/// Create api key request view model
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateApiKeyViewModel {
+    #[schema(example = "nightly-export", min_length = 1, max_length = 64)]
+    pub name: String,
+    /// Permissions granted to callers using this key, at least one is required
+    #[schema(min_items = 1)]
+    pub scopes: Vec<Permission>,
+    /// Seconds until the key expires, the key never expires if not specified
+    #[schema(example = 7776000)]
+    pub expires_in: Option<u64>,
+}
+
+/// Created api key response view model
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreatedApiKeyViewModel {
+    /// The key to send in the X-Api-Key header, it cannot be retrieved again
+    #[schema(example = "sk_1a2b3c4d5e6f.c2VjcmV0")]
+    pub key: String,
+    pub api_key: ApiKeyViewModel,
+}
+
+impl Validate for CreateApiKeyViewModel {}
@@ -0,0 +1,70 @@
+// Models are the defined structures of the data that will be stored in the database.
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Permissions required by the endpoints, granted through the role of the user
+/// Routes declare the one they need with the Authorized extractor, see extractors/authorized.rs
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    /// Read and update your own user
+    #[serde(rename = "users:read")]
+    UsersRead,
+    /// Read, update and delete any user
+    #[serde(rename = "users:admin")]
+    UsersAdmin,
+}
+
+impl Permission {
+    /// Whether holding this permission is enough for `required`, users:admin includes users:read
+    pub fn grants(&self, required: Permission) -> bool {
+        *self == required || (*self == Permission::UsersAdmin && required == Permission::UsersRead)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::UsersRead => "users:read",
+            Permission::UsersAdmin => "users:admin",
+        }
+    }
+}
+
+impl TryFrom<&str> for Permission {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "users:read" => Ok(Permission::UsersRead),
+            "users:admin" => Ok(Permission::UsersAdmin),
+            _ => Err(format!("Unknown permission {}", value)),
+        }
+    }
+}
+
+/// Every login starts a new family of refresh tokens
+/// Only the latest token of the family can be used, each refresh replaces it with a new one
+/// Presenting an older token means it has leaked, so the whole family is revoked
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "sql", derive(sqlx::FromRow))]
+pub struct RefreshTokenFamily {
+    pub id: String,
+    pub user_id: String,
+    /// SHA-256 of the secret part of the current refresh token, the token itself is never stored
+    pub token_hash: String,
+    /// Unix timestamp after which the family can no longer be refreshed
+    /// Also used as the TTL attribute of the DynamoDB table so expired families are cleaned up
+    #[cfg_attr(feature = "sql", sqlx(try_from = "i64"))]
+    pub expires_at: u64,
+    /// Set on logout or when reuse is detected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<String>,
+}
+
+impl RefreshTokenFamily {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at <= now
+    }
+}
@@ -0,0 +1,76 @@
+// View models that are shared across the different domains
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use utoipa::{
+    openapi::{schema::SchemaType, ArrayBuilder, ObjectBuilder, Ref, Schema},
+    IntoParams,
+};
+
+use crate::utils::validation::Validate;
+
+/// A page of items from a list endpoint
+/// Pass `next_cursor` back as the `cursor` query parameter to get the next page
+/// Generic schemas cannot be registered in the openapi documentation, each domain declares
+/// its own alias with page_schema instead e.g user::view_models::UserPage
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page<T> {
+    /// Items in this page
+    pub items: Vec<T>,
+    /// Opaque cursor to the next page, absent when there are no more items
+    pub next_cursor: Option<String>,
+}
+
+/// Schema of a Page whose items are the schema named `items` e.g UserViewModel
+pub fn page_schema(items: &str) -> Schema {
+    ObjectBuilder::new()
+        .description(Some(
+            "A page of items from a list endpoint\n\
+             Pass `next_cursor` back as the `cursor` query parameter to get the next page",
+        ))
+        .property(
+            "items",
+            ArrayBuilder::new()
+                .items(Ref::from_schema_name(items))
+                .description(Some("Items in this page")),
+        )
+        .required("items")
+        .property(
+            "next_cursor",
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .description(Some(
+                    "Opaque cursor to the next page, absent when there are no more items",
+                ))
+                .example(Some(json!("eyJpZCI6eyJTIjoicHBJZDEyMyJ9fQ.c2lnbmF0dXJl")))
+                .nullable(true),
+        )
+        .into()
+}
+
+/// Deserializes a nullable field of a partial update, so that an explicit null, Some(None),
+/// can be told apart from a missing field, None, which leaves the value unchanged
+/// Use with #[serde(default, deserialize_with = "double_option")]
+pub fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Pagination query parameters
+#[derive(Debug, Default, Serialize, Deserialize, IntoParams)]
+pub struct PageQuery {
+    /// Maximum number of items to return, defaults to 20
+    #[param(example = 20, minimum = 1, maximum = 100)]
+    pub limit: Option<i32>,
+    /// Cursor returned as `next_cursor` by the previous page
+    pub cursor: Option<String>,
+}
+
+impl Validate for PageQuery {}
+
+impl PageQuery {
+    pub const DEFAULT_LIMIT: i32 = 20;
+    pub const MAX_LIMIT: i32 = 100;
+}
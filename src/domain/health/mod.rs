@@ -0,0 +1 @@
+pub mod view_models;
\ No newline at end of file
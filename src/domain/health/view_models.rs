@@ -0,0 +1,47 @@
+// View models is where we define the data that will be returned to the client
+// This is also where we can define the data that will be accepted from the client
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Overall status of the instance
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Every check passed
+    Ok,
+    /// A non critical check failed, the instance keeps receiving traffic
+    Degraded,
+    /// A critical check failed or the instance is shutting down
+    Unavailable,
+}
+
+/// Status of a single dependency check
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentStatus {
+    Ok,
+    Failing,
+    TimedOut,
+}
+
+/// Readiness response view model
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq)]
+pub struct HealthViewModel {
+    pub status: HealthStatus,
+    /// Results of the dependency checks, empty while shutting down
+    pub components: Vec<ComponentHealthViewModel>,
+}
+
+/// Result of a dependency check, results are cached for a few seconds
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq)]
+pub struct ComponentHealthViewModel {
+    #[schema(example = "dynamodb:users")]
+    pub name: String,
+    pub status: ComponentStatus,
+    /// Whether a failure of this check makes the instance unavailable
+    #[schema(example = true)]
+    pub critical: bool,
+    /// How long the check took, in milliseconds
+    #[schema(example = 12)]
+    pub latency_ms: u64,
+}
@@ -1 +1,5 @@
-pub mod user;
\ No newline at end of file
+pub mod api_key;
+pub mod auth;
+pub mod common;
+pub mod health;
+pub mod user;
@@ -1,7 +1,51 @@
 // Models are the defined structures of the data that will be stored in the database.
 use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize)]
+use crate::domain::auth::models::Permission;
+
+/// What a user is allowed to do, see Role::permissions
+/// Stored as a lowercase string e.g "admin"
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::User => &[Permission::UsersRead],
+            Role::Admin => &[Permission::UsersRead, Permission::UsersAdmin],
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+// Lets the sql repository read the role column, see the sqlx(try_from) attribute below
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "user" => Ok(Role::User),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role {}", value)),
+        }
+    }
+}
+
+// FromRow lets the sql repository read users straight out of the users table
+#[derive(Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "sql", derive(sqlx::FromRow))]
 pub struct User {
     pub id: String,
     pub email: String,
@@ -9,5 +53,107 @@ pub struct User {
     pub bio: String,
     pub image: Option<String>,
     created_at: String,
-    updated_at: String
-}
\ No newline at end of file
+    updated_at: String,
+    /// Incremented on every write, used for optimistic concurrency
+    /// Items written before versioning was introduced are treated as version 0
+    #[serde(default)]
+    #[cfg_attr(feature = "sql", sqlx(try_from = "i64"))]
+    pub version: u64,
+    /// Set when the user has been soft deleted, soft deleted users are treated as not found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<String>,
+    /// Argon2id PHC string, see utils/password.rs
+    /// Users without one cannot log in with a password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+    /// Users written before roles were introduced are treated as Role::User
+    #[serde(default)]
+    #[cfg_attr(feature = "sql", sqlx(try_from = "String"))]
+    pub role: Role,
+}
+
+/// Emails are stored and looked up lowercased, so that every UserStore matches them case insensitively
+pub fn normalize_email(email: &str) -> String {
+    email.to_lowercase()
+}
+
+/// Length bounds of a username, the same as the schema of CreateUserViewModel
+pub const USERNAME_MIN_LENGTH: usize = 3;
+pub const USERNAME_MAX_LENGTH: usize = 32;
+
+/// Turns a name we did not pick, e.g the preferred_username of an OIDC provider, into a username
+/// that registration would accept: letters, digits, dots, dashes and underscores only, truncated to fit
+/// None when fewer than USERNAME_MIN_LENGTH characters are left
+pub fn sanitize_username(name: &str) -> Option<String> {
+    let username: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        .take(USERNAME_MAX_LENGTH)
+        .collect();
+
+    (username.len() >= USERNAME_MIN_LENGTH).then_some(username)
+}
+
+impl User {
+    /// Creates a brand new user, stamping both created_at and updated_at with the current time
+    /// The email is normalized, see normalize_email
+    pub fn new(id: String, email: String, username: String, bio: String, image: Option<String>) -> Self {
+        let now = chrono::Utc::now().to_rfc3339();
+
+        Self {
+            id,
+            email: normalize_email(&email),
+            username,
+            bio,
+            image,
+            created_at: now.clone(),
+            updated_at: now,
+            version: 0,
+            deleted_at: None,
+            password_hash: None,
+            role: Role::User,
+        }
+    }
+
+    /// Sets the hash produced by utils::password::hash_password, never a plain text password
+    pub fn with_password_hash(mut self, password_hash: String) -> Self {
+        self.password_hash = Some(password_hash);
+        self
+    }
+
+    pub fn created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    pub fn updated_at(&self) -> &str {
+        &self.updated_at
+    }
+
+    pub fn deleted_at(&self) -> Option<&str> {
+        self.deleted_at.as_deref()
+    }
+
+    pub fn password_hash(&self) -> Option<&str> {
+        self.password_hash.as_deref()
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Soft deletes the user, remember to call touch() afterwards
+    pub fn mark_deleted(&mut self) {
+        self.deleted_at = Some(chrono::Utc::now().to_rfc3339());
+    }
+
+    /// Undoes a soft delete, remember to call touch() afterwards
+    pub fn restore(&mut self) {
+        self.deleted_at = None;
+    }
+
+    /// Stamps updated_at with the current time and bumps the version
+    pub fn touch(&mut self) {
+        self.updated_at = chrono::Utc::now().to_rfc3339();
+        self.version += 1;
+    }
+}
@@ -1,9 +1,18 @@
 // View models is where we define the data that will be returned to the client
 // This is also where we can define the data that will be accepted from the client
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{
+    openapi::{RefOr, Schema},
+    IntoParams, ToSchema,
+};
 
-use super::models::User;
+use crate::{
+    domain::common::view_models::{double_option, page_schema, Page},
+    errors::FieldError,
+    utils::validation::Validate,
+};
+
+use super::models::{Role, User};
 
 // This is the view model that will be returned to the client
 // Utoipa's ToSchema is used to generate the openapi documentation
@@ -32,6 +41,12 @@ pub struct UserViewModel {
         example = "https://www.pexels.com/photo/selective-focus-photography-of-orange-tabby-cat-1170986"
     )]
     pub image: Option<String>,
+    /// Version of the user, also returned as the ETag header
+    /// Send it back in the If-Match header when updating to avoid overwriting someone else's changes
+    #[schema(example = 1)]
+    pub version: u64,
+    /// Role of the user, decides what the user is allowed to do
+    pub role: Role,
 }
 
 /// This is for quick conversion from the model to the view model which can be used in the handlers
@@ -44,6 +59,134 @@ impl From<User> for UserViewModel {
             username: user.username,
             bio: user.bio,
             image: user.image,
+            version: user.version,
+            role: user.role,
         }
     }
 }
+
+/// A page of users, see Page
+pub type UserPage = Page<UserViewModel>;
+
+impl<'s> ToSchema<'s> for UserPage {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        ("UserPage", page_schema("UserViewModel").into())
+    }
+}
+
+/// Create user request view model
+/// The schema attributes are also the validation rules, see utils/validation.rs
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateUserViewModel {
+    /// Email of the user, must not be registered by another user
+    #[schema(example = "pp@gmail.com", format = "email", max_length = 254)]
+    pub email: String,
+    /// Username of the user, must not be taken by another user
+    /// Letters, digits, dots, dashes and underscores only
+    #[schema(example = "pplogin", min_length = 3, max_length = 32, pattern = "^[A-Za-z0-9_.-]+$")]
+    pub username: String,
+    /// Bio of the user
+    #[serde(default)]
+    #[schema(example = "I love to eat", max_length = 500)]
+    pub bio: String,
+    /// Image of the user
+    #[schema(
+        example = "https://www.pexels.com/photo/selective-focus-photography-of-orange-tabby-cat-1170986",
+        format = "uri",
+        max_length = 2048
+    )]
+    pub image: Option<String>,
+    /// Password used to log in with POST /auth/login, must not be the email or the username
+    /// Users created without a password cannot log in with one
+    #[schema(example = "correct horse battery staple", write_only, min_length = 8, max_length = 128)]
+    pub password: Option<String>,
+}
+
+impl Validate for CreateUserViewModel {
+    fn validate(&self) -> Vec<FieldError> {
+        match &self.password {
+            Some(password) if password == &self.email || password == &self.username => {
+                vec![FieldError::new("password", "must not be the same as the email or the username")]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Update user request view model
+/// Only the fields that are present will be updated
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct UpdateUserViewModel {
+    /// New email of the user, must not be registered by another user
+    #[schema(example = "pp@gmail.com", format = "email", max_length = 254)]
+    pub email: Option<String>,
+    /// New username of the user, must not be taken by another user
+    #[schema(example = "pplogin", min_length = 3, max_length = 32, pattern = "^[A-Za-z0-9_.-]+$")]
+    pub username: Option<String>,
+    /// New bio of the user
+    #[schema(example = "I love to eat", max_length = 500)]
+    pub bio: Option<String>,
+    /// New image of the user, null removes the image
+    #[schema(
+        example = "https://www.pexels.com/photo/selective-focus-photography-of-orange-tabby-cat-1170986",
+        format = "uri",
+        max_length = 2048,
+        nullable
+    )]
+    #[serde(default, deserialize_with = "double_option", skip_serializing_if = "Option::is_none")]
+    pub image: Option<Option<String>>,
+    /// New role of the user, only admins can change roles
+    pub role: Option<Role>,
+}
+
+impl Validate for UpdateUserViewModel {}
+
+/// Get user by email path parameters
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct UserEmailPath {
+    /// Email of the user
+    #[param(example = "pp@gmail.com", format = "email", max_length = 254)]
+    pub email: String,
+}
+
+impl Validate for UserEmailPath {}
+
+/// Get user by username path parameters
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct UserUsernamePath {
+    /// Username of the user
+    #[param(example = "pplogin", min_length = 3, max_length = 32, pattern = "^[A-Za-z0-9_.-]+$")]
+    pub username: String,
+}
+
+impl Validate for UserUsernamePath {}
+
+/// Batch get users request view model
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchGetUsersViewModel {
+    /// Ids of the users to get, ids asked for more than once are only returned once
+    #[schema(example = json!(["ppId123", "ppId456"]), min_items = 1, max_items = 100)]
+    pub ids: Vec<String>,
+}
+
+impl Validate for BatchGetUsersViewModel {}
+
+/// Batch get users response view model
+#[derive(Debug, Serialize, Deserialize, ToSchema, PartialEq)]
+pub struct BatchGetUsersResultViewModel {
+    /// Users that were found, in the order of the requested ids
+    pub users: Vec<UserViewModel>,
+    /// Requested ids without a user, soft deleted users are reported as missing
+    #[schema(example = json!(["ppId456"]))]
+    pub missing_ids: Vec<String>,
+}
+
+/// Delete user query parameters
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct DeleteUserQuery {
+    /// Permanently remove the user instead of soft deleting it, a hard deleted user cannot be restored
+    #[param(example = false)]
+    pub hard: Option<bool>,
+}
@@ -1,16 +1,28 @@
 // This is where we define our custom app errors and map them to http status codes
 // You can rename AppResult into your own naming such as MyCoolApiResult
+// Errors are returned as ApiError by default, clients that send Accept: application/problem+json
+// get an RFC 9457 problem details document instead, see negotiate_error_format
 
 use axum::{
-    http::StatusCode,
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use crate::{
+    repositories::repository_error::RepositoryError,
+    utils::{dynamodb_helpers::AttributeValueError, request_id},
+};
+
 pub type AppResult<T> = Result<T, AppError>;
 
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
 /// Having a custom error type will allow us to handle errors in a more structured way
 /// This error enum will be used heavily by the controller and service layer to return errors
 /// Error annotations using thiserror will allow us write custom error messages
@@ -24,12 +36,16 @@ pub enum AppError {
     Forbidden,
     #[error("{0}")]
     BadRequest(String),
+    /// The request is well formed but some of its fields are invalid
     #[error("Unprocessable entity request")]
-    UnprocessableEntity,
+    UnprocessableEntity(Vec<FieldError>),
     #[error("{0}")]
     NotFound(String),
     #[error("{0}")]
     ObjectConflict(String),
+    /// The If-Match header of the request does not match the current version of the resource
+    #[error("{0}")]
+    PreconditionFailed(String),
     #[error("Unexpected error occurred")]
     InternalServerError,
     #[error("{0}")]
@@ -38,25 +54,130 @@ pub enum AppError {
     SerdeDynamoError(#[from] serde_dynamo::Error),
     #[error(transparent)]
     AnyhowError(#[from] anyhow::Error),
+    #[error(transparent)]
+    RepositoryError(#[from] RepositoryError),
+    #[error(transparent)]
+    AttributeValueError(#[from] AttributeValueError),
+}
+
+/// Stable machine readable error codes, clients should match on these rather than on messages
+/// New codes may be added but existing ones are never renamed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Unauthorized,
+    Forbidden,
+    BadRequest,
+    ValidationFailed,
+    NotFound,
+    Conflict,
+    PreconditionFailed,
+    InternalError,
+    ServiceUnavailable,
+    GatewayTimeout,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 10] = [
+        ErrorCode::Unauthorized,
+        ErrorCode::Forbidden,
+        ErrorCode::BadRequest,
+        ErrorCode::ValidationFailed,
+        ErrorCode::NotFound,
+        ErrorCode::Conflict,
+        ErrorCode::PreconditionFailed,
+        ErrorCode::InternalError,
+        ErrorCode::ServiceUnavailable,
+        ErrorCode::GatewayTimeout,
+    ];
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::ValidationFailed => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::GatewayTimeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::ValidationFailed => "validation_failed",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::PreconditionFailed => "precondition_failed",
+            ErrorCode::InternalError => "internal_error",
+            ErrorCode::ServiceUnavailable => "service_unavailable",
+            ErrorCode::GatewayTimeout => "gateway_timeout",
+        }
+    }
+
+    /// The problem type uri, a urn since we do not host documentation pages for our errors
+    pub fn problem_type(&self) -> String {
+        format!("urn:problem-type:{}", self.as_str())
+    }
+}
+
+/// Why a single field of the request was rejected
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct FieldError {
+    /// Name of the field as it appears in the request e.g email
+    #[schema(example = "email")]
+    pub field: String,
+    #[schema(example = "must be a valid email address")]
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
 }
 
 /// This implementation will allow us to convert our AppError into an Axum response
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status_code, error_message, status_text) = match self {
-            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, AppError::Unauthorized.to_string(), "Unauthorized"),
-            AppError::Forbidden => (StatusCode::FORBIDDEN, AppError::Forbidden.to_string(), "Forbidden"),
-            AppError::BadRequest(err) => (StatusCode::BAD_REQUEST, err, "Bad Request"),
-            AppError::UnprocessableEntity => (StatusCode::UNPROCESSABLE_ENTITY, AppError::UnprocessableEntity.to_string(), "Unprocessable Entity"),
-            AppError::NotFound(err) => (StatusCode::NOT_FOUND, err, "Not Found"),
-            AppError::ObjectConflict(err) => (StatusCode::CONFLICT, err, "Conflict"),
-            AppError::InternalServerErrorWithMessage(err) => (StatusCode::INTERNAL_SERVER_ERROR, err, "Internal Server Error"),
-            _ => (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error".to_string(), "Internal Server Error"),
+        let retry_after = match &self {
+            AppError::RepositoryError(err) => err.retry_after(),
+            _ => None,
         };
 
-        let body = Json(ApiError::new(status_code.as_u16(), &error_message, status_text));
+        let (error_code, error_message, errors) = match self {
+            AppError::Unauthorized => (ErrorCode::Unauthorized, AppError::Unauthorized.to_string(), vec![]),
+            AppError::Forbidden => (ErrorCode::Forbidden, AppError::Forbidden.to_string(), vec![]),
+            AppError::BadRequest(err) => (ErrorCode::BadRequest, err, vec![]),
+            AppError::UnprocessableEntity(errors) => (ErrorCode::ValidationFailed, "Unprocessable entity request".to_string(), errors),
+            AppError::NotFound(err) => (ErrorCode::NotFound, err, vec![]),
+            AppError::ObjectConflict(err) => (ErrorCode::Conflict, err, vec![]),
+            AppError::PreconditionFailed(err) => (ErrorCode::PreconditionFailed, err, vec![]),
+            AppError::InternalServerErrorWithMessage(err) => (ErrorCode::InternalError, err, vec![]),
+            AppError::RepositoryError(err @ RepositoryError::Throttled) => (ErrorCode::ServiceUnavailable, err.to_string(), vec![]),
+            AppError::RepositoryError(err @ RepositoryError::ConditionalCheckFailed) => (ErrorCode::Conflict, err.to_string(), vec![]),
+            AppError::RepositoryError(err @ RepositoryError::Timeout) => (ErrorCode::GatewayTimeout, err.to_string(), vec![]),
+            _ => (ErrorCode::InternalError, "Internal Server Error".to_string(), vec![]),
+        };
+
+        let api_error = ApiError::new(error_code, &error_message).with_errors(errors);
+        let mut response = (error_code.status_code(), Json(api_error.clone())).into_response();
+        if let Some(retry_after) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+        }
+        // Kept around so that negotiate_error_format can render it as problem+json instead
+        response.extensions_mut().insert(api_error);
 
-        (status_code, body).into_response()
+        response
     }
 }
 
@@ -65,23 +186,222 @@ impl IntoResponse for AppError {
 /// Feel free to design your own API Error response
 /// For this example I am referencing Google's JSON API error response
 /// https://cloud.google.com/apis/design/errors
-#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct ApiError {
     #[schema(example = "500")]
     pub code: u16,
     #[schema(example = "Internal Server Error")]
     pub message: String,
-    #[schema(example = "500")]
+    #[schema(example = "Internal Server Error")]
     pub status: String,
+    #[schema(example = "internal_error")]
+    pub error_code: ErrorCode,
+    /// The fields that were rejected, only set when error_code is validation_failed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    /// Id of the request, also sent in the X-Request-Id response header
+    #[schema(example = "6f1c2a4e-3b9d-4f7a-9c1e-2d5b8a7e4f10")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 impl ApiError {
-    pub fn new(code: u16, message: &str, status: &str) -> Self {
+    pub fn new(error_code: ErrorCode, message: &str) -> Self {
+        let status_code = error_code.status_code();
+
         Self {
-            code,
+            code: status_code.as_u16(),
             message: message.to_string(),
-            status: status.to_string(),
+            status: status_code.canonical_reason().unwrap_or_default().to_string(),
+            error_code,
+            errors: vec![],
+            request_id: request_id::current(),
+        }
+    }
+
+    pub fn with_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = errors;
+        self
+    }
+}
+
+/// RFC 9457 problem details, returned instead of ApiError when the client accepts application/problem+json
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ProblemDetails {
+    /// Identifies the kind of problem, one per error code
+    #[serde(rename = "type")]
+    #[schema(example = "urn:problem-type:not_found")]
+    pub problem_type: String,
+    #[schema(example = "Not Found")]
+    pub title: String,
+    #[schema(example = "404")]
+    pub status: u16,
+    #[schema(example = "User not found")]
+    pub detail: String,
+    /// Path of the request that failed
+    #[schema(example = "/user/6f1c2a4e-3b9d-4f7a-9c1e-2d5b8a7e4f10")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[schema(example = "not_found")]
+    pub code: ErrorCode,
+    /// The fields that were rejected, only set when code is validation_failed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    #[schema(example = "6f1c2a4e-3b9d-4f7a-9c1e-2d5b8a7e4f10")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl ProblemDetails {
+    pub fn new(api_error: ApiError, instance: Option<String>) -> Self {
+        Self {
+            problem_type: api_error.error_code.problem_type(),
+            title: api_error.status,
+            status: api_error.code,
+            detail: api_error.message,
+            instance,
+            code: api_error.error_code,
+            errors: api_error.errors,
+            request_id: api_error.request_id,
         }
     }
 }
 
+/// Middleware rendering AppError responses as problem+json for the clients that ask for it
+/// Responses that did not come from an AppError are left alone
+pub async fn negotiate_error_format(request: Request, next: Next) -> Response {
+    let wants_problem_json = accepts_problem_json(request.headers());
+    let instance = request.uri().path().to_string();
+
+    let response = next.run(request).await;
+    if !wants_problem_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Some(api_error) = parts.extensions.remove::<ApiError>() else {
+        return Response::from_parts(parts, body);
+    };
+
+    let problem = ProblemDetails::new(api_error, Some(instance));
+    let body = serde_json::to_vec(&problem).expect("Problem details can be serialized");
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// True when application/problem+json is listed in the Accept header and not refused with q=0
+fn accepts_problem_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            let mut params = media_range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+
+            media_type.eq_ignore_ascii_case(PROBLEM_JSON) && !refused
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, http::Request, middleware, response::IntoResponse, routing::get, Router};
+    use tower::ServiceExt;
+
+    use crate::{
+        errors::{negotiate_error_format, AppError, FieldError, PROBLEM_JSON},
+        repositories::repository_error::RepositoryError,
+    };
+
+    fn get_router() -> Router {
+        Router::new()
+            .route(
+                "/user/:id",
+                get(|| async { Err::<(), _>(AppError::NotFound("User not found".to_string())) }),
+            )
+            .route(
+                "/users",
+                get(|| async {
+                    Err::<(), _>(AppError::UnprocessableEntity(vec![FieldError::new(
+                        "email",
+                        "must be a valid email address",
+                    )]))
+                }),
+            )
+            .layer(middleware::from_fn(negotiate_error_format))
+    }
+
+    async fn send(uri: &str, accept: &str) -> (String, serde_json::Value) {
+        let request = Request::builder()
+            .uri(uri)
+            .header("accept", accept)
+            .body(Body::empty())
+            .unwrap();
+        let response = get_router().oneshot(request).await.unwrap();
+        let content_type = response.headers()["content-type"].to_str().unwrap().to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn errors_default_to_api_error() {
+        // Act
+        let (content_type, body) = send("/user/42", "application/json").await;
+
+        // Assert
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body["code"], 404);
+        assert_eq!(body["status"], "Not Found");
+        assert_eq!(body["error_code"], "not_found");
+        assert_eq!(body["message"], "User not found");
+    }
+
+    #[tokio::test]
+    async fn errors_are_problem_json_when_accepted() {
+        // Act
+        let (content_type, body) = send("/users", "application/problem+json, application/json;q=0.5").await;
+        let (_, refused) = send("/users", "application/problem+json;q=0").await;
+
+        // Assert
+        assert_eq!(content_type, PROBLEM_JSON);
+        assert_eq!(body["type"], "urn:problem-type:validation_failed");
+        assert_eq!(body["title"], "Unprocessable Entity");
+        assert_eq!(body["status"], 422);
+        assert_eq!(body["instance"], "/users");
+        assert_eq!(body["code"], "validation_failed");
+        assert_eq!(body["errors"][0]["field"], "email");
+        assert_eq!(refused["error_code"], "validation_failed");
+    }
+
+    #[test]
+    fn repository_errors_map_to_precise_status_codes() {
+        // Arrange
+        let cases = [
+            (RepositoryError::Throttled, 503),
+            (RepositoryError::ConditionalCheckFailed, 409),
+            (RepositoryError::TableNotFound("users".to_string()), 500),
+            (RepositoryError::Timeout, 504),
+        ];
+
+        for (error, expected) in cases {
+            // Act
+            let response = AppError::from(error).into_response();
+
+            // Assert
+            assert_eq!(response.status(), expected);
+            assert_eq!(response.headers().contains_key("retry-after"), expected == 503);
+        }
+    }
+}
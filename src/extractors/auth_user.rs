@@ -0,0 +1,91 @@
+// Extractors run before the handler and can reject the request early
+// See https://docs.rs/axum/latest/axum/extract/index.html#defining-custom-extractors
+// Adding AuthUser as a handler argument is all it takes to require a valid bearer token or api key
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, HeaderName},
+};
+
+use crate::{
+    domain::auth::models::Permission,
+    errors::{AppError, AppResult},
+    services::{api_key_service::ApiKeyService, token_service::TokenService},
+};
+
+pub const X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// The authenticated caller, taken from the `Authorization: Bearer <jwt>` header
+/// or from the `X-Api-Key` header for service to service callers
+/// Use Authorized instead to also require a permission, see extractors/authorized.rs
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthUser {
+    /// Id of the user, the sub claim of the token
+    /// For api keys this is the id of the key, which never matches a user
+    pub id: String,
+    /// Permissions granted by the role claim of the token or the scopes of the api key
+    pub permissions: Vec<Permission>,
+}
+
+impl AuthUser {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions
+            .iter()
+            .any(|granted| granted.grants(permission))
+    }
+
+    /// Fails with Forbidden if the caller does not have the permission
+    pub fn require(&self, permission: Permission) -> AppResult<()> {
+        if !self.has_permission(permission) {
+            return Err(AppError::Forbidden);
+        }
+
+        Ok(())
+    }
+
+    /// Anyone can act on themselves, acting on other users requires users:admin
+    pub fn require_self_or_admin(&self, user_id: &str) -> AppResult<()> {
+        if self.id == user_id {
+            return Ok(());
+        }
+
+        self.require(Permission::UsersAdmin)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    TokenService: FromRef<S>,
+    ApiKeyService: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(key) = parts.headers.get(X_API_KEY) {
+            let key = key.to_str().map_err(|_| AppError::Unauthorized)?;
+            let api_key = ApiKeyService::from_ref(state).authenticate(key.trim()).await?;
+
+            return Ok(AuthUser {
+                id: api_key.id,
+                permissions: api_key.scopes,
+            });
+        }
+
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AppError::Unauthorized)?;
+
+        let claims = TokenService::from_ref(state).verify(token.trim())?;
+
+        Ok(AuthUser {
+            id: claims.sub,
+            permissions: claims.role.permissions().to_vec(),
+        })
+    }
+}
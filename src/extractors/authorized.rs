@@ -0,0 +1,74 @@
+// Authorized is an AuthUser that also holds a given permission
+// Handlers declare the permission they need through the type parameter e.g Authorized<UsersAdmin>,
+// so the requirement sits right next to the route and is checked before the handler runs
+// Checks that depend on the request, such as acting on yourself, are done in the handler with AuthUser::require*
+
+use std::{marker::PhantomData, ops::Deref};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+
+use crate::{
+    domain::auth::models::Permission,
+    errors::AppError,
+    extractors::auth_user::AuthUser,
+    services::{api_key_service::ApiKeyService, token_service::TokenService},
+};
+
+/// Marker types used as the type parameter of Authorized, one per Permission
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+/// Requires users:read
+pub struct UsersRead;
+
+impl RequiredPermission for UsersRead {
+    const PERMISSION: Permission = Permission::UsersRead;
+}
+
+/// Requires users:admin
+pub struct UsersAdmin;
+
+impl RequiredPermission for UsersAdmin {
+    const PERMISSION: Permission = Permission::UsersAdmin;
+}
+
+/// Rejects the request with Unauthorized without a valid bearer token or api key
+/// and with Forbidden if the caller does not have the permission P
+pub struct Authorized<P: RequiredPermission> {
+    pub user: AuthUser,
+    _permission: PhantomData<P>,
+}
+
+impl<P: RequiredPermission> Deref for Authorized<P> {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+#[async_trait]
+impl<S, P> FromRequestParts<S> for Authorized<P>
+where
+    TokenService: FromRef<S>,
+    ApiKeyService: FromRef<S>,
+    S: Send + Sync,
+    P: RequiredPermission,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        user.require(P::PERMISSION)?;
+
+        Ok(Authorized {
+            user,
+            _permission: PhantomData,
+        })
+    }
+}
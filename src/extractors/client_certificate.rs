@@ -0,0 +1,41 @@
+// The certificate presented by the client over mutual TLS
+// The TLS acceptor inserts it into the request extensions once the handshake succeeds, see utils/tls.rs
+// Take `Option<ClientCertificate>` as a handler argument when the server may run without mutual TLS
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::errors::AppError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertificate {
+    /// Distinguished name of the certificate subject e.g CN=nightly-export, O=Scaffold
+    pub subject: String,
+}
+
+impl ClientCertificate {
+    /// Reads the subject out of a DER encoded certificate, None if the certificate cannot be parsed
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, certificate) = X509Certificate::from_der(der).ok()?;
+
+        Some(Self {
+            subject: certificate.subject().to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientCertificate
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ClientCertificate>()
+            .cloned()
+            .ok_or(AppError::Unauthorized)
+    }
+}
@@ -0,0 +1,4 @@
+pub mod auth_user;
+pub mod authorized;
+pub mod client_certificate;
+pub mod validated;
@@ -0,0 +1,111 @@
+// Extractors validating the request before the handler runs, see utils/validation.rs for the rules
+// Use them in place of Json, Query and Path, e.g ValidatedJson(request): ValidatedJson<CreateUserViewModel>
+// Malformed requests are rejected with a 400, well formed requests that break a rule with a 422
+// listing every failing field, so that clients can show all the errors at once
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, FromRequestParts, Path, Query, Request},
+    http::request::Parts,
+    Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use utoipa::{openapi::path::ParameterIn, IntoParams, ToSchema};
+
+use crate::{
+    errors::{AppError, FieldError},
+    utils::validation::{validate_params, validate_schema, Validate},
+};
+
+/// A json body checked against the schema of T and its Validate impl
+pub struct ValidatedJson<T>(pub T);
+
+/// Query parameters checked against the params of T and its Validate impl
+pub struct ValidatedQuery<T>(pub T);
+
+/// Path parameters checked against the params of T and its Validate impl
+/// T has to be a struct deriving IntoParams with #[into_params(parameter_in = Path)]
+pub struct ValidatedPath<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + for<'s> ToSchema<'s> + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<Value>::from_request(req, state)
+            .await
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+
+        let (_, schema) = T::schema();
+        let mut errors = validate_schema(&value, &schema);
+        match serde_json::from_value::<T>(value) {
+            Ok(request) => {
+                errors.extend(request.validate());
+                reject_errors(errors).map(|_| ValidatedJson(request))
+            }
+            // The schema checks explain why the body could not be deserialized better than serde does
+            Err(_) if !errors.is_empty() => Err(AppError::UnprocessableEntity(errors)),
+            Err(e) => Err(AppError::BadRequest(e.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Serialize + IntoParams + Validate + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+
+        validate_parameters(&query, ParameterIn::Query).map(|_| ValidatedQuery(query))
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidatedPath<T>
+where
+    T: DeserializeOwned + Serialize + IntoParams + Validate + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(path) = Path::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+
+        validate_parameters(&path, ParameterIn::Path).map(|_| ValidatedPath(path))
+    }
+}
+
+/// Parameters are checked once deserialized, serializing them back gives us values to check the params against
+fn validate_parameters<T>(parameters: &T, parameter_in: ParameterIn) -> Result<(), AppError>
+where
+    T: Serialize + IntoParams + Validate,
+{
+    let value = serde_json::to_value(parameters).map_err(anyhow::Error::from)?;
+    let params = T::into_params(|| Some(parameter_in.clone()));
+
+    let mut errors = validate_params(&value, &params);
+    errors.extend(parameters.validate());
+    reject_errors(errors)
+}
+
+fn reject_errors(errors: Vec<FieldError>) -> Result<(), AppError> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::UnprocessableEntity(errors))
+    }
+}
@@ -2,6 +2,7 @@ pub mod config;
 pub mod controllers;
 pub mod domain;
 pub mod errors;
+pub mod extractors;
 pub mod repositories;
 pub mod services;
 pub mod utils;
@@ -11,17 +12,22 @@ use std::sync::Arc;
 
 use clap::Parser;
 use config::AppConfig;
+use utils::telemetry::Telemetry;
 
 #[tokio::main]
 async fn main() {
-    // Initialize logger
-    tracing_subscriber::fmt::init();
-
     // Initialize environment
     let app_config = get_app_config();
 
+    // Initialize logger and tracing
+    let telemetry = Telemetry::init(&app_config).unwrap();
+
     // Start the server
-    server::serve(app_config).await.unwrap();
+    let res = server::serve(app_config).await;
+
+    // Flush the spans of the last requests before exiting
+    telemetry.shutdown();
+    res.unwrap();
 }
 
 // Separating this so we can reuse it in tests
@@ -0,0 +1,113 @@
+// DynamoDB implementation of the ApiKeyStore trait
+// Keys live in their own api_keys table keyed by id
+
+use async_trait::async_trait;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::Client;
+use tracing::instrument;
+
+use crate::domain::api_key::models::ApiKey;
+use crate::errors::{AppError, AppResult};
+use crate::repositories::api_key_store::{ApiKeyStore, API_KEY_CONFLICT};
+use crate::repositories::repository_error::RepositoryError;
+use crate::utils::dynamodb_helpers::{
+    Condition, DynamoTable, KeySchema, ReadOptions, UpdateExpression,
+};
+
+#[derive(Clone)]
+pub struct ApiKeyRepository {
+    table: DynamoTable<ApiKey>,
+}
+
+impl ApiKeyRepository {
+    pub async fn new(shared_config: &SdkConfig) -> Self {
+        let client = Client::new(shared_config);
+
+        Self {
+            // Hardcoded for the same reason as the users table, see UserRepository
+            // Reads are consistent since a revoked key must be rejected straight away
+            table: DynamoTable::new(client, "api_keys", KeySchema::new("id"))
+                .with_consistent_reads(true),
+        }
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for ApiKeyRepository {
+    #[instrument(skip_all)]
+    async fn get_api_key(&self, id: String) -> AppResult<Option<ApiKey>> {
+        self.table.get(self.table.key(id)).await
+    }
+
+    /// Scans the whole table, there are only ever a handful of keys
+    #[instrument(skip_all)]
+    async fn list_api_keys(&self) -> AppResult<Vec<ApiKey>> {
+        let mut api_keys = vec![];
+        let mut exclusive_start_key = None;
+
+        loop {
+            let page = self
+                .table
+                .scan(ReadOptions {
+                    exclusive_start_key,
+                    ..Default::default()
+                })
+                .await?;
+            api_keys.extend(page.items);
+            exclusive_start_key = page.last_evaluated_key;
+
+            if exclusive_start_key.is_none() {
+                // Scans are not ordered
+                api_keys.sort_by(|a, b| a.id.cmp(&b.id));
+                return Ok(api_keys);
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn put_api_key(&self, api_key: &ApiKey) -> AppResult<()> {
+        let condition = Condition::attribute_not_exists("id").build();
+
+        match self.table.put(api_key, Some(condition)).await {
+            Err(AppError::RepositoryError(RepositoryError::ConditionalCheckFailed)) => {
+                Err(AppError::ObjectConflict(API_KEY_CONFLICT.to_string()))
+            }
+            res => res,
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn revoke_api_key(&self, id: String, revoked_at: String) -> AppResult<()> {
+        let update = UpdateExpression::new()
+            .set_if_not_exists("revoked_at", revoked_at)
+            .build();
+        // Without the condition the update would create an empty key
+        let condition = Condition::attribute_exists("id").build();
+
+        match self
+            .table
+            .update(self.table.key(id), update, Some(condition))
+            .await
+        {
+            Err(AppError::RepositoryError(RepositoryError::ConditionalCheckFailed)) => Ok(()),
+            res => res,
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn record_api_key_usage(&self, id: String, last_used_at: u64) -> AppResult<()> {
+        let update = UpdateExpression::new()
+            .set("last_used_at", last_used_at)
+            .build();
+        let condition = Condition::attribute_exists("id").build();
+
+        match self
+            .table
+            .update(self.table.key(id), update, Some(condition))
+            .await
+        {
+            Err(AppError::RepositoryError(RepositoryError::ConditionalCheckFailed)) => Ok(()),
+            res => res,
+        }
+    }
+}
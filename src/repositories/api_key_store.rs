@@ -0,0 +1,27 @@
+// The ApiKeyStore trait persists api keys, see ApiKey
+// ApiKeyRepository implements it on top of DynamoDB and InMemoryApiKeyRepository
+// keeps everything in memory for tests, the same way as UserStore
+
+use async_trait::async_trait;
+
+use crate::{domain::api_key::models::ApiKey, errors::AppResult};
+
+pub const API_KEY_CONFLICT: &str = "Api key already exists";
+
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    /// Revoked and expired keys are returned as well, it is up to the caller to reject them
+    async fn get_api_key(&self, id: String) -> AppResult<Option<ApiKey>>;
+
+    /// Lists every key including the revoked ones, ordered by id
+    async fn list_api_keys(&self) -> AppResult<Vec<ApiKey>>;
+
+    /// Fails with ObjectConflict if the id is already taken
+    async fn put_api_key(&self, api_key: &ApiKey) -> AppResult<()>;
+
+    /// Sets revoked_at, keys that are already revoked keep their original revoked_at
+    async fn revoke_api_key(&self, id: String, revoked_at: String) -> AppResult<()>;
+
+    /// Sets last_used_at, does nothing if the key does not exist
+    async fn record_api_key_usage(&self, id: String, last_used_at: u64) -> AppResult<()>;
+}
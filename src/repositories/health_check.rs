@@ -0,0 +1,20 @@
+// A HealthCheck is a cheap call to a dependency that tells whether it is reachable
+// Repositories implement it so that /health/ready can report on them, see HealthService
+// Checks are run with a timeout by the HealthService, implementations do not need their own
+
+use async_trait::async_trait;
+
+use crate::errors::AppResult;
+
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Name the check is reported under e.g dynamodb:users
+    fn name(&self) -> String;
+
+    /// Whether a failing check makes the instance unready, non critical failures only degrade it
+    fn critical(&self) -> bool {
+        true
+    }
+
+    async fn check(&self) -> AppResult<()>;
+}
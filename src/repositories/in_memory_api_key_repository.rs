@@ -0,0 +1,71 @@
+// In-memory implementation of the ApiKeyStore trait
+// Useful for tests and for running the api locally without access to DynamoDB
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+use crate::{
+    domain::api_key::models::ApiKey,
+    errors::{AppError, AppResult},
+    repositories::api_key_store::{ApiKeyStore, API_KEY_CONFLICT},
+};
+
+#[derive(Clone, Default)]
+pub struct InMemoryApiKeyRepository {
+    // BTreeMap keeps the keys ordered by id like the other implementations
+    api_keys: Arc<RwLock<BTreeMap<String, ApiKey>>>,
+}
+
+impl InMemoryApiKeyRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for InMemoryApiKeyRepository {
+    async fn get_api_key(&self, id: String) -> AppResult<Option<ApiKey>> {
+        let api_keys = self.api_keys.read().unwrap();
+
+        Ok(api_keys.get(&id).cloned())
+    }
+
+    async fn list_api_keys(&self) -> AppResult<Vec<ApiKey>> {
+        let api_keys = self.api_keys.read().unwrap();
+
+        Ok(api_keys.values().cloned().collect())
+    }
+
+    async fn put_api_key(&self, api_key: &ApiKey) -> AppResult<()> {
+        let mut api_keys = self.api_keys.write().unwrap();
+
+        if api_keys.contains_key(&api_key.id) {
+            return Err(AppError::ObjectConflict(API_KEY_CONFLICT.to_string()));
+        }
+        api_keys.insert(api_key.id.clone(), api_key.clone());
+
+        Ok(())
+    }
+
+    async fn revoke_api_key(&self, id: String, revoked_at: String) -> AppResult<()> {
+        let mut api_keys = self.api_keys.write().unwrap();
+
+        if let Some(stored) = api_keys.get_mut(&id) {
+            stored.revoked_at.get_or_insert(revoked_at);
+        }
+
+        Ok(())
+    }
+
+    async fn record_api_key_usage(&self, id: String, last_used_at: u64) -> AppResult<()> {
+        let mut api_keys = self.api_keys.write().unwrap();
+
+        if let Some(stored) = api_keys.get_mut(&id) {
+            stored.last_used_at = Some(last_used_at);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+// In-memory implementation of the RefreshTokenStore trait
+// Useful for tests and for running the api locally without access to DynamoDB
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+use crate::{
+    domain::auth::models::RefreshTokenFamily,
+    errors::{AppError, AppResult},
+    repositories::refresh_token_store::{RefreshTokenStore, REFRESH_TOKEN_CONFLICT},
+};
+
+#[derive(Clone, Default)]
+pub struct InMemoryRefreshTokenRepository {
+    families: Arc<RwLock<HashMap<String, RefreshTokenFamily>>>,
+}
+
+impl InMemoryRefreshTokenRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RefreshTokenStore for InMemoryRefreshTokenRepository {
+    async fn get_family(&self, id: String) -> AppResult<Option<RefreshTokenFamily>> {
+        let families = self.families.read().unwrap();
+
+        Ok(families.get(&id).cloned())
+    }
+
+    async fn put_family(&self, family: &RefreshTokenFamily) -> AppResult<()> {
+        let mut families = self.families.write().unwrap();
+
+        families.insert(family.id.clone(), family.clone());
+
+        Ok(())
+    }
+
+    async fn rotate_token(&self, family: &RefreshTokenFamily, next_token_hash: &str) -> AppResult<()> {
+        let mut families = self.families.write().unwrap();
+
+        match families.get_mut(&family.id) {
+            Some(stored) if !stored.is_revoked() && stored.token_hash == family.token_hash => {
+                stored.token_hash = next_token_hash.to_string();
+                Ok(())
+            }
+            _ => Err(AppError::ObjectConflict(REFRESH_TOKEN_CONFLICT.to_string())),
+        }
+    }
+
+    async fn revoke_family(&self, id: String) -> AppResult<()> {
+        let mut families = self.families.write().unwrap();
+
+        if let Some(stored) = families.get_mut(&id) {
+            stored.revoked_at = Some(chrono::Utc::now().to_rfc3339());
+        }
+
+        Ok(())
+    }
+}
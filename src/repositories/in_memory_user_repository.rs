@@ -0,0 +1,193 @@
+// In-memory implementation of the UserStore trait
+// Useful for tests and for running the api locally without access to DynamoDB
+// Conditional writes behave the same way as UserRepository so that the services can rely on them
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+use crate::{
+    domain::user::models::{normalize_email, User},
+    errors::{AppError, AppResult},
+    repositories::user_store::{
+        UserStore, DELETED_CONFLICT, EMAIL_CONFLICT, USERNAME_CONFLICT, USER_CONFLICT,
+        VERSION_CONFLICT,
+    },
+    utils::dynamodb_helpers::{DynamoItem, DynamoPage, IntoAttributeValue},
+};
+
+#[derive(Clone, Default)]
+pub struct InMemoryUserRepository {
+    // Everything lives behind a single lock so that each write is atomic like a DynamoDB transaction
+    tables: Arc<RwLock<Tables>>,
+}
+
+#[derive(Default)]
+struct Tables {
+    // BTreeMap keeps the users ordered by id so that we can page through them
+    users: BTreeMap<String, User>,
+    // Lowercased email or username to the id of the user that holds it
+    emails: HashMap<String, String>,
+    usernames: HashMap<String, String>,
+}
+
+impl InMemoryUserRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl UserStore for InMemoryUserRepository {
+    async fn get_user_by_id(&self, id: String) -> AppResult<Option<User>> {
+        let tables = self.tables.read().unwrap();
+
+        Ok(tables.users.get(&id).cloned())
+    }
+
+    async fn get_users_by_ids(&self, ids: Vec<String>) -> AppResult<Vec<Option<User>>> {
+        let tables = self.tables.read().unwrap();
+
+        Ok(ids.iter().map(|id| tables.users.get(id).cloned()).collect())
+    }
+
+    async fn get_user_by_email(&self, email: String) -> AppResult<Option<User>> {
+        let tables = self.tables.read().unwrap();
+
+        Ok(tables
+            .emails
+            .get(&normalize_email(&email))
+            .and_then(|id| tables.users.get(id))
+            .cloned())
+    }
+
+    async fn get_user_by_username(&self, username: String) -> AppResult<Option<User>> {
+        let tables = self.tables.read().unwrap();
+
+        Ok(tables
+            .users
+            .values()
+            .find(|user| user.username == username)
+            .cloned())
+    }
+
+    async fn list_users(
+        &self,
+        limit: i32,
+        exclusive_start_key: Option<DynamoItem>,
+    ) -> AppResult<DynamoPage<User>> {
+        let tables = self.tables.read().unwrap();
+
+        let start = match exclusive_start_key.as_ref().and_then(|key| key.get("id")) {
+            Some(id) => {
+                let id = id
+                    .as_s()
+                    .map_err(|_| AppError::BadRequest("Invalid cursor".to_string()))?;
+                Bound::Excluded(id.clone())
+            }
+            None => Bound::Unbounded,
+        };
+
+        let mut remaining = tables
+            .users
+            .range((start, Bound::Unbounded))
+            .map(|(_, user)| user)
+            .filter(|user| !user.is_deleted())
+            .peekable();
+
+        let items: Vec<User> = remaining.by_ref().take(limit as usize).cloned().collect();
+
+        // Only hand out a key if there is something left to read
+        let last_evaluated_key = match (items.last(), remaining.peek()) {
+            (Some(last), Some(_)) => Some(DynamoItem::from([(
+                "id".to_string(),
+                last.id.clone().into_av(),
+            )])),
+            _ => None,
+        };
+
+        Ok(DynamoPage {
+            items,
+            last_evaluated_key,
+        })
+    }
+
+    async fn put_user(&self, user: &User) -> AppResult<()> {
+        let mut tables = self.tables.write().unwrap();
+
+        if tables.users.contains_key(&user.id) {
+            return Err(AppError::ObjectConflict(USER_CONFLICT.to_string()));
+        }
+        if tables.emails.contains_key(&user.email.to_lowercase()) {
+            return Err(AppError::ObjectConflict(EMAIL_CONFLICT.to_string()));
+        }
+        if tables.usernames.contains_key(&user.username.to_lowercase()) {
+            return Err(AppError::ObjectConflict(USERNAME_CONFLICT.to_string()));
+        }
+
+        tables
+            .emails
+            .insert(user.email.to_lowercase(), user.id.clone());
+        tables
+            .usernames
+            .insert(user.username.to_lowercase(), user.id.clone());
+        tables.users.insert(user.id.clone(), normalized(user));
+
+        Ok(())
+    }
+
+    async fn update_user(&self, current: &User, updated: &User) -> AppResult<()> {
+        let mut tables = self.tables.write().unwrap();
+
+        match tables.users.get(&current.id) {
+            Some(stored) if stored.version == current.version => {}
+            _ => return Err(AppError::ObjectConflict(VERSION_CONFLICT.to_string())),
+        }
+
+        let taken_by_other = |holders: &HashMap<String, String>, value: &str| {
+            holders
+                .get(&value.to_lowercase())
+                .is_some_and(|holder| holder != &current.id)
+        };
+        if taken_by_other(&tables.emails, &updated.email) {
+            return Err(AppError::ObjectConflict(EMAIL_CONFLICT.to_string()));
+        }
+        if taken_by_other(&tables.usernames, &updated.username) {
+            return Err(AppError::ObjectConflict(USERNAME_CONFLICT.to_string()));
+        }
+
+        tables.emails.remove(&current.email.to_lowercase());
+        tables.usernames.remove(&current.username.to_lowercase());
+        tables
+            .emails
+            .insert(updated.email.to_lowercase(), updated.id.clone());
+        tables
+            .usernames
+            .insert(updated.username.to_lowercase(), updated.id.clone());
+        tables.users.insert(updated.id.clone(), normalized(updated));
+
+        Ok(())
+    }
+
+    async fn delete_user(&self, user: &User) -> AppResult<()> {
+        let mut tables = self.tables.write().unwrap();
+
+        let Some(stored) = tables.users.remove(&user.id) else {
+            return Err(AppError::ObjectConflict(DELETED_CONFLICT.to_string()));
+        };
+
+        tables.emails.remove(&stored.email.to_lowercase());
+        tables.usernames.remove(&stored.username.to_lowercase());
+
+        Ok(())
+    }
+}
+
+/// The user as it is stored, with its email normalized like the other stores do
+fn normalized(user: &User) -> User {
+    let mut user = user.clone();
+    user.email = normalize_email(&user.email);
+    user
+}
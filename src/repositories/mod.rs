@@ -1 +1,17 @@
-pub mod user_repository;
\ No newline at end of file
+pub mod api_key_repository;
+pub mod api_key_store;
+pub mod health_check;
+pub mod in_memory_api_key_repository;
+pub mod in_memory_refresh_token_repository;
+pub mod in_memory_user_repository;
+pub mod refresh_token_repository;
+pub mod refresh_token_store;
+pub mod repository_error;
+#[cfg(feature = "sql")]
+pub mod sql_api_key_repository;
+#[cfg(feature = "sql")]
+pub mod sql_refresh_token_repository;
+#[cfg(feature = "sql")]
+pub mod sql_user_repository;
+pub mod user_repository;
+pub mod user_store;
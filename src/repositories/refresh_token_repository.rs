@@ -0,0 +1,92 @@
+// DynamoDB implementation of the RefreshTokenStore trait
+// Families live in their own refresh_tokens table keyed by id
+// Enable TTL on the expires_at attribute of the table so that expired families are removed automatically
+
+use async_trait::async_trait;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::Client;
+use tracing::instrument;
+
+use crate::domain::auth::models::RefreshTokenFamily;
+use crate::errors::{AppError, AppResult};
+use crate::repositories::refresh_token_store::{RefreshTokenStore, REFRESH_TOKEN_CONFLICT};
+use crate::repositories::repository_error::RepositoryError;
+use crate::utils::dynamodb_helpers::{Condition, DynamoTable, KeySchema, UpdateExpression};
+
+#[derive(Clone)]
+pub struct RefreshTokenRepository {
+    table: DynamoTable<RefreshTokenFamily>,
+}
+
+impl RefreshTokenRepository {
+    pub async fn new(shared_config: &SdkConfig) -> Self {
+        let client = Client::new(shared_config);
+
+        Self {
+            // Hardcoded for the same reason as the users table, see UserRepository
+            // Reads are consistent since rotation and revocation must be seen straight away,
+            // or a used token could be accepted again
+            table: DynamoTable::new(client, "refresh_tokens", KeySchema::new("id"))
+                .with_consistent_reads(true),
+        }
+    }
+}
+
+#[async_trait]
+impl RefreshTokenStore for RefreshTokenRepository {
+    #[instrument(skip_all)]
+    async fn get_family(&self, id: String) -> AppResult<Option<RefreshTokenFamily>> {
+        self.table.get(self.table.key(id)).await
+    }
+
+    #[instrument(skip_all)]
+    async fn put_family(&self, family: &RefreshTokenFamily) -> AppResult<()> {
+        self.table
+            .put(family, Some(Condition::attribute_not_exists("id").build()))
+            .await
+    }
+
+    #[instrument(skip_all)]
+    async fn rotate_token(
+        &self,
+        family: &RefreshTokenFamily,
+        next_token_hash: &str,
+    ) -> AppResult<()> {
+        let update = UpdateExpression::new()
+            .set("token_hash", next_token_hash.to_string())
+            .build();
+        let condition = Condition::attribute_exists("id")
+            .and(Condition::eq("token_hash", family.token_hash.clone()))
+            .and(Condition::attribute_not_exists("revoked_at"))
+            .build();
+
+        match self
+            .table
+            .update(self.table.key(family.id.clone()), update, Some(condition))
+            .await
+        {
+            Err(AppError::RepositoryError(RepositoryError::ConditionalCheckFailed)) => {
+                Err(AppError::ObjectConflict(REFRESH_TOKEN_CONFLICT.to_string()))
+            }
+            res => res,
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn revoke_family(&self, id: String) -> AppResult<()> {
+        let update = UpdateExpression::new()
+            .set("revoked_at", chrono::Utc::now().to_rfc3339())
+            .build();
+        // Without the condition the update would create an empty family
+        let condition = Condition::attribute_exists("id").build();
+
+        match self
+            .table
+            .update(self.table.key(id), update, Some(condition))
+            .await
+        {
+            Err(AppError::RepositoryError(RepositoryError::ConditionalCheckFailed)) => Ok(()),
+            res => res,
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// The RefreshTokenStore trait persists refresh token families, see RefreshTokenFamily
+// RefreshTokenRepository implements it on top of DynamoDB and InMemoryRefreshTokenRepository
+// keeps everything in memory for tests, the same way as UserStore
+
+use async_trait::async_trait;
+
+use crate::{domain::auth::models::RefreshTokenFamily, errors::AppResult};
+
+pub const REFRESH_TOKEN_CONFLICT: &str = "Refresh token has already been used";
+
+#[async_trait]
+pub trait RefreshTokenStore: Send + Sync {
+    /// Revoked and expired families are returned as well, it is up to the caller to reject them
+    async fn get_family(&self, id: String) -> AppResult<Option<RefreshTokenFamily>>;
+
+    async fn put_family(&self, family: &RefreshTokenFamily) -> AppResult<()>;
+
+    /// Replaces the current token of the family with `next_token_hash`
+    /// Fails with ObjectConflict if the family has been revoked or its token is no longer
+    /// `family.token_hash`, which happens when the same token is refreshed twice concurrently
+    async fn rotate_token(&self, family: &RefreshTokenFamily, next_token_hash: &str) -> AppResult<()>;
+
+    /// Marks the family as revoked, does nothing if the family does not exist
+    async fn revoke_family(&self, id: String) -> AppResult<()>;
+}
@@ -0,0 +1,179 @@
+// Errors the DynamoDB repositories can run into, classified so that clients get a precise status code
+// instead of an opaque 500, e.g a throttled request is worth retrying while a missing table is not
+// Repositories convert every SdkError with RepositoryError::from_sdk_error, which also logs it
+
+use std::{fmt::Debug, time::Duration};
+
+use aws_sdk_dynamodb::error::{ProvideErrorMetadata, SdkError};
+use tracing::log::error;
+
+use crate::utils::dynamodb_helpers::log_sdk_error;
+
+/// How long clients are asked to wait before retrying a throttled request
+/// The sdk has already retried with backoff by the time we give up, so a second is enough
+pub const THROTTLED_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Error codes DynamoDB uses when requests are over the provisioned or account throughput
+const THROTTLING_CODES: [&str; 3] = [
+    "ProvisionedThroughputExceededException",
+    "ThrottlingException",
+    "RequestLimitExceeded",
+];
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum RepositoryError {
+    #[error("The database is busy, please retry later")]
+    Throttled,
+    #[error("The item was modified by another request")]
+    ConditionalCheckFailed,
+    #[error("Table {0} does not exist")]
+    TableNotFound(String),
+    #[error("The database did not respond in time")]
+    Timeout,
+    #[error("Error while accessing the database")]
+    Unexpected,
+}
+
+impl RepositoryError {
+    /// Logs the error with log_sdk_error and classifies it, failed conditions are not logged
+    pub fn from_sdk_error<E, R>(error: SdkError<E, R>, table_name: &str) -> Self
+    where
+        E: ProvideErrorMetadata + Debug,
+        R: Debug,
+    {
+        let repository_error = Self::classify(&error, table_name);
+        match repository_error {
+            // The expected outcome of a conditional write, it is up to the caller to treat it as an error
+            RepositoryError::ConditionalCheckFailed => {}
+            // Most likely a misconfiguration, make it obvious in the logs
+            RepositoryError::TableNotFound(_) => {
+                error!(
+                    "Table {} does not exist, check the table name and the AWS region",
+                    table_name
+                );
+                log_sdk_error(error);
+            }
+            _ => log_sdk_error(error),
+        }
+
+        repository_error
+    }
+
+    /// How long clients should wait before retrying, only set for throttled requests
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RepositoryError::Throttled => Some(THROTTLED_RETRY_AFTER),
+            _ => None,
+        }
+    }
+
+    /// Label of the error in the DynamoDB metrics and spans, see metrics::record_dynamodb_call
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            RepositoryError::Throttled => "throttled",
+            RepositoryError::ConditionalCheckFailed => "conditional_check_failed",
+            RepositoryError::TableNotFound(_) => "table_not_found",
+            RepositoryError::Timeout => "timeout",
+            RepositoryError::Unexpected => "unexpected",
+        }
+    }
+
+    /// Classifies the error without logging it, see from_sdk_error
+    pub fn classify<E, R>(error: &SdkError<E, R>, table_name: &str) -> Self
+    where
+        E: ProvideErrorMetadata,
+    {
+        match error {
+            SdkError::TimeoutError(_) => RepositoryError::Timeout,
+            SdkError::DispatchFailure(failure) if failure.is_timeout() => RepositoryError::Timeout,
+            SdkError::ServiceError(_) => match error.code() {
+                Some(code) if THROTTLING_CODES.contains(&code) => RepositoryError::Throttled,
+                Some("ConditionalCheckFailedException") => RepositoryError::ConditionalCheckFailed,
+                Some("ResourceNotFoundException") => {
+                    RepositoryError::TableNotFound(table_name.to_string())
+                }
+                _ => RepositoryError::Unexpected,
+            },
+            _ => RepositoryError::Unexpected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use aws_sdk_dynamodb::{error::SdkError, operation::get_item::GetItemError};
+    use aws_smithy_types::error::ErrorMetadata;
+
+    use crate::repositories::repository_error::RepositoryError;
+
+    fn service_error(code: &str) -> SdkError<GetItemError, ()> {
+        SdkError::service_error(
+            GetItemError::generic(ErrorMetadata::builder().code(code).build()),
+            (),
+        )
+    }
+
+    #[test]
+    fn from_sdk_error_classifies_service_errors() {
+        // Arrange
+        let cases = [
+            (
+                "ProvisionedThroughputExceededException",
+                RepositoryError::Throttled,
+            ),
+            ("ThrottlingException", RepositoryError::Throttled),
+            (
+                "ConditionalCheckFailedException",
+                RepositoryError::ConditionalCheckFailed,
+            ),
+            (
+                "ResourceNotFoundException",
+                RepositoryError::TableNotFound("users".to_string()),
+            ),
+            ("InternalServerError", RepositoryError::Unexpected),
+        ];
+
+        for (code, expected) in cases {
+            // Act
+            let error = RepositoryError::from_sdk_error(service_error(code), "users");
+
+            // Assert
+            assert_eq!(error, expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn from_sdk_error_classifies_timeouts() {
+        // Arrange
+        let error = SdkError::<GetItemError, ()>::timeout_error("operation timed out");
+
+        // Act
+        let error = RepositoryError::from_sdk_error(error, "users");
+
+        // Assert
+        assert_eq!(error, RepositoryError::Timeout);
+    }
+
+    #[test]
+    fn outcome_is_the_classification() {
+        // Arrange
+        let cases = [
+            (service_error("ThrottlingException"), "throttled"),
+            (
+                service_error("ConditionalCheckFailedException"),
+                "conditional_check_failed",
+            ),
+            (service_error("ResourceNotFoundException"), "table_not_found"),
+            (SdkError::timeout_error("operation timed out"), "timeout"),
+            (service_error("InternalServerError"), "unexpected"),
+        ];
+
+        for (error, expected) in cases {
+            // Act
+            let outcome = RepositoryError::classify(&error, "users").outcome();
+
+            // Assert
+            assert_eq!(outcome, expected);
+        }
+    }
+}
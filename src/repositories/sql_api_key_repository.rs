@@ -0,0 +1,140 @@
+// Sql implementation of the ApiKeyStore trait, see sql_user_repository.rs
+// Shares the connection pool of SqlUserRepository, the table is created by the embedded migrations
+// Scopes are stored space separated e.g "users:read users:admin"
+
+use async_trait::async_trait;
+use sqlx::AnyPool;
+use tracing::instrument;
+
+use crate::{
+    domain::{api_key::models::ApiKey, auth::models::Permission},
+    errors::{AppError, AppResult},
+    repositories::{api_key_store::ApiKeyStore, sql_user_repository::map_sqlx_error},
+};
+
+#[derive(Clone)]
+pub struct SqlApiKeyRepository {
+    pool: AnyPool,
+}
+
+impl SqlApiKeyRepository {
+    pub fn new(pool: AnyPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// The api_keys table as it is stored, converted into ApiKey with try_into
+#[derive(sqlx::FromRow)]
+struct ApiKeyRow {
+    id: String,
+    name: String,
+    key_hash: String,
+    scopes: String,
+    created_by: String,
+    created_at: String,
+    expires_at: Option<i64>,
+    last_used_at: Option<i64>,
+    revoked_at: Option<String>,
+}
+
+impl TryFrom<ApiKeyRow> for ApiKey {
+    type Error = AppError;
+
+    fn try_from(row: ApiKeyRow) -> Result<Self, Self::Error> {
+        let scopes = row
+            .scopes
+            .split_whitespace()
+            .map(Permission::try_from)
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow::anyhow!("Invalid scopes on api key {}: {}", row.id, e))?;
+
+        Ok(ApiKey {
+            id: row.id,
+            name: row.name,
+            key_hash: row.key_hash,
+            scopes,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            expires_at: row.expires_at.map(|expires_at| expires_at as u64),
+            last_used_at: row.last_used_at.map(|last_used_at| last_used_at as u64),
+            revoked_at: row.revoked_at,
+        })
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for SqlApiKeyRepository {
+    #[instrument(skip_all)]
+    async fn get_api_key(&self, id: String) -> AppResult<Option<ApiKey>> {
+        let row: Option<ApiKeyRow> = sqlx::query_as("SELECT * FROM api_keys WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        row.map(ApiKey::try_from).transpose()
+    }
+
+    #[instrument(skip_all)]
+    async fn list_api_keys(&self) -> AppResult<Vec<ApiKey>> {
+        let rows: Vec<ApiKeyRow> = sqlx::query_as("SELECT * FROM api_keys ORDER BY id")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        rows.into_iter().map(ApiKey::try_from).collect()
+    }
+
+    #[instrument(skip_all)]
+    async fn put_api_key(&self, api_key: &ApiKey) -> AppResult<()> {
+        let scopes = api_key
+            .scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        sqlx::query(
+            "INSERT INTO api_keys (id, name, key_hash, scopes, created_by, created_at, expires_at, last_used_at, revoked_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(api_key.id.clone())
+        .bind(api_key.name.clone())
+        .bind(api_key.key_hash.clone())
+        .bind(scopes)
+        .bind(api_key.created_by.clone())
+        .bind(api_key.created_at.clone())
+        .bind(api_key.expires_at.map(|expires_at| expires_at as i64))
+        .bind(api_key.last_used_at.map(|last_used_at| last_used_at as i64))
+        .bind(api_key.revoked_at.clone())
+        .execute(&self.pool)
+        .await
+        .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn revoke_api_key(&self, id: String, revoked_at: String) -> AppResult<()> {
+        sqlx::query("UPDATE api_keys SET revoked_at = $1 WHERE id = $2 AND revoked_at IS NULL")
+            .bind(revoked_at)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn record_api_key_usage(&self, id: String, last_used_at: u64) -> AppResult<()> {
+        sqlx::query("UPDATE api_keys SET last_used_at = $1 WHERE id = $2")
+            .bind(last_used_at as i64)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+}
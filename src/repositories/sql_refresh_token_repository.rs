@@ -0,0 +1,88 @@
+// Sql implementation of the RefreshTokenStore trait, see sql_user_repository.rs
+// Shares the connection pool of SqlUserRepository, the table is created by the embedded migrations
+
+use async_trait::async_trait;
+use sqlx::AnyPool;
+use tracing::instrument;
+
+use crate::{
+    domain::auth::models::RefreshTokenFamily,
+    errors::{AppError, AppResult},
+    repositories::{
+        refresh_token_store::{RefreshTokenStore, REFRESH_TOKEN_CONFLICT},
+        sql_user_repository::map_sqlx_error,
+    },
+};
+
+#[derive(Clone)]
+pub struct SqlRefreshTokenRepository {
+    pool: AnyPool,
+}
+
+impl SqlRefreshTokenRepository {
+    pub fn new(pool: AnyPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl RefreshTokenStore for SqlRefreshTokenRepository {
+    #[instrument(skip_all)]
+    async fn get_family(&self, id: String) -> AppResult<Option<RefreshTokenFamily>> {
+        sqlx::query_as("SELECT * FROM refresh_token_families WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    #[instrument(skip_all)]
+    async fn put_family(&self, family: &RefreshTokenFamily) -> AppResult<()> {
+        sqlx::query(
+            "INSERT INTO refresh_token_families (id, user_id, token_hash, expires_at, revoked_at) \
+             VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(family.id.clone())
+        .bind(family.user_id.clone())
+        .bind(family.token_hash.clone())
+        .bind(family.expires_at as i64)
+        .bind(family.revoked_at.clone())
+        .execute(&self.pool)
+        .await
+        .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn rotate_token(&self, family: &RefreshTokenFamily, next_token_hash: &str) -> AppResult<()> {
+        let res = sqlx::query(
+            "UPDATE refresh_token_families SET token_hash = $1 \
+             WHERE id = $2 AND token_hash = $3 AND revoked_at IS NULL",
+        )
+        .bind(next_token_hash.to_string())
+        .bind(family.id.clone())
+        .bind(family.token_hash.clone())
+        .execute(&self.pool)
+        .await
+        .map_err(map_sqlx_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(AppError::ObjectConflict(REFRESH_TOKEN_CONFLICT.to_string()));
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn revoke_family(&self, id: String) -> AppResult<()> {
+        sqlx::query("UPDATE refresh_token_families SET revoked_at = $1 WHERE id = $2")
+            .bind(chrono::Utc::now().to_rfc3339())
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+// Sql implementation of the UserStore trait for deployments that cannot use DynamoDB
+// Only compiled with the sqlite or postgres cargo feature, see Cargo.toml
+// sqlx's Any driver picks SQLite or Postgres from the database url so the same queries run on both
+// Placeholders must appear in ascending order in every query, SQLite numbers $N parameters by first appearance
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use sqlx::any::{install_default_drivers, AnyPoolOptions};
+use sqlx::error::ErrorKind;
+use sqlx::AnyPool;
+use tracing::{instrument, log::error};
+
+use crate::{
+    domain::user::models::{normalize_email, User},
+    errors::{AppError, AppResult},
+    repositories::health_check::HealthCheck,
+    repositories::user_store::{
+        UserStore, DELETED_CONFLICT, EMAIL_CONFLICT, USERNAME_CONFLICT, USER_CONFLICT,
+        VERSION_CONFLICT,
+    },
+    utils::dynamodb_helpers::{DynamoItem, DynamoPage, IntoAttributeValue},
+};
+
+#[derive(Clone)]
+pub struct SqlUserRepository {
+    pool: AnyPool,
+}
+
+impl SqlUserRepository {
+    /// Connects to the database and runs the embedded migrations in /migrations
+    pub async fn new(database_url: &str) -> anyhow::Result<Self> {
+        install_default_drivers();
+
+        // Every connection to an in memory SQLite database gets its own database,
+        // so we have to stick to a single connection for those
+        let max_connections = if database_url.contains(":memory:") { 1 } else { 10 };
+        let pool = AnyPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(database_url)
+            .await?;
+
+        sqlx::migrate!("./migrations").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    /// The pool is shared with SqlRefreshTokenRepository
+    pub fn pool(&self) -> AnyPool {
+        self.pool.clone()
+    }
+}
+
+#[async_trait]
+impl HealthCheck for SqlUserRepository {
+    fn name(&self) -> String {
+        "sql".to_string()
+    }
+
+    #[instrument(skip_all)]
+    async fn check(&self) -> AppResult<()> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserStore for SqlUserRepository {
+    #[instrument(skip_all)]
+    async fn get_user_by_id(&self, id: String) -> AppResult<Option<User>> {
+        sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    /// Reads the users a hundred ids at a time to stay under the bound parameter limits
+    #[instrument(skip_all)]
+    async fn get_users_by_ids(&self, ids: Vec<String>) -> AppResult<Vec<Option<User>>> {
+        let mut found = HashMap::new();
+
+        for chunk in ids.chunks(100) {
+            let placeholders = (1..=chunk.len())
+                .map(|index| format!("${}", index))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let sql = format!("SELECT * FROM users WHERE id IN ({})", placeholders);
+
+            let users: Vec<User> = chunk
+                .iter()
+                .fold(sqlx::query_as(&sql), |query, id| query.bind(id))
+                .fetch_all(&self.pool)
+                .await
+                .map_err(map_sqlx_error)?;
+            found.extend(users.into_iter().map(|user| (user.id.clone(), user)));
+        }
+
+        Ok(ids.iter().map(|id| found.get(id).cloned()).collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn get_user_by_email(&self, email: String) -> AppResult<Option<User>> {
+        // lower(email) also matches rows written before emails were normalized, using users_email_unique
+        sqlx::query_as("SELECT * FROM users WHERE lower(email) = $1")
+            .bind(normalize_email(&email))
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    #[instrument(skip_all)]
+    async fn get_user_by_username(&self, username: String) -> AppResult<Option<User>> {
+        sqlx::query_as("SELECT * FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    #[instrument(skip_all)]
+    async fn list_users(
+        &self,
+        limit: i32,
+        exclusive_start_key: Option<DynamoItem>,
+    ) -> AppResult<DynamoPage<User>> {
+        let start_after = match exclusive_start_key.as_ref().and_then(|key| key.get("id")) {
+            Some(id) => id
+                .as_s()
+                .map_err(|_| AppError::BadRequest("Invalid cursor".to_string()))?
+                .clone(),
+            None => String::new(),
+        };
+
+        // Read one more than asked for to know whether there is another page
+        let mut items: Vec<User> = sqlx::query_as(
+            "SELECT * FROM users WHERE deleted_at IS NULL AND id > $1 ORDER BY id LIMIT $2",
+        )
+        .bind(start_after)
+        .bind(limit as i64 + 1)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(map_sqlx_error)?;
+
+        let last_evaluated_key = if items.len() > limit as usize {
+            items.truncate(limit as usize);
+            items
+                .last()
+                .map(|last| DynamoItem::from([("id".to_string(), last.id.clone().into_av())]))
+        } else {
+            None
+        };
+
+        Ok(DynamoPage {
+            items,
+            last_evaluated_key,
+        })
+    }
+
+    #[instrument(skip_all)]
+    async fn put_user(&self, user: &User) -> AppResult<()> {
+        sqlx::query(
+            "INSERT INTO users (id, email, username, bio, image, created_at, updated_at, version, deleted_at, password_hash, role) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+        )
+        .bind(user.id.clone())
+        .bind(normalize_email(&user.email))
+        .bind(user.username.clone())
+        .bind(user.bio.clone())
+        .bind(user.image.clone())
+        .bind(user.created_at().to_string())
+        .bind(user.updated_at().to_string())
+        .bind(user.version as i64)
+        .bind(user.deleted_at().map(|deleted_at| deleted_at.to_string()))
+        .bind(user.password_hash().map(|password_hash| password_hash.to_string()))
+        .bind(user.role.as_str())
+        .execute(&self.pool)
+        .await
+        .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn update_user(&self, current: &User, updated: &User) -> AppResult<()> {
+        let res = sqlx::query(
+            "UPDATE users SET email = $1, username = $2, bio = $3, image = $4, updated_at = $5, \
+             version = $6, deleted_at = $7, password_hash = $8, role = $9 WHERE id = $10 AND version = $11",
+        )
+        .bind(normalize_email(&updated.email))
+        .bind(updated.username.clone())
+        .bind(updated.bio.clone())
+        .bind(updated.image.clone())
+        .bind(updated.updated_at().to_string())
+        .bind(updated.version as i64)
+        .bind(updated.deleted_at().map(|deleted_at| deleted_at.to_string()))
+        .bind(updated.password_hash().map(|password_hash| password_hash.to_string()))
+        .bind(updated.role.as_str())
+        .bind(current.id.clone())
+        .bind(current.version as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(map_sqlx_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(AppError::ObjectConflict(VERSION_CONFLICT.to_string()));
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn delete_user(&self, user: &User) -> AppResult<()> {
+        let res = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user.id.clone())
+            .execute(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(AppError::ObjectConflict(DELETED_CONFLICT.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Unique violations become the same conflicts UserRepository returns, anything else is logged
+pub(crate) fn map_sqlx_error(e: sqlx::Error) -> AppError {
+    if let sqlx::Error::Database(database_error) = &e {
+        if database_error.kind() == ErrorKind::UniqueViolation {
+            // SQLite only tells us the index name through the message while Postgres has constraint()
+            let constraint = database_error
+                .constraint()
+                .map(|constraint| constraint.to_string())
+                .unwrap_or_else(|| database_error.message().to_string());
+
+            let message = if constraint.contains("users_email_unique") {
+                EMAIL_CONFLICT
+            } else if constraint.contains("users_username_unique") {
+                USERNAME_CONFLICT
+            } else {
+                USER_CONFLICT
+            };
+
+            return AppError::ObjectConflict(message.to_string());
+        }
+    }
+
+    error!("Sql error: {:?}", e);
+    AppError::AnyhowError(anyhow::anyhow!("Error while accessing the database"))
+}
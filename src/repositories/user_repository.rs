@@ -1,20 +1,46 @@
 // The repository layer is where you will be writing queries to call the database
 // In this layer we DO NOT process the data retrieved from the database
-// We will simply handle the queries and return the data as is
+// We will simply handle the queries and return the data as is, converted into our models
 // Any database related errors should be handled here
-// In the repository layer I am using anyhow to handle errors as it is more convenient
-// You could AWS SdkError into errors.rs and handle it instead too
+// AWS SdkErrors are classified into a RepositoryError, so that e.g throttling is reported as a 503
+// rather than an opaque 500, see repository_error.rs
 
+use crate::domain::user::models::{normalize_email, User};
+use crate::errors::{AppError, AppResult};
+use crate::repositories::health_check::HealthCheck;
+use crate::repositories::user_store::{
+    UserStore, DELETED_CONFLICT, EMAIL_CONFLICT, USERNAME_CONFLICT, USER_CONFLICT, VERSION_CONFLICT,
+};
+use crate::repositories::repository_error::RepositoryError;
 use crate::utils::dynamodb_helpers::log_sdk_error;
 use crate::utils::dynamodb_helpers::DynamoItem;
+use crate::utils::dynamodb_helpers::DynamoPage;
+use crate::utils::dynamodb_helpers::Condition;
+use crate::utils::dynamodb_helpers::DynamoTable;
 use crate::utils::dynamodb_helpers::IntoAttributeValue;
+use crate::utils::dynamodb_helpers::KeyCondition;
+use crate::utils::dynamodb_helpers::KeySchema;
+use crate::utils::dynamodb_helpers::Placeholders;
+use crate::utils::dynamodb_helpers::Projection;
+use crate::utils::dynamodb_helpers::ReadOptions;
+use crate::utils::dynamodb_helpers::UpdateExpression;
+use crate::utils::metrics::record_dynamodb_call;
+use async_trait::async_trait;
 use aws_config::SdkConfig;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::types::{AttributeValue, Delete, Put, TransactWriteItem, Update};
 use aws_sdk_dynamodb::Client;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
 
 #[derive(Clone)]
 pub struct UserRepository {
-    client: Client,
-    table_name: String,
+    users: DynamoTable<User>,
+    /// The same table, to read the ids projected by the email and username indexes
+    user_ids: DynamoTable<UserId>,
+    email_index_name: String,
+    username_index_name: String,
     pub max_retries: Option<u32>,
 }
 
@@ -23,29 +49,464 @@ impl UserRepository {
         let client = Client::new(shared_config);
 
         Self {
-            client,
             // For the sake of simplicity we will hardcode the table name here
             // You can also use environment variable to store the table name such as user_table_name
-            table_name: "users".to_string(),
+            users: DynamoTable::new(client.clone(), "users", KeySchema::new("id")),
+            user_ids: DynamoTable::new(client, "users", KeySchema::new("id")),
+            email_index_name: "email-index".to_string(),
+            username_index_name: "username-index".to_string(),
             max_retries,
         }
     }
 
-    pub async fn get_user_by_id(self, id: String) -> anyhow::Result<Option<DynamoItem>> {
-        let res = self
-            .client
-            .get_item()
-            .table_name(self.table_name)
-            .key("id", id.into_av()) // into_av() is a helper function that converts a primitive value into dynamodb's AttributeValue
-            .send()
-            .await;
+    /// Overrides the names of the global secondary indexes keyed by email and username
+    /// The default names are kept for the ones that are None
+    pub fn with_index_names(
+        mut self,
+        email_index_name: Option<String>,
+        username_index_name: Option<String>,
+    ) -> Self {
+        if let Some(email_index_name) = email_index_name {
+            self.email_index_name = email_index_name;
+        }
+        if let Some(username_index_name) = username_index_name {
+            self.username_index_name = username_index_name;
+        }
+        self
+    }
+
+    /// Looks up the user id through a GSI and then reads the user from the table
+    /// Only the id is read from the index so this works whatever attributes the index projects,
+    /// and the user we return is as consistent as a normal get_item
+    #[instrument(skip_all)]
+    async fn get_user_by_index(
+        &self,
+        index_name: &str,
+        attribute: &str,
+        value: String,
+    ) -> AppResult<Option<User>> {
+        let page = self
+            .user_ids
+            .query(
+                KeyCondition::partition(attribute, value).build(),
+                ReadOptions {
+                    index_name: Some(index_name.to_string()),
+                    projection: Some(Projection::of(&["id"]).build()),
+                    limit: Some(1),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let id = page.items.into_iter().next().map(|user_id| user_id.id);
+
+        match id {
+            Some(id) => self.get_user_by_id(id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Runs the given items in a single transaction
+    /// Each item is paired with the conflict message to return if its condition fails
+    #[instrument(skip_all)]
+    async fn write_transaction(&self, items: Vec<(TransactWriteItem, &str)>) -> AppResult<()> {
+        let (transact_items, conflict_messages): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+
+        let res = record_dynamodb_call(
+            "TransactWriteItems",
+            self.users.table_name(),
+            self.users
+                .client()
+                .transact_write_items()
+                .set_transact_items(Some(transact_items))
+                .send(),
+        )
+        .await;
 
         match res {
-            Ok(res) => Ok(res.item),
+            Ok(_) => Ok(()),
             Err(e) => {
-                log_sdk_error(e);
-                Err(anyhow::anyhow!("Error while getting data"))
+                // The cancellation reasons are in the same order as the transact items
+                if let Some(reasons) = cancellation_codes(&e) {
+                    let conflict = reasons
+                        .iter()
+                        .position(|code| code.as_deref() == Some("ConditionalCheckFailed"));
+
+                    if let Some(index) = conflict {
+                        return Err(AppError::ObjectConflict(
+                            conflict_messages[index].to_string(),
+                        ));
+                    }
+
+                    // A throttled item cancels the whole transaction instead of failing it with a throttling error
+                    if reasons.iter().any(|code| code.as_deref() == Some("ThrottlingError")) {
+                        log_sdk_error(e);
+                        return Err(RepositoryError::Throttled.into());
+                    }
+                }
+
+                Err(RepositoryError::from_sdk_error(e, self.users.table_name()).into())
+            }
+        }
+    }
+
+    fn put_if_absent(&self, item: DynamoItem) -> TransactWriteItem {
+        let mut placeholders = Placeholders::default();
+        let condition = placeholders.add(Some(Condition::attribute_not_exists("id").build()));
+
+        let put = Put::builder()
+            .table_name(self.users.table_name())
+            .set_item(Some(item))
+            .set_condition_expression(condition)
+            .set_expression_attribute_names(placeholders.names())
+            .build();
+
+        TransactWriteItem::builder().put(put).build()
+    }
+
+    fn delete_uniqueness_item(&self, prefix: &str, value: &str) -> TransactWriteItem {
+        let delete = Delete::builder()
+            .table_name(self.users.table_name())
+            .key("id", uniqueness_key(prefix, value))
+            .build();
+
+        TransactWriteItem::builder().delete(delete).build()
+    }
+}
+
+#[async_trait]
+impl UserStore for UserRepository {
+    #[instrument(skip_all)]
+    async fn get_user_by_id(&self, id: String) -> AppResult<Option<User>> {
+        self.users.get(self.users.key(id)).await
+    }
+
+    /// Reads the users with BatchGetItem, see DynamoTable::batch_get
+    /// Ids of uniqueness items are reported as missing rather than read as users
+    /// There is no batch write of users to go with it, BatchWriteItem cannot have conditions
+    /// so it could neither check nor reserve the email and username uniqueness items
+    #[instrument(skip_all)]
+    async fn get_users_by_ids(&self, ids: Vec<String>) -> AppResult<Vec<Option<User>>> {
+        let keys = ids
+            .iter()
+            .filter(|id| !is_uniqueness_key(id))
+            .map(|id| self.users.key(id.clone()))
+            .collect();
+        let mut users = self.users.batch_get(keys).await?.into_iter();
+
+        Ok(ids
+            .iter()
+            .map(|id| match is_uniqueness_key(id) {
+                true => None,
+                false => users.next().flatten(),
+            })
+            .collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn get_user_by_email(&self, email: String) -> AppResult<Option<User>> {
+        self.get_user_by_index(&self.email_index_name, "email", normalize_email(&email))
+            .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_user_by_username(&self, username: String) -> AppResult<Option<User>> {
+        self.get_user_by_index(&self.username_index_name, "username", username)
+            .await
+    }
+
+    /// Scans through the users table, skipping uniqueness items and soft deleted users
+    /// Because the filter is applied after DynamoDB reads a page, a single Scan can come back
+    /// short, so we keep scanning until the page is full or the table is exhausted
+    #[instrument(skip_all)]
+    async fn list_users(
+        &self,
+        limit: i32,
+        exclusive_start_key: Option<DynamoItem>,
+    ) -> AppResult<DynamoPage<User>> {
+        let mut page = DynamoPage {
+            items: vec![],
+            last_evaluated_key: exclusive_start_key,
+        };
+
+        loop {
+            let res = self
+                .users
+                .scan(ReadOptions {
+                    filter: Some(
+                        Condition::attribute_exists("email")
+                            .and(Condition::attribute_not_exists("deleted_at"))
+                            .build(),
+                    ),
+                    limit: Some(limit - page.items.len() as i32),
+                    exclusive_start_key: page.last_evaluated_key.take(),
+                    ..Default::default()
+                })
+                .await?;
+            page.items.extend(res.items);
+            page.last_evaluated_key = res.last_evaluated_key;
+
+            if page.items.len() as i32 >= limit || page.last_evaluated_key.is_none() {
+                return Ok(page);
             }
         }
     }
+
+    /// Inserts a new user together with its email and username uniqueness items in a single transaction
+    /// DynamoDB has no unique constraints other than the primary key, so we reserve
+    /// `email#<email>` and `username#<username>` keys in the same table and let the
+    /// condition expressions fail the whole transaction if any of them already exist
+    #[instrument(skip_all)]
+    async fn put_user(&self, user: &User) -> AppResult<()> {
+        let mut user_item = self.users.to_item(user)?;
+        user_item.insert("email".to_string(), normalize_email(&user.email).into_av());
+
+        self.write_transaction(vec![
+            (self.put_if_absent(user_item), USER_CONFLICT),
+            (
+                self.put_if_absent(uniqueness_item(EMAIL_PREFIX, &user.email, &user.id)),
+                EMAIL_CONFLICT,
+            ),
+            (
+                self.put_if_absent(uniqueness_item(USERNAME_PREFIX, &user.username, &user.id)),
+                USERNAME_CONFLICT,
+            ),
+        ])
+        .await
+    }
+
+    /// Writes the changes between `current` and `updated` using an UpdateExpression
+    /// The write is rejected if the stored version is no longer `current.version`
+    /// If the email or username changed, their uniqueness items are swapped within the same transaction
+    #[instrument(skip_all)]
+    async fn update_user(&self, current: &User, updated: &User) -> AppResult<()> {
+        // Items written before versioning was introduced do not have a version attribute
+        let expected_version = if current.version == 0 {
+            Condition::attribute_not_exists("version").or(Condition::eq("version", current.version))
+        } else {
+            Condition::eq("version", current.version)
+        };
+        let condition = Condition::attribute_exists("id").and(expected_version);
+
+        let mut placeholders = Placeholders::default();
+        let update = Update::builder()
+            .table_name(self.users.table_name())
+            .key("id", current.id.clone().into_av())
+            .set_update_expression(placeholders.add(Some(user_update_expression(updated).build())))
+            .set_condition_expression(placeholders.add(Some(condition.build())))
+            .set_expression_attribute_names(placeholders.names())
+            .set_expression_attribute_values(placeholders.values())
+            .build();
+
+        let mut items = vec![(
+            TransactWriteItem::builder().update(update).build(),
+            VERSION_CONFLICT,
+        )];
+
+        if current.email.to_lowercase() != updated.email.to_lowercase() {
+            items.push((
+                self.delete_uniqueness_item(EMAIL_PREFIX, &current.email),
+                EMAIL_CONFLICT,
+            ));
+            items.push((
+                self.put_if_absent(uniqueness_item(EMAIL_PREFIX, &updated.email, &updated.id)),
+                EMAIL_CONFLICT,
+            ));
+        }
+
+        if current.username.to_lowercase() != updated.username.to_lowercase() {
+            items.push((
+                self.delete_uniqueness_item(USERNAME_PREFIX, &current.username),
+                USERNAME_CONFLICT,
+            ));
+            items.push((
+                self.put_if_absent(uniqueness_item(
+                    USERNAME_PREFIX,
+                    &updated.username,
+                    &updated.id,
+                )),
+                USERNAME_CONFLICT,
+            ));
+        }
+
+        self.write_transaction(items).await
+    }
+
+    /// Permanently removes the user and releases its email and username
+    #[instrument(skip_all)]
+    async fn delete_user(&self, user: &User) -> AppResult<()> {
+        let mut placeholders = Placeholders::default();
+        let condition = placeholders.add(Some(Condition::attribute_exists("id").build()));
+
+        let delete = Delete::builder()
+            .table_name(self.users.table_name())
+            .key("id", user.id.clone().into_av())
+            .set_condition_expression(condition)
+            .set_expression_attribute_names(placeholders.names())
+            .build();
+
+        self.write_transaction(vec![
+            (
+                TransactWriteItem::builder().delete(delete).build(),
+                DELETED_CONFLICT,
+            ),
+            (
+                self.delete_uniqueness_item(EMAIL_PREFIX, &user.email),
+                EMAIL_CONFLICT,
+            ),
+            (
+                self.delete_uniqueness_item(USERNAME_PREFIX, &user.username),
+                USERNAME_CONFLICT,
+            ),
+        ])
+        .await
+    }
+}
+
+// DescribeTable is a control plane call, it does not consume read capacity
+#[async_trait]
+impl HealthCheck for UserRepository {
+    fn name(&self) -> String {
+        format!("dynamodb:{}", self.users.table_name())
+    }
+
+    #[instrument(skip_all)]
+    async fn check(&self) -> AppResult<()> {
+        let res = record_dynamodb_call(
+            "DescribeTable",
+            self.users.table_name(),
+            self.users
+                .client()
+                .describe_table()
+                .table_name(self.users.table_name())
+                .send(),
+        )
+        .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(RepositoryError::from_sdk_error(e, self.users.table_name()).into()),
+        }
+    }
+}
+
+/// What is read from the email and username indexes, see get_user_by_index
+#[derive(Serialize, Deserialize)]
+struct UserId {
+    id: String,
+}
+
+/// Sets every attribute of the user, optional attributes that are None are removed
+/// e.g soft deleting a user with an image and a password only has a SET clause
+fn user_update_expression(updated: &User) -> UpdateExpression {
+    let mut update_expression = UpdateExpression::new()
+        .set("email", normalize_email(&updated.email))
+        .set("username", updated.username.clone())
+        .set("bio", updated.bio.clone())
+        .set("updated_at", updated.updated_at().to_string())
+        .set("version", updated.version)
+        .set("role", updated.role.as_str().to_string());
+
+    update_expression = match &updated.image {
+        Some(image) => update_expression.set("image", image.clone()),
+        None => update_expression.remove("image"),
+    };
+
+    update_expression = match updated.deleted_at() {
+        Some(deleted_at) => update_expression.set("deleted_at", deleted_at.to_string()),
+        None => update_expression.remove("deleted_at"),
+    };
+
+    update_expression = match updated.password_hash() {
+        Some(password_hash) => update_expression.set("password_hash", password_hash.to_string()),
+        None => update_expression.remove("password_hash"),
+    };
+
+    update_expression
+}
+
+const EMAIL_PREFIX: &str = "email#";
+const USERNAME_PREFIX: &str = "username#";
+
+/// Builds the item that reserves a unique value such as an email for the given user
+fn uniqueness_item(prefix: &str, value: &str, user_id: &str) -> DynamoItem {
+    DynamoItem::from([
+        ("id".to_string(), uniqueness_key(prefix, value)),
+        ("user_id".to_string(), user_id.to_string().into_av()),
+    ])
+}
+
+/// Values are lowercased so that uniqueness is case insensitive
+fn uniqueness_key(prefix: &str, value: &str) -> AttributeValue {
+    format!("{}{}", prefix, value.to_lowercase()).into_av()
+}
+
+fn is_uniqueness_key(id: &str) -> bool {
+    id.starts_with(EMAIL_PREFIX) || id.starts_with(USERNAME_PREFIX)
+}
+
+/// Extracts the per item cancellation codes when a transaction was cancelled
+fn cancellation_codes(error: &SdkError<TransactWriteItemsError>) -> Option<Vec<Option<String>>> {
+    match error {
+        SdkError::ServiceError(service_error) => match service_error.err() {
+            TransactWriteItemsError::TransactionCanceledException(exception) => Some(
+                exception
+                    .cancellation_reasons()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|reason| reason.code().map(|code| code.to_string()))
+                    .collect(),
+            ),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{domain::user::models::User, repositories::user_repository::user_update_expression};
+
+    #[test]
+    fn soft_deleting_a_user_with_an_image_only_sets_attributes() {
+        // Arrange
+        let mut user = User::new(
+            "ppId123".to_string(),
+            "pp@gmail.com".to_string(),
+            "pplogin".to_string(),
+            "I love to eat".to_string(),
+            Some("https://www.pexels.com/photo/1170986".to_string()),
+        )
+        .with_password_hash("hash".to_string());
+        user.mark_deleted();
+
+        // Act
+        let expression = user_update_expression(&user).build();
+
+        // Assert
+        assert!(expression.expression.starts_with("SET "));
+        assert!(expression.expression.contains("#deleted_at = "));
+        assert!(expression.expression.contains("#image = "));
+        assert!(!expression.expression.contains("REMOVE"));
+    }
+
+    #[test]
+    fn restoring_a_user_removes_the_deleted_at_attribute() {
+        // Arrange
+        let mut user = User::new(
+            "ppId123".to_string(),
+            "pp@gmail.com".to_string(),
+            "pplogin".to_string(),
+            "I love to eat".to_string(),
+            None,
+        )
+        .with_password_hash("hash".to_string());
+        user.mark_deleted();
+        user.restore();
+
+        // Act
+        let expression = user_update_expression(&user).build();
+
+        // Assert
+        assert!(expression.expression.ends_with(" REMOVE #image, #deleted_at"));
+    }
 }
@@ -0,0 +1,54 @@
+// The UserStore trait is what the service layer depends on instead of a concrete database
+// UserRepository implements it on top of DynamoDB and InMemoryUserRepository keeps everything in memory
+// so that services and controllers can be tested without any network access
+// Every implementation must behave the same way, including the conditional writes below
+
+use async_trait::async_trait;
+
+use crate::{
+    domain::user::models::User,
+    errors::AppResult,
+    utils::dynamodb_helpers::{DynamoItem, DynamoPage},
+};
+
+pub const USER_CONFLICT: &str = "User already exists";
+pub const EMAIL_CONFLICT: &str = "Email is already registered";
+pub const USERNAME_CONFLICT: &str = "Username is already taken";
+pub const VERSION_CONFLICT: &str =
+    "User has been modified by another request, fetch it again and retry";
+pub const DELETED_CONFLICT: &str = "User has already been deleted";
+
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    /// Soft deleted users are returned as well, it is up to the caller to hide them
+    async fn get_user_by_id(&self, id: String) -> AppResult<Option<User>>;
+
+    /// Same as get_user_by_id for several users at once
+    /// The result is in the same order as the ids, with None for the ids that do not exist
+    async fn get_users_by_ids(&self, ids: Vec<String>) -> AppResult<Vec<Option<User>>>;
+
+    async fn get_user_by_email(&self, email: String) -> AppResult<Option<User>>;
+
+    async fn get_user_by_username(&self, username: String) -> AppResult<Option<User>>;
+
+    /// Lists users that are not soft deleted, starting after `exclusive_start_key`
+    /// The key has the same shape as a DynamoDB LastEvaluatedKey e.g {"id": S("ppId123")}
+    async fn list_users(
+        &self,
+        limit: i32,
+        exclusive_start_key: Option<DynamoItem>,
+    ) -> AppResult<DynamoPage<User>>;
+
+    /// Fails with ObjectConflict if the id, email or username is already taken
+    /// Emails and usernames are compared case insensitively
+    async fn put_user(&self, user: &User) -> AppResult<()>;
+
+    /// Replaces `current` with `updated`
+    /// Fails with ObjectConflict if the stored version is no longer `current.version`
+    /// or if the new email or username is already taken by someone else
+    async fn update_user(&self, current: &User, updated: &User) -> AppResult<()>;
+
+    /// Permanently removes the user and releases its email and username
+    /// Fails with ObjectConflict if the user does not exist anymore
+    async fn delete_user(&self, user: &User) -> AppResult<()>;
+}
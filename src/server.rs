@@ -1,17 +1,28 @@
-use std::sync::Arc;
+use std::{
+    future::{Future, IntoFuture},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Context;
-use axum::Router;
-use tokio::net::TcpListener;
+use axum::{middleware, Router};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use tokio::{net::TcpListener, sync::watch};
 use tower::ServiceBuilder;
 use tower_http::cors::{self, CorsLayer};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
     config::AppConfig,
-    controllers::{health, user_controller},
+    controllers::{
+        api_key_controller, auth_controller, health, metrics, oidc_controller, user_controller,
+    },
+    errors,
     services::service_register::ServiceRegister,
-    utils::openapi_generator,
+    utils::{
+        self, openapi_generator,
+        tls::{ClientCertificateAcceptor, TlsPaths},
+    },
 };
 
 /// Server entry point where we register the services and start the server
@@ -25,35 +36,160 @@ pub async fn serve(config: Arc<AppConfig>) -> anyhow::Result<()> {
     );
 
     // Register Services to be used in handlers
-    let services = ServiceRegister::new(config.clone()).await;
+    let services = ServiceRegister::new(config.clone()).await?;
+    let health_service = services
+        .health_service
+        .clone()
+        .expect("HealthService is always registered");
+
+    // OIDC login is only available when a provider is configured
+    let oidc_router = match services.oidc_service {
+        Some(_) => oidc_controller::router(),
+        None => Router::new(),
+    };
 
     let app = Router::new()
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", openapi))
         .nest("/", health::router())
         .nest("/", user_controller::router())
+        .nest("/", auth_controller::router())
+        .nest("/", api_key_controller::router())
+        .merge(oidc_router)
+        .nest("/", metrics::router())
+        // route_layer runs after routing, which is what makes the matched route template available
+        .route_layer(middleware::from_fn(utils::metrics::track_metrics))
+        .route_layer(middleware::from_fn(utils::telemetry::trace_request))
+        // Requests that match no route never reach the route layers, they are recorded here instead
+        .layer(middleware::from_fn(utils::metrics::track_unmatched_metrics))
         .layer(
             // Use ServiceBuilder to apply multiple middleware
             // This will ensure that the middleware is applied in the order from top to bottom
             // Read https://docs.rs/axum/latest/axum/middleware/index.html#ordering for more info
-            ServiceBuilder::new().layer(
-                CorsLayer::new()
-                    // .allow_credentials(true)
-                    // .allow_methods([
-                    //     Method::GET,
-                    //     Method::POST,
-                    //     Method::OPTIONS,
-                    //     Method::DELETE,
-                    //     Method::PUT,
-                    // ])
-                    // .allow_headers([AUTHORIZATION, ACCEPT, COOKIE, CONTENT_TYPE]),
-                    .allow_origin(cors::Any), // In a real application, you should validate the `Origin` header.
-            ),
+            ServiceBuilder::new()
+                .layer(middleware::from_fn(utils::request_id::propagate_request_id))
+                .layer(middleware::from_fn(errors::negotiate_error_format))
+                .layer(
+                    CorsLayer::new()
+                        // .allow_credentials(true)
+                        // .allow_methods([
+                        //     Method::GET,
+                        //     Method::POST,
+                        //     Method::OPTIONS,
+                        //     Method::DELETE,
+                        //     Method::PUT,
+                        // ])
+                        // .allow_headers([AUTHORIZATION, ACCEPT, COOKIE, CONTENT_TYPE]),
+                        .allow_origin(cors::Any) // In a real application, you should validate the `Origin` header.
+                        // Lets browser clients read the request id to show it in error reports
+                        .expose_headers([utils::request_id::REQUEST_ID_HEADER.clone()]),
+                ),
         ).with_state(services); // Inject services into handlers as state
 
-    tracing::info!("Listening on port 3000");
-    let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    let listener = TcpListener::bind(&config.server_address)
+        .await
+        .with_context(|| format!("Failed to bind to {}", config.server_address))?;
+    let tls_paths = TlsPaths::from_config(&config)?;
+    tracing::info!(
+        "Listening on {}://{}",
+        if tls_paths.is_some() { "https" } else { "http" },
+        listener.local_addr()?
+    );
+
+    let readiness_delay = Duration::from_secs(config.shutdown_readiness_delay.unwrap_or(5));
+    let drain_timeout = Duration::from_secs(config.shutdown_drain_timeout.unwrap_or(30));
+
+    // Resolves once the signal has been received and the readiness delay has passed,
+    // at which point the server stops accepting connections and waits for in-flight requests
+    let shutdown = async move {
+        shutdown_signal().await;
+        tracing::info!("Shutting down, reporting unhealthy for {:?} before draining", readiness_delay);
+        health_service.mark_not_ready();
+        tokio::time::sleep(readiness_delay).await;
+        tracing::info!("No longer accepting connections, draining for up to {:?}", drain_timeout);
+    };
+
+    match tls_paths {
+        Some(tls_paths) => serve_tls(listener, app, tls_paths, shutdown, drain_timeout).await,
+        None => serve_http(listener, app, shutdown, drain_timeout).await,
+    }
+}
+
+async fn serve_http(
+    listener: TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    drain_timeout: Duration,
+) -> anyhow::Result<()> {
+    let (draining_tx, mut draining_rx) = watch::channel(false);
+    let shutdown = async move {
+        shutdown.await;
+        draining_tx.send_replace(true);
+    };
+
+    let server = axum::serve(listener, app.into_make_service()).with_graceful_shutdown(shutdown);
 
-    axum::serve(listener, app.into_make_service())
+    tokio::select! {
+        res = server.into_future() => res.context("Failed to start server"),
+        _ = async {
+            let _ = draining_rx.wait_for(|draining| *draining).await;
+            tokio::time::sleep(drain_timeout).await;
+        } => {
+            tracing::warn!("Drain timeout elapsed, exiting with requests still in flight");
+            Ok(())
+        }
+    }
+}
+
+async fn serve_tls(
+    listener: TcpListener,
+    app: Router,
+    tls_paths: TlsPaths,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    drain_timeout: Duration,
+) -> anyhow::Result<()> {
+    let rustls_config = RustlsConfig::from_config(Arc::new(tls_paths.load()?));
+    // Dropping the watcher stops the reloads, keep it around for as long as we serve
+    let _watcher = tls_paths.watch(rustls_config.clone())?;
+
+    // axum-server enforces the drain timeout itself
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown.await;
+            handle.graceful_shutdown(Some(drain_timeout));
+        }
+    });
+
+    axum_server::from_tcp(listener.into_std()?)
+        .acceptor(ClientCertificateAcceptor::new(rustls_config))
+        .handle(handle)
+        .serve(app.into_make_service())
         .await
         .context("Failed to start server")
 }
+
+/// Resolves on Ctrl+C, or SIGTERM which is what docker and kubernetes send to stop the container
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
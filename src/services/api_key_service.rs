@@ -0,0 +1,207 @@
+// The api key service mints and checks the long lived keys used by service to service callers
+// Keys are `<id>.<secret>` strings, the id is looked up and the SHA-256 of the secret compared,
+// the same way as refresh tokens, see auth_service.rs
+// Handlers should not use this directly, extract an AuthUser instead, see extractors/auth_user.rs
+
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::get_current_timestamp;
+use sha2::{Digest, Sha256};
+use tracing::{instrument, log::warn};
+
+use crate::{
+    domain::api_key::{
+        models::ApiKey,
+        view_models::{ApiKeyViewModel, CreateApiKeyViewModel, CreatedApiKeyViewModel},
+    },
+    errors::{AppError, AppResult},
+    repositories::api_key_store::ApiKeyStore,
+};
+
+use super::service_register::ServiceRegister;
+
+/// last_used_at is only written when it is older than this, to avoid a write on every request
+const LAST_USED_AT_PRECISION: u64 = 60;
+
+#[derive(Clone)]
+pub struct ApiKeyService {
+    api_key_repository: Arc<dyn ApiKeyStore>,
+}
+
+impl FromRef<ServiceRegister> for ApiKeyService {
+    fn from_ref(state: &ServiceRegister) -> Self {
+        state.api_key_service.clone().unwrap()
+    }
+}
+
+impl ApiKeyService {
+    pub fn new(api_key_repository: impl ApiKeyStore + 'static) -> Self {
+        Self {
+            api_key_repository: Arc::new(api_key_repository),
+        }
+    }
+
+    /// Mints a new key, the returned key is the only time the secret is ever seen
+    #[instrument(skip_all)]
+    pub async fn create_api_key(
+        &self,
+        request: CreateApiKeyViewModel,
+        created_by: String,
+    ) -> AppResult<CreatedApiKeyViewModel> {
+        if request.scopes.is_empty() {
+            return Err(AppError::BadRequest(
+                "At least one scope is required".to_string(),
+            ));
+        }
+
+        let secret = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        let api_key = ApiKey {
+            id: format!("sk_{}", hex(&rand::random::<[u8; 6]>())),
+            name: request.name,
+            key_hash: hash_secret(&secret),
+            scopes: request.scopes,
+            created_by,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            expires_at: request
+                .expires_in
+                .map(|expires_in| get_current_timestamp() + expires_in),
+            last_used_at: None,
+            revoked_at: None,
+        };
+        self.api_key_repository.put_api_key(&api_key).await?;
+
+        Ok(CreatedApiKeyViewModel {
+            key: format!("{}.{}", api_key.id, secret),
+            api_key: ApiKeyViewModel::from(api_key),
+        })
+    }
+
+    #[instrument(skip_all)]
+    pub async fn list_api_keys(&self) -> AppResult<Vec<ApiKeyViewModel>> {
+        let api_keys = self.api_key_repository.list_api_keys().await?;
+
+        Ok(api_keys.into_iter().map(ApiKeyViewModel::from).collect())
+    }
+
+    /// Revoked keys are rejected from the next request on, revoking a key twice does nothing
+    #[instrument(skip_all)]
+    pub async fn revoke_api_key(&self, id: String) -> AppResult<ApiKeyViewModel> {
+        self.api_key_repository
+            .revoke_api_key(id.clone(), chrono::Utc::now().to_rfc3339())
+            .await?;
+
+        let api_key = self.api_key_repository.get_api_key(id).await?;
+
+        api_key
+            .map(ApiKeyViewModel::from)
+            .ok_or_else(|| AppError::NotFound("Api key not found".to_string()))
+    }
+
+    /// Checks the key sent in the X-Api-Key header
+    /// Unknown, revoked and expired keys are all rejected with Unauthorized
+    #[instrument(skip_all)]
+    pub async fn authenticate(&self, key: &str) -> AppResult<ApiKey> {
+        let (id, secret) = key.split_once('.').ok_or(AppError::Unauthorized)?;
+
+        let api_key = self
+            .api_key_repository
+            .get_api_key(id.to_string())
+            .await?
+            .ok_or(AppError::Unauthorized)?;
+
+        let now = get_current_timestamp();
+        if hash_secret(secret) != api_key.key_hash || api_key.is_revoked() || api_key.is_expired(now) {
+            return Err(AppError::Unauthorized);
+        }
+
+        let stale = match api_key.last_used_at {
+            Some(last_used_at) => last_used_at + LAST_USED_AT_PRECISION <= now,
+            None => true,
+        };
+        if stale {
+            // Failing to record the usage should not fail the request
+            if let Err(e) = self
+                .api_key_repository
+                .record_api_key_usage(api_key.id.clone(), now)
+                .await
+            {
+                warn!("Unable to record usage of api key {}: {}", api_key.id, e);
+            }
+        }
+
+        Ok(api_key)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hash_secret(secret: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(secret.as_bytes()))
+}
+
+// Like UserService, these tests run against the in memory repositories
+#[cfg(test)]
+mod test {
+    use crate::{
+        domain::{api_key::view_models::CreateApiKeyViewModel, auth::models::Permission},
+        errors::AppError,
+        repositories::in_memory_api_key_repository::InMemoryApiKeyRepository,
+        services::api_key_service::ApiKeyService,
+    };
+
+    fn create_request(expires_in: Option<u64>) -> CreateApiKeyViewModel {
+        CreateApiKeyViewModel {
+            name: "nightly-export".to_string(),
+            scopes: vec![Permission::UsersRead],
+            expires_in,
+        }
+    }
+
+    #[tokio::test]
+    async fn authenticate_accepts_minted_key_until_revoked() {
+        // Arrange
+        let api_key_service = ApiKeyService::new(InMemoryApiKeyRepository::new());
+        let created = api_key_service
+            .create_api_key(create_request(None), "ppId123".to_string())
+            .await
+            .unwrap();
+
+        // Act
+        let authenticated = api_key_service.authenticate(&created.key).await.unwrap();
+        let wrong_secret = api_key_service
+            .authenticate(&format!("{}.not-the-secret", created.api_key.id))
+            .await;
+        let revoked = api_key_service
+            .revoke_api_key(created.api_key.id.clone())
+            .await
+            .unwrap();
+        let after_revoke = api_key_service.authenticate(&created.key).await;
+
+        // Assert
+        assert_eq!(authenticated.scopes, vec![Permission::UsersRead]);
+        assert!(matches!(wrong_secret, Err(AppError::Unauthorized)));
+        assert!(revoked.revoked_at.is_some());
+        assert!(matches!(after_revoke, Err(AppError::Unauthorized)));
+    }
+
+    #[tokio::test]
+    async fn authenticate_rejects_expired_key() {
+        // Arrange
+        let api_key_service = ApiKeyService::new(InMemoryApiKeyRepository::new());
+        let created = api_key_service
+            .create_api_key(create_request(Some(0)), "ppId123".to_string())
+            .await
+            .unwrap();
+
+        // Act
+        let res = api_key_service.authenticate(&created.key).await;
+
+        // Assert
+        assert!(matches!(res, Err(AppError::Unauthorized)));
+    }
+}
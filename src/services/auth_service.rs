@@ -0,0 +1,321 @@
+// The auth service trades credentials for token pairs
+// Access tokens are short lived JWTs issued by the TokenService
+// Refresh tokens are opaque `<family id>.<secret>` strings, only the SHA-256 of the secret is stored
+// Every refresh rotates the secret, presenting a secret that has already been rotated out means
+// that the token has leaked, in which case the whole family is revoked and the user has to log in again
+
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::get_current_timestamp;
+use sha2::{Digest, Sha256};
+use tracing::{instrument, log::warn};
+
+use crate::{
+    domain::auth::{
+        models::RefreshTokenFamily,
+        view_models::{LoginViewModel, TokenViewModel},
+    },
+    domain::user::models::Role,
+    errors::{AppError, AppResult},
+    repositories::{refresh_token_store::RefreshTokenStore, user_store::UserStore},
+    utils::password::verify_password,
+};
+
+use super::{service_register::ServiceRegister, token_service::TokenService};
+
+#[derive(Clone)]
+pub struct AuthService {
+    user_repository: Arc<dyn UserStore>,
+    refresh_token_repository: Arc<dyn RefreshTokenStore>,
+    token_service: TokenService,
+    refresh_token_ttl: u64,
+}
+
+impl FromRef<ServiceRegister> for AuthService {
+    fn from_ref(state: &ServiceRegister) -> Self {
+        state.auth_service.clone().unwrap()
+    }
+}
+
+impl AuthService {
+    pub const DEFAULT_REFRESH_TOKEN_TTL: u64 = 30 * 24 * 60 * 60;
+
+    pub fn new(
+        user_repository: impl UserStore + 'static,
+        refresh_token_repository: impl RefreshTokenStore + 'static,
+        token_service: TokenService,
+    ) -> Self {
+        Self {
+            user_repository: Arc::new(user_repository),
+            refresh_token_repository: Arc::new(refresh_token_repository),
+            token_service,
+            refresh_token_ttl: Self::DEFAULT_REFRESH_TOKEN_TTL,
+        }
+    }
+
+    /// How long a refresh token family can be refreshed for, in seconds, counted from login
+    pub fn with_refresh_token_ttl(mut self, refresh_token_ttl: u64) -> Self {
+        self.refresh_token_ttl = refresh_token_ttl;
+        self
+    }
+
+    /// Checks the email and password and starts a new refresh token family
+    /// Unknown emails, deleted users and wrong passwords are all reported the same way
+    #[instrument(skip_all)]
+    pub async fn login(&self, request: LoginViewModel) -> AppResult<TokenViewModel> {
+        let user = self
+            .user_repository
+            .get_user_by_email(request.email)
+            .await?
+            .filter(|user| !user.is_deleted());
+
+        let password_hash = user
+            .as_ref()
+            .and_then(|user| user.password_hash())
+            .map(|password_hash| password_hash.to_string());
+        if !verify_password(request.password, password_hash).await? {
+            return Err(AppError::Unauthorized);
+        }
+        let user = user.ok_or(AppError::Unauthorized)?;
+
+        self.issue_tokens(user.id, user.role).await
+    }
+
+    /// Starts a new refresh token family for a user that has already been authenticated
+    /// e.g through an OIDC provider, see oidc_service.rs
+    #[instrument(skip_all)]
+    pub async fn issue_tokens(&self, user_id: String, role: Role) -> AppResult<TokenViewModel> {
+        let secret = new_secret();
+        let family = RefreshTokenFamily {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id,
+            token_hash: hash_secret(&secret),
+            expires_at: get_current_timestamp() + self.refresh_token_ttl,
+            revoked_at: None,
+        };
+        self.refresh_token_repository.put_family(&family).await?;
+
+        self.token_pair(&family, role, &secret)
+    }
+
+    /// Trades a refresh token for a new token pair, the given refresh token can not be used again
+    #[instrument(skip_all)]
+    pub async fn refresh(&self, refresh_token: String) -> AppResult<TokenViewModel> {
+        let (family, secret) = self.find_family(&refresh_token).await?;
+
+        if family.is_revoked() || family.is_expired(get_current_timestamp()) {
+            return Err(AppError::Unauthorized);
+        }
+
+        if hash_secret(&secret) != family.token_hash {
+            warn!("Refresh token reuse detected, revoking family {}", family.id);
+            self.refresh_token_repository
+                .revoke_family(family.id)
+                .await?;
+            return Err(AppError::Unauthorized);
+        }
+
+        // Deleted users keep their families around but can no longer refresh them
+        let user = self
+            .user_repository
+            .get_user_by_id(family.user_id.clone())
+            .await?;
+        let user = match user {
+            Some(user) if !user.is_deleted() => user,
+            _ => return Err(AppError::Unauthorized),
+        };
+
+        let next_secret = new_secret();
+        match self
+            .refresh_token_repository
+            .rotate_token(&family, &hash_secret(&next_secret))
+            .await
+        {
+            Ok(()) => {}
+            // Someone else rotated the same token in between our read and write, treat it as reuse
+            Err(AppError::ObjectConflict(_)) => {
+                warn!("Refresh token reuse detected, revoking family {}", family.id);
+                self.refresh_token_repository
+                    .revoke_family(family.id)
+                    .await?;
+                return Err(AppError::Unauthorized);
+            }
+            Err(e) => return Err(e),
+        }
+
+        // The role is read again so that role changes apply from the next refresh
+        self.token_pair(&family, user.role, &next_secret)
+    }
+
+    /// Revokes the refresh token family, access tokens that were already issued stay valid until they expire
+    /// Only the current refresh token of the family can revoke it, knowing the family id is not enough
+    #[instrument(skip_all)]
+    pub async fn logout(&self, refresh_token: String) -> AppResult<()> {
+        let (family, secret) = self.find_family(&refresh_token).await?;
+
+        if hash_secret(&secret) != family.token_hash {
+            return Err(AppError::Unauthorized);
+        }
+
+        self.refresh_token_repository.revoke_family(family.id).await
+    }
+
+    async fn find_family(&self, refresh_token: &str) -> AppResult<(RefreshTokenFamily, String)> {
+        let (family_id, secret) = refresh_token
+            .split_once('.')
+            .ok_or(AppError::Unauthorized)?;
+
+        let family = self
+            .refresh_token_repository
+            .get_family(family_id.to_string())
+            .await?
+            .ok_or(AppError::Unauthorized)?;
+
+        Ok((family, secret.to_string()))
+    }
+
+    fn token_pair(
+        &self,
+        family: &RefreshTokenFamily,
+        role: Role,
+        secret: &str,
+    ) -> AppResult<TokenViewModel> {
+        Ok(TokenViewModel {
+            access_token: self.token_service.issue_access_token(&family.user_id, role)?,
+            token_type: "Bearer".to_string(),
+            expires_in: self.token_service.access_token_ttl(),
+            refresh_token: format!("{}.{}", family.id, secret),
+        })
+    }
+}
+
+fn new_secret() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+/// The secrets are random so a plain SHA-256 is enough, no need for a password hash here
+fn hash_secret(secret: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(secret.as_bytes()))
+}
+
+// Like UserService, these tests run against the in memory repositories
+#[cfg(test)]
+mod test {
+    use crate::{
+        domain::{auth::view_models::LoginViewModel, user::models::User},
+        errors::AppError,
+        repositories::{
+            in_memory_refresh_token_repository::InMemoryRefreshTokenRepository,
+            in_memory_user_repository::InMemoryUserRepository, user_store::UserStore,
+        },
+        services::{auth_service::AuthService, token_service::TokenService},
+        utils::password::hash_password,
+    };
+
+    async fn get_auth_service() -> AuthService {
+        let user_repository = InMemoryUserRepository::new();
+        user_repository
+            .put_user(
+                &User::new(
+                    "ppId123".to_string(),
+                    "pp@gmail.com".to_string(),
+                    "pplogin".to_string(),
+                    "I love to eat".to_string(),
+                    None,
+                )
+                .with_password_hash(hash_password("password123".to_string()).await.unwrap()),
+            )
+            .await
+            .unwrap();
+
+        AuthService::new(
+            user_repository,
+            InMemoryRefreshTokenRepository::new(),
+            TokenService::new(Some(b"secret"), None),
+        )
+    }
+
+    fn login(password: &str) -> LoginViewModel {
+        LoginViewModel {
+            email: "pp@gmail.com".to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn login_checks_password() {
+        // Arrange
+        let auth_service = get_auth_service().await;
+
+        // Act
+        let tokens = auth_service.login(login("password123")).await.unwrap();
+        let wrong_password = auth_service.login(login("password321")).await;
+        let other_case = auth_service
+            .login(LoginViewModel {
+                email: "PP@Gmail.com".to_string(),
+                password: "password123".to_string(),
+            })
+            .await;
+
+        // Assert
+        assert_eq!(tokens.token_type, "Bearer");
+        assert!(other_case.is_ok());
+        assert!(matches!(wrong_password, Err(AppError::Unauthorized)));
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_token_and_revokes_family_on_reuse() {
+        // Arrange
+        let auth_service = get_auth_service().await;
+        let tokens = auth_service.login(login("password123")).await.unwrap();
+
+        // Act
+        let rotated = auth_service
+            .refresh(tokens.refresh_token.clone())
+            .await
+            .unwrap();
+        let reused = auth_service.refresh(tokens.refresh_token.clone()).await;
+        let after_reuse = auth_service.refresh(rotated.refresh_token.clone()).await;
+
+        // Assert
+        assert_ne!(rotated.refresh_token, tokens.refresh_token);
+        assert!(matches!(reused, Err(AppError::Unauthorized)));
+        assert!(matches!(after_reuse, Err(AppError::Unauthorized)));
+    }
+
+    #[tokio::test]
+    async fn logout_revokes_family() {
+        // Arrange
+        let auth_service = get_auth_service().await;
+        let tokens = auth_service.login(login("password123")).await.unwrap();
+
+        // Act
+        auth_service
+            .logout(tokens.refresh_token.clone())
+            .await
+            .unwrap();
+        let refreshed = auth_service.refresh(tokens.refresh_token).await;
+
+        // Assert
+        assert!(matches!(refreshed, Err(AppError::Unauthorized)));
+    }
+
+    #[tokio::test]
+    async fn logout_checks_the_secret() {
+        // Arrange
+        let auth_service = get_auth_service().await;
+        let tokens = auth_service.login(login("password123")).await.unwrap();
+        let (family_id, _) = tokens.refresh_token.split_once('.').unwrap();
+
+        // Act
+        let forged = auth_service.logout(format!("{}.forged", family_id)).await;
+        let refreshed = auth_service.refresh(tokens.refresh_token).await;
+
+        // Assert
+        assert!(matches!(forged, Err(AppError::Unauthorized)));
+        assert!(refreshed.is_ok());
+    }
+}
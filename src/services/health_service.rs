@@ -0,0 +1,263 @@
+// The health service tracks whether this instance should receive traffic
+// Readiness is flipped off on shutdown before we stop accepting connections,
+// giving the load balancer time to notice and route new requests elsewhere, see server.rs
+// Otherwise the instance is ready when every critical dependency check passes
+// Checks run concurrently, each with a timeout, and their results are cached so that
+// frequent probes from several load balancers do not turn into a flood of calls to our dependencies
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::extract::FromRef;
+use futures::future::join_all;
+use tokio::sync::Mutex;
+use tracing::log::warn;
+
+use crate::{
+    domain::health::view_models::{
+        ComponentHealthViewModel, ComponentStatus, HealthStatus, HealthViewModel,
+    },
+    repositories::health_check::HealthCheck,
+};
+
+use super::service_register::ServiceRegister;
+
+/// Check results along with when they were taken
+type CachedComponents = Option<(Instant, Vec<ComponentHealthViewModel>)>;
+
+#[derive(Clone)]
+pub struct HealthService {
+    ready: Arc<AtomicBool>,
+    checks: Vec<Arc<dyn HealthCheck>>,
+    check_timeout: Duration,
+    cache_ttl: Duration,
+    // Also serializes the checks, concurrent probes wait for the running checks instead of starting their own
+    cache: Arc<Mutex<CachedComponents>>,
+}
+
+impl FromRef<ServiceRegister> for HealthService {
+    fn from_ref(state: &ServiceRegister) -> Self {
+        state.health_service.clone().unwrap()
+    }
+}
+
+impl Default for HealthService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthService {
+    pub const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+    pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(5);
+
+    pub fn new() -> Self {
+        Self {
+            ready: Arc::new(AtomicBool::new(true)),
+            checks: Vec::new(),
+            check_timeout: Self::DEFAULT_CHECK_TIMEOUT,
+            cache_ttl: Self::DEFAULT_CACHE_TTL,
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Registers a dependency check to run on readiness probes
+    pub fn with_check(mut self, check: Arc<dyn HealthCheck>) -> Self {
+        self.checks.push(check);
+        self
+    }
+
+    /// How long a single check may take before it is reported as timed out
+    pub fn with_check_timeout(mut self, check_timeout: Duration) -> Self {
+        self.check_timeout = check_timeout;
+        self
+    }
+
+    /// How long check results are reused for
+    pub fn with_cache_ttl(mut self, cache_ttl: Duration) -> Self {
+        self.cache_ttl = cache_ttl;
+        self
+    }
+
+    /// False once the server has started shutting down
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Makes the readiness check fail from now on, shared by every clone of the service
+    pub fn mark_not_ready(&self) {
+        self.ready.store(false, Ordering::SeqCst);
+    }
+
+    /// Runs the dependency checks, or reuses their cached results, and sums them up
+    pub async fn readiness(&self) -> HealthViewModel {
+        if !self.is_ready() {
+            return HealthViewModel {
+                status: HealthStatus::Unavailable,
+                components: Vec::new(),
+            };
+        }
+
+        let components = self.components().await;
+        let failing = |critical: bool| {
+            components
+                .iter()
+                .any(|component| component.critical == critical && component.status != ComponentStatus::Ok)
+        };
+        let status = if failing(true) {
+            HealthStatus::Unavailable
+        } else if failing(false) {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Ok
+        };
+
+        HealthViewModel { status, components }
+    }
+
+    async fn components(&self) -> Vec<ComponentHealthViewModel> {
+        let mut cache = self.cache.lock().await;
+        if let Some((checked_at, components)) = cache.as_ref() {
+            if checked_at.elapsed() < self.cache_ttl {
+                return components.clone();
+            }
+        }
+
+        let components = join_all(self.checks.iter().map(|check| self.run_check(check.as_ref()))).await;
+        *cache = Some((Instant::now(), components.clone()));
+
+        components
+    }
+
+    async fn run_check(&self, check: &dyn HealthCheck) -> ComponentHealthViewModel {
+        let name = check.name();
+        let started_at = Instant::now();
+
+        let status = match tokio::time::timeout(self.check_timeout, check.check()).await {
+            Ok(Ok(())) => ComponentStatus::Ok,
+            Ok(Err(e)) => {
+                warn!("Health check {} failed: {}", name, e);
+                ComponentStatus::Failing
+            }
+            Err(_) => {
+                warn!("Health check {} timed out after {:?}", name, self.check_timeout);
+                ComponentStatus::TimedOut
+            }
+        };
+
+        ComponentHealthViewModel {
+            name,
+            status,
+            critical: check.critical(),
+            latency_ms: started_at.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use crate::{
+        domain::health::view_models::{ComponentStatus, HealthStatus},
+        errors::{AppError, AppResult},
+        repositories::health_check::HealthCheck,
+        services::health_service::HealthService,
+    };
+
+    /// A check that takes `delay` and then passes or fails, counting how often it ran
+    pub struct StubCheck {
+        pub name: &'static str,
+        pub critical: bool,
+        pub healthy: bool,
+        pub delay: Duration,
+        pub calls: Arc<AtomicUsize>,
+    }
+
+    impl StubCheck {
+        pub fn new(name: &'static str, critical: bool, healthy: bool) -> Self {
+            Self {
+                name,
+                critical,
+                healthy,
+                delay: Duration::ZERO,
+                calls: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl HealthCheck for StubCheck {
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn critical(&self) -> bool {
+            self.critical
+        }
+
+        async fn check(&self) -> AppResult<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+
+            if self.healthy {
+                Ok(())
+            } else {
+                Err(AppError::InternalServerError)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn readiness_times_out_slow_checks() {
+        // Arrange
+        let slow = StubCheck {
+            delay: Duration::from_secs(5),
+            ..StubCheck::new("slow", true, true)
+        };
+        let health_service = HealthService::new()
+            .with_check(Arc::new(StubCheck::new("fast", true, true)))
+            .with_check(Arc::new(slow))
+            .with_check_timeout(Duration::from_millis(50));
+
+        // Act
+        let health = health_service.readiness().await;
+
+        // Assert
+        assert_eq!(health.status, HealthStatus::Unavailable);
+        assert_eq!(health.components[0].status, ComponentStatus::Ok);
+        assert_eq!(health.components[1].status, ComponentStatus::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn readiness_caches_check_results() {
+        // Arrange
+        let check = StubCheck::new("flaky", false, false);
+        let calls = check.calls.clone();
+        let health_service = HealthService::new()
+            .with_check(Arc::new(check))
+            .with_cache_ttl(Duration::from_secs(60));
+
+        // Act
+        let first = health_service.readiness().await;
+        let second = health_service.readiness().await;
+
+        // Assert
+        assert_eq!(first.status, HealthStatus::Degraded);
+        assert_eq!(second, first);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
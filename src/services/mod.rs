@@ -1,2 +1,7 @@
+pub mod api_key_service;
+pub mod auth_service;
+pub mod health_service;
+pub mod oidc_service;
 pub mod service_register;
-pub mod user_service;
\ No newline at end of file
+pub mod token_service;
+pub mod user_service;
@@ -0,0 +1,349 @@
+// The OIDC service signs users in through an external OpenID Connect provider
+// using the authorization code flow with PKCE, see https://openid.net/specs/openid-connect-core-1_0.html
+// 1. authorization_url() sends the browser to the provider along with a state cookie
+//    holding the state, nonce and PKCE verifier, signed so that it cannot be tampered with
+// 2. The provider sends the browser back to the redirect uri, complete_login() checks the state,
+//    trades the code for an ID token, validates it and signs the user in by its verified email
+// The provider endpoints are read from its discovery document on first use
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::FromRef;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{
+    decode, decode_header, get_current_timestamp, jwk::JwkSet, Algorithm, DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use tracing::{instrument, log::debug};
+
+use crate::{
+    config::AppConfig,
+    domain::auth::view_models::TokenViewModel,
+    errors::{AppError, AppResult},
+    utils::signer::Signer,
+};
+
+use super::{auth_service::AuthService, service_register::ServiceRegister, user_service::UserService};
+
+pub const OIDC_STATE_COOKIE: &str = "oidc_state";
+
+/// How long the user has to complete the login at the provider, in seconds
+const STATE_TTL: u64 = 10 * 60;
+
+/// The part of the discovery document we need
+/// See https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: Option<String>,
+}
+
+/// Kept in the signed state cookie between the login redirect and the callback
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginState {
+    state: String,
+    nonce: String,
+    code_verifier: String,
+    exp: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    nonce: Option<String>,
+    preferred_username: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct OidcService {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    state_signer: Signer,
+    http_client: reqwest::Client,
+    metadata: Arc<OnceCell<ProviderMetadata>>,
+    user_service: UserService,
+    auth_service: AuthService,
+}
+
+impl FromRef<ServiceRegister> for OidcService {
+    fn from_ref(state: &ServiceRegister) -> Self {
+        state.oidc_service.clone().unwrap()
+    }
+}
+
+impl OidcService {
+    pub fn new(
+        issuer: String,
+        client_id: String,
+        client_secret: Option<String>,
+        redirect_uri: String,
+        user_service: UserService,
+        auth_service: AuthService,
+    ) -> Self {
+        // The client secret is shared by every instance so it doubles as the key of the state cookie
+        // Public clients have no secret, their state cookie only survives as long as the instance
+        let state_signer = match &client_secret {
+            Some(client_secret) => Signer::new(client_secret.as_bytes()),
+            None => Signer::new(rand::random::<[u8; 32]>()),
+        };
+
+        Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret,
+            redirect_uri,
+            state_signer,
+            http_client: reqwest::Client::new(),
+            metadata: Arc::new(OnceCell::new()),
+            user_service,
+            auth_service,
+        }
+    }
+
+    /// Returns None when OIDC_ISSUER is not set, in which case OIDC login is disabled
+    pub fn from_config(
+        app_config: &AppConfig,
+        user_service: UserService,
+        auth_service: AuthService,
+    ) -> anyhow::Result<Option<Self>> {
+        let Some(issuer) = app_config.oidc_issuer.clone() else {
+            return Ok(None);
+        };
+        let client_id = app_config
+            .oidc_client_id
+            .clone()
+            .context("OIDC_CLIENT_ID is required when OIDC_ISSUER is set")?;
+        let redirect_uri = app_config
+            .oidc_redirect_uri
+            .clone()
+            .context("OIDC_REDIRECT_URI is required when OIDC_ISSUER is set")?;
+
+        if app_config.oidc_client_secret.is_none() {
+            tracing::warn!("OIDC_CLIENT_SECRET is not set, OIDC logins started on one instance can not complete on another");
+        }
+
+        Ok(Some(Self::new(
+            issuer,
+            client_id,
+            app_config.oidc_client_secret.clone(),
+            redirect_uri,
+            user_service,
+            auth_service,
+        )))
+    }
+
+    /// Builds the provider url to send the browser to and the value of the state cookie to set
+    #[instrument(skip_all)]
+    pub async fn authorization_url(&self) -> AppResult<(String, String)> {
+        let metadata = self.metadata().await?;
+        let login_state = LoginState {
+            state: random_string(),
+            nonce: random_string(),
+            code_verifier: random_string(),
+            exp: get_current_timestamp() + STATE_TTL,
+        };
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(login_state.code_verifier.as_bytes()));
+
+        let url = reqwest::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("scope", "openid email profile"),
+                ("state", &login_state.state),
+                ("nonce", &login_state.nonce),
+                ("code_challenge", &code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid authorization endpoint: {}", e))?;
+
+        Ok((url.to_string(), self.state_signer.sign(&login_state)))
+    }
+
+    /// Completes the login with the code and state the provider sent back
+    /// `state_cookie` is the value of the cookie set by authorization_url
+    #[instrument(skip_all)]
+    pub async fn complete_login(
+        &self,
+        code: String,
+        state: String,
+        state_cookie: Option<&str>,
+    ) -> AppResult<TokenViewModel> {
+        let login_state = state_cookie
+            .and_then(|cookie| self.state_signer.verify::<LoginState>(cookie))
+            .ok_or(AppError::Unauthorized)?;
+        if login_state.state != state || login_state.exp <= get_current_timestamp() {
+            return Err(AppError::Unauthorized);
+        }
+
+        let id_token = self.exchange_code(code, &login_state.code_verifier).await?;
+        let claims = self.validate_id_token(&id_token, &login_state.nonce).await?;
+
+        // Only a verified email proves that the user owns the account we are linking to
+        let email = match claims.email {
+            Some(email) if claims.email_verified => email,
+            _ => {
+                return Err(AppError::BadRequest(
+                    "The identity provider did not return a verified email".to_string(),
+                ))
+            }
+        };
+
+        let user = self
+            .user_service
+            .link_or_create_user(email, claims.preferred_username)
+            .await
+            .map_err(|e| match e {
+                // The user was soft deleted
+                AppError::NotFound(_) => AppError::Unauthorized,
+                e => e,
+            })?;
+
+        self.auth_service.issue_tokens(user.id, user.role).await
+    }
+
+    async fn metadata(&self) -> AppResult<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer);
+                let metadata: ProviderMetadata = self.get_json(&url).await?;
+
+                if metadata.issuer.trim_end_matches('/') != self.issuer {
+                    return Err(anyhow::anyhow!(
+                        "Discovery document of {} is for issuer {}",
+                        self.issuer,
+                        metadata.issuer
+                    )
+                    .into());
+                }
+
+                Ok(metadata)
+            })
+            .await
+    }
+
+    async fn exchange_code(&self, code: String, code_verifier: &str) -> AppResult<String> {
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let res = self
+            .http_client
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Error while calling the token endpoint: {}", e))?;
+
+        // An invalid or reused code is the caller's fault rather than ours
+        if res.status().is_client_error() {
+            debug!("Token endpoint rejected the code: {}", res.status());
+            return Err(AppError::Unauthorized);
+        }
+
+        let res: TokenResponse = res
+            .error_for_status()
+            .map_err(|e| anyhow::anyhow!("Error while calling the token endpoint: {}", e))?
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Invalid token endpoint response: {}", e))?;
+
+        Ok(res.id_token)
+    }
+
+    /// Checks the signature, iss, aud, exp and nonce of the ID token
+    /// HS256 tokens are signed with the client secret, anything else with a key from the provider JWKS
+    async fn validate_id_token(&self, id_token: &str, nonce: &str) -> AppResult<IdTokenClaims> {
+        let header = decode_header(id_token).map_err(unauthorized)?;
+
+        let key = match header.alg {
+            Algorithm::HS256 => {
+                let client_secret = self.client_secret.as_ref().ok_or(AppError::Unauthorized)?;
+                DecodingKey::from_secret(client_secret.as_bytes())
+            }
+            _ => {
+                let jwks_uri = self
+                    .metadata()
+                    .await?
+                    .jwks_uri
+                    .as_ref()
+                    .ok_or(AppError::Unauthorized)?;
+                // Fetched every time so that rotated keys are picked up, logins are rare enough
+                let jwks: JwkSet = self.get_json(jwks_uri).await?;
+                let jwk = match &header.kid {
+                    Some(kid) => jwks.find(kid),
+                    None if jwks.keys.len() == 1 => jwks.keys.first(),
+                    None => None,
+                }
+                .ok_or(AppError::Unauthorized)?;
+
+                DecodingKey::from_jwk(jwk).map_err(unauthorized)?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer, &format!("{}/", self.issuer)]);
+        validation.set_audience(&[&self.client_id]);
+        validation.required_spec_claims =
+            HashSet::from(["exp", "iss", "aud", "sub"].map(str::to_string));
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(unauthorized)?
+            .claims;
+
+        // The nonce ties the ID token to the login that we started
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AppError::Unauthorized);
+        }
+
+        Ok(claims)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> AppResult<T> {
+        self.http_client
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| anyhow::anyhow!("Error while calling {}: {}", url, e))?
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Invalid response from {}: {}", url, e).into())
+    }
+}
+
+fn random_string() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+fn unauthorized(e: jsonwebtoken::errors::Error) -> AppError {
+    debug!("Rejected ID token: {}", e);
+    AppError::Unauthorized
+}
@@ -1,10 +1,29 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use anyhow::Context;
 use aws_config::{meta::region::RegionProviderChain, retry::RetryConfigBuilder, SdkConfig};
 
-use crate::{config::AppConfig, repositories::user_repository::UserRepository};
+use crate::{
+    config::AppConfig,
+    repositories::{
+        api_key_repository::ApiKeyRepository, api_key_store::ApiKeyStore, health_check::HealthCheck,
+        refresh_token_repository::RefreshTokenRepository, refresh_token_store::RefreshTokenStore,
+        user_repository::UserRepository, user_store::UserStore,
+    },
+    utils::dynamodb_helpers::PageCursorCodec,
+};
 
-use super::user_service::UserService;
+#[cfg(feature = "sql")]
+use crate::repositories::{
+    sql_api_key_repository::SqlApiKeyRepository,
+    sql_refresh_token_repository::SqlRefreshTokenRepository,
+    sql_user_repository::SqlUserRepository,
+};
+
+use super::{
+    api_key_service::ApiKeyService, auth_service::AuthService, health_service::HealthService,
+    oidc_service::OidcService, token_service::TokenService, user_service::UserService,
+};
 
 // We will be implementing a substate for each router therefore we need to implement FromRef
 // See https://docs.rs/axum/latest/axum/extract/struct.State.html#substates
@@ -17,21 +36,140 @@ pub struct ServiceRegister {
     // See https://docs.rs/axum/latest/axum/#sharing-state-with-handlers
     // In this case we are using State for compile time type safety
     pub user_service: Option<UserService>,
+    pub token_service: Option<TokenService>,
+    pub auth_service: Option<AuthService>,
+    pub api_key_service: Option<ApiKeyService>,
+    // Only set when OIDC_ISSUER is set, the OIDC routes are not mounted otherwise
+    pub oidc_service: Option<OidcService>,
+    pub health_service: Option<HealthService>,
 }
 
 // Common place to instantiate all our services
 impl ServiceRegister {
-    pub async fn new(app_config: Arc<AppConfig>) -> Self {
+    /// Fails when the config is invalid or the database cannot be reached
+    pub async fn new(app_config: Arc<AppConfig>) -> anyhow::Result<Self> {
+        match app_config.storage.as_deref().unwrap_or("dynamodb") {
+            "dynamodb" => Self::with_dynamodb(app_config).await,
+            "sql" => Self::with_sql(app_config).await,
+            storage => anyhow::bail!("Unknown STORAGE {}, expected dynamodb or sql", storage),
+        }
+    }
+
+    async fn with_dynamodb(app_config: Arc<AppConfig>) -> anyhow::Result<Self> {
         // Setup AWS Related Config
-        let shared_config = get_aws_shared_config(app_config).await;
+        let shared_config = get_aws_shared_config(app_config.clone()).await;
+
+        // Setup UserRepository
+        let user_repository = UserRepository::new(&shared_config, None)
+            .await
+            .with_index_names(
+                app_config.user_email_index.clone(),
+                app_config.user_username_index.clone(),
+            );
+
+        // Setup RefreshTokenRepository
+        let refresh_token_repository = RefreshTokenRepository::new(&shared_config).await;
+
+        // Setup ApiKeyRepository
+        let api_key_repository = ApiKeyRepository::new(&shared_config).await;
+
+        Self::with_stores(
+            app_config,
+            user_repository.clone(),
+            refresh_token_repository,
+            api_key_repository,
+            vec![Arc::new(user_repository)],
+        )
+    }
+
+    #[cfg(feature = "sql")]
+    async fn with_sql(app_config: Arc<AppConfig>) -> anyhow::Result<Self> {
+        let database_url = app_config
+            .database_url
+            .clone()
+            .context("DATABASE_URL is required when STORAGE is sql")?;
+
+        // Setup SqlUserRepository, this also runs the migrations
+        let user_repository = SqlUserRepository::new(&database_url)
+            .await
+            .context("Unable to connect to the database")?;
+        let refresh_token_repository = SqlRefreshTokenRepository::new(user_repository.pool());
+        let api_key_repository = SqlApiKeyRepository::new(user_repository.pool());
+
+        Self::with_stores(
+            app_config,
+            user_repository.clone(),
+            refresh_token_repository,
+            api_key_repository,
+            vec![Arc::new(user_repository)],
+        )
+    }
 
+    #[cfg(not(feature = "sql"))]
+    async fn with_sql(_app_config: Arc<AppConfig>) -> anyhow::Result<Self> {
+        anyhow::bail!("STORAGE is sql but the binary was built without the sqlite or postgres feature")
+    }
+
+    /// Instantiates the services on top of any store implementations
+    /// e.g InMemoryUserRepository and InMemoryRefreshTokenRepository to run without DynamoDB
+    /// The user store is cloned into each service that needs it, so clones must share their data
+    /// The health checks are run by /health/ready
+    /// Fails when the JWKS file cannot be read or the OIDC config is incomplete
+    pub fn with_stores(
+        app_config: Arc<AppConfig>,
+        user_store: impl UserStore + Clone + 'static,
+        refresh_token_store: impl RefreshTokenStore + 'static,
+        api_key_store: impl ApiKeyStore + 'static,
+        health_checks: Vec<Arc<dyn HealthCheck>>,
+    ) -> anyhow::Result<Self> {
         // Setup UserService
-        let user_repository = UserRepository::new(&shared_config, None).await;
-        let user_service = UserService::new(user_repository);
+        let user_service = UserService::new(user_store.clone(), get_page_cursor_codec(&app_config));
+
+        // Setup TokenService
+        let token_service =
+            TokenService::from_config(&app_config).context("Unable to read JWT_JWKS_PATH")?;
+
+        // Setup AuthService
+        let auth_service = AuthService::new(user_store, refresh_token_store, token_service.clone())
+            .with_refresh_token_ttl(
+                app_config
+                    .jwt_refresh_token_ttl
+                    .unwrap_or(AuthService::DEFAULT_REFRESH_TOKEN_TTL),
+            );
+
+        // Setup ApiKeyService
+        let api_key_service = ApiKeyService::new(api_key_store);
+
+        // Setup OidcService
+        let oidc_service =
+            OidcService::from_config(&app_config, user_service.clone(), auth_service.clone())?;
 
-        Self {
+        // Setup HealthService
+        let health_service = health_checks.into_iter().fold(
+            HealthService::new()
+                .with_check_timeout(
+                    app_config
+                        .health_check_timeout
+                        .map(Duration::from_millis)
+                        .unwrap_or(HealthService::DEFAULT_CHECK_TIMEOUT),
+                )
+                .with_cache_ttl(
+                    app_config
+                        .health_check_cache_ttl
+                        .map(Duration::from_millis)
+                        .unwrap_or(HealthService::DEFAULT_CACHE_TTL),
+                ),
+            HealthService::with_check,
+        );
+
+        Ok(Self {
             user_service: Some(user_service),
-        }
+            token_service: Some(token_service),
+            auth_service: Some(auth_service),
+            api_key_service: Some(api_key_service),
+            oidc_service,
+            health_service: Some(health_service),
+        })
     }
 }
 
@@ -50,3 +188,14 @@ pub async fn get_aws_shared_config(app_config: Arc<AppConfig>) -> SdkConfig {
         .load()
         .await
 }
+
+/// Helper to get the codec used to sign pagination cursors
+pub fn get_page_cursor_codec(app_config: &AppConfig) -> PageCursorCodec {
+    match &app_config.pagination_secret {
+        Some(secret) => PageCursorCodec::new(secret.as_bytes()),
+        None => {
+            tracing::warn!("PAGINATION_SECRET is not set, cursors will not survive a restart or work across instances");
+            PageCursorCodec::new(rand::random::<[u8; 32]>())
+        }
+    }
+}
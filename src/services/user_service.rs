@@ -6,21 +6,36 @@
 // e.g checking if a user is already registered before creating a new user
 // Input level validations should be done in the controller layer instead or by a middleware
 
+use std::{collections::HashSet, sync::Arc};
+
 use axum::extract::FromRef;
-use serde_dynamo::from_item;
-use tracing::log::error;
+
+use tracing::instrument;
 
 use crate::{
-    domain::user::{models::User, view_models::UserViewModel},
+    domain::common::view_models::{Page, PageQuery},
+    domain::user::{
+        models::{normalize_email, sanitize_username, User, USERNAME_MAX_LENGTH},
+        view_models::{
+            BatchGetUsersResultViewModel, CreateUserViewModel, UpdateUserViewModel, UserViewModel,
+        },
+    },
     errors::{AppError, AppResult},
-    repositories::user_repository::UserRepository,
+    repositories::user_store::{UserStore, EMAIL_CONFLICT, USERNAME_CONFLICT, VERSION_CONFLICT},
+    utils::{
+        dynamodb_helpers::PageCursorCodec,
+        password::{hash_password, MIN_PASSWORD_LENGTH},
+    },
 };
 
 use super::service_register::ServiceRegister;
 
 #[derive(Clone)]
 pub struct UserService {
-    user_repository: UserRepository,
+    // Depending on the UserStore trait rather than UserRepository lets us swap in
+    // InMemoryUserRepository when testing, see repositories/user_store.rs
+    user_repository: Arc<dyn UserStore>,
+    cursor_codec: PageCursorCodec,
 }
 
 /// This implementation is to for us to extract substates from our main state in handlers for each router
@@ -34,51 +49,320 @@ impl FromRef<ServiceRegister> for UserService {
 }
 
 impl UserService {
-    pub fn new(user_repository: UserRepository) -> Self {
-        Self { user_repository }
+    pub fn new(user_repository: impl UserStore + 'static, cursor_codec: PageCursorCodec) -> Self {
+        Self {
+            user_repository: Arc::new(user_repository),
+            cursor_codec,
+        }
     }
 
+    #[instrument(skip_all)]
     pub async fn get_current_user(self, id: String) -> AppResult<UserViewModel> {
-        // Get user from database
-        let dynamo_items = self.user_repository.get_user_by_id(id).await?;
-
-        match dynamo_items {
-            Some(dynamo_items) => {
-                // Convert the dynamo item into a User model
-                let user: User = match from_item(dynamo_items) {
-                    Ok(users) => users,
-                    Err(e) => {
-                        error!("Error while converting dynamo item into User model: {}", e);
-                        return Err(AppError::SerdeDynamoError(e));
-                    }
-                };
-
-                // Convert the User model into a UserViewModel and return
+        let user = self.find_active_user(id).await?;
+
+        // Convert the User model into a UserViewModel and return
+        Ok(UserViewModel::from(user))
+    }
+
+    /// Looks up several users at once, in the order of the ids
+    /// Ids asked for more than once are only looked up once, and soft deleted users are reported as missing
+    #[instrument(skip_all)]
+    pub async fn get_users_by_ids(
+        &self,
+        ids: Vec<String>,
+    ) -> AppResult<BatchGetUsersResultViewModel> {
+        let mut seen = HashSet::new();
+        let ids: Vec<String> = ids
+            .into_iter()
+            .filter(|id| seen.insert(id.clone()))
+            .collect();
+
+        let users = self.user_repository.get_users_by_ids(ids.clone()).await?;
+
+        let mut result = BatchGetUsersResultViewModel {
+            users: vec![],
+            missing_ids: vec![],
+        };
+        for (id, user) in ids.into_iter().zip(users) {
+            match user {
+                Some(user) if !user.is_deleted() => result.users.push(UserViewModel::from(user)),
+                _ => result.missing_ids.push(id),
+            }
+        }
+
+        Ok(result)
+    }
+
+    #[instrument(skip_all)]
+    pub async fn get_user_by_email(&self, email: String) -> AppResult<UserViewModel> {
+        let user = self.user_repository.get_user_by_email(email).await?;
+
+        active_user(user_or_not_found(user)?).map(UserViewModel::from)
+    }
+
+    #[instrument(skip_all)]
+    pub async fn get_user_by_username(&self, username: String) -> AppResult<UserViewModel> {
+        let user = self.user_repository.get_user_by_username(username).await?;
+
+        active_user(user_or_not_found(user)?).map(UserViewModel::from)
+    }
+
+    /// Lists users a page at a time, soft deleted users are left out
+    #[instrument(skip_all)]
+    pub async fn list_users(&self, query: PageQuery) -> AppResult<Page<UserViewModel>> {
+        let limit = query.limit.unwrap_or(PageQuery::DEFAULT_LIMIT);
+        if !(1..=PageQuery::MAX_LIMIT).contains(&limit) {
+            return Err(AppError::BadRequest(format!(
+                "limit must be between 1 and {}",
+                PageQuery::MAX_LIMIT
+            )));
+        }
+
+        let exclusive_start_key = match query.cursor {
+            Some(cursor) => Some(self.cursor_codec.decode(&cursor)?),
+            None => None,
+        };
+
+        let page = self
+            .user_repository
+            .list_users(limit, exclusive_start_key)
+            .await?;
+
+        Ok(Page {
+            items: page.items.into_iter().map(UserViewModel::from).collect(),
+            next_cursor: page
+                .last_evaluated_key
+                .map(|key| self.cursor_codec.encode(&key)),
+        })
+    }
+
+    #[instrument(skip_all)]
+    pub async fn create_user(&self, request: CreateUserViewModel) -> AppResult<UserViewModel> {
+        let mut user = User::new(
+            uuid::Uuid::new_v4().to_string(),
+            request.email,
+            request.username,
+            request.bio,
+            request.image,
+        );
+
+        if let Some(password) = request.password {
+            if password.chars().count() < MIN_PASSWORD_LENGTH {
+                return Err(AppError::BadRequest(format!(
+                    "password must be at least {} characters",
+                    MIN_PASSWORD_LENGTH
+                )));
+            }
+            user = user.with_password_hash(hash_password(password).await?);
+        }
+
+        // Uniqueness of email and username is enforced atomically by the repository
+        self.user_repository.put_user(&user).await?;
+
+        Ok(UserViewModel::from(user))
+    }
+
+    /// Returns the user registered with the email, creating one without a password if there is none
+    /// Used to sign in users whose email has been verified by an OIDC provider, see oidc_service.rs
+    /// The username defaults to `preferred_username`, or the part of the email before the @,
+    /// cleaned up with sanitize_username, or `user-<id>` when too little of it is left
+    /// A random suffix is added if it is already taken
+    #[instrument(skip_all)]
+    pub async fn link_or_create_user(
+        &self,
+        email: String,
+        preferred_username: Option<String>,
+    ) -> AppResult<UserViewModel> {
+        if let Some(user) = self.user_repository.get_user_by_email(email.clone()).await? {
+            // A soft deleted user has to be restored before signing in again
+            return active_user(user).map(UserViewModel::from);
+        }
+
+        let id = uuid::Uuid::new_v4();
+        let username = preferred_username
+            .as_deref()
+            .or_else(|| email.split('@').next())
+            .and_then(sanitize_username)
+            .unwrap_or_else(|| format!("user-{}", &id.simple().to_string()[..8]));
+        let mut user = User::new(
+            id.to_string(),
+            email.clone(),
+            username.clone(),
+            String::new(),
+            None,
+        );
+
+        match self.user_repository.put_user(&user).await {
+            Ok(()) => Ok(UserViewModel::from(user)),
+            Err(AppError::ObjectConflict(message)) if message == USERNAME_CONFLICT => {
+                // Sanitized usernames are ascii, so they can be cut anywhere to make room for the suffix
+                let suffix = &uuid::Uuid::new_v4().simple().to_string()[..6];
+                let prefix_length = username.len().min(USERNAME_MAX_LENGTH - suffix.len() - 1);
+                user.username = format!("{}-{}", &username[..prefix_length], suffix);
+                self.user_repository.put_user(&user).await?;
+
                 Ok(UserViewModel::from(user))
             }
-            None => Err(AppError::NotFound("User not found".to_string())),
+            // Someone signed up with the same email in the meantime
+            Err(AppError::ObjectConflict(message)) if message == EMAIL_CONFLICT => {
+                let user = self.user_repository.get_user_by_email(email).await?;
+
+                active_user(user_or_not_found(user)?).map(UserViewModel::from)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Applies a partial update to the user
+    /// If `expected_version` is given (from the If-Match header) the update is rejected with
+    /// PreconditionFailed when the user has been modified since that version
+    #[instrument(skip_all)]
+    pub async fn update_user(
+        &self,
+        id: String,
+        request: UpdateUserViewModel,
+        expected_version: Option<u64>,
+    ) -> AppResult<UserViewModel> {
+        let current = self.find_active_user(id).await?;
+
+        if expected_version.is_some_and(|version| version != current.version) {
+            return Err(AppError::PreconditionFailed(VERSION_CONFLICT.to_string()));
+        }
+
+        let mut updated = current.clone();
+        if let Some(email) = request.email {
+            updated.email = normalize_email(&email);
+        }
+        if let Some(username) = request.username {
+            updated.username = username;
+        }
+        if let Some(bio) = request.bio {
+            updated.bio = bio;
+        }
+        if let Some(image) = request.image {
+            updated.image = image;
+        }
+        if let Some(role) = request.role {
+            updated.role = role;
+        }
+        updated.touch();
+
+        // The repository re-checks the version so concurrent writers between our read and write are rejected too
+        match self.user_repository.update_user(&current, &updated).await {
+            Ok(()) => {}
+            Err(AppError::ObjectConflict(message))
+                if expected_version.is_some() && message == VERSION_CONFLICT =>
+            {
+                return Err(AppError::PreconditionFailed(message));
+            }
+            Err(e) => return Err(e),
         }
+
+        Ok(UserViewModel::from(updated))
     }
+
+    /// Soft deletes the user by default, the user can be brought back with restore_user
+    /// A hard delete permanently removes the user and frees up its email and username
+    #[instrument(skip_all)]
+    pub async fn delete_user(&self, id: String, hard: bool) -> AppResult<()> {
+        if hard {
+            // Soft deleted users can still be hard deleted
+            let user = self.find_user(id).await?;
+            return self.user_repository.delete_user(&user).await;
+        }
+
+        let current = self.find_active_user(id).await?;
+        let mut deleted = current.clone();
+        deleted.mark_deleted();
+        deleted.touch();
+
+        self.user_repository.update_user(&current, &deleted).await
+    }
+
+    /// Undoes a soft delete, restoring a user that is not deleted does nothing
+    #[instrument(skip_all)]
+    pub async fn restore_user(&self, id: String) -> AppResult<UserViewModel> {
+        let current = self.find_user(id).await?;
+
+        if !current.is_deleted() {
+            return Ok(UserViewModel::from(current));
+        }
+
+        let mut restored = current.clone();
+        restored.restore();
+        restored.touch();
+        self.user_repository.update_user(&current, &restored).await?;
+
+        Ok(UserViewModel::from(restored))
+    }
+
+    /// Same as find_user but soft deleted users are reported as not found
+    async fn find_active_user(&self, id: String) -> AppResult<User> {
+        active_user(self.find_user(id).await?)
+    }
+
+    async fn find_user(&self, id: String) -> AppResult<User> {
+        // Get user from database
+        let user = self.user_repository.get_user_by_id(id).await?;
+
+        user_or_not_found(user)
+    }
+}
+
+/// Soft deleted users are reported as not found
+fn active_user(user: User) -> AppResult<User> {
+    match user {
+        user if user.is_deleted() => Err(AppError::NotFound("User not found".to_string())),
+        user => Ok(user),
+    }
+}
+
+fn user_or_not_found(user: Option<User>) -> AppResult<User> {
+    user.ok_or_else(|| AppError::NotFound("User not found".to_string()))
 }
 
-// For this test we are also not mocking but doing an actual test
-// calling from the database defined in the environment
-// Feel free to add mocks if required.
+// For these tests we swap UserRepository for InMemoryUserRepository
+// so that they can run anywhere without AWS credentials
+// To test against the real database, build UserService with UserRepository instead
 #[cfg(test)]
 mod test {
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use utoipa::ToSchema;
+
     use crate::{
-        domain::user::view_models::UserViewModel,
-        repositories::user_repository::UserRepository,
-        services::{service_register::get_aws_shared_config, user_service::UserService}, get_app_config,
+        domain::{
+            common::view_models::PageQuery,
+            user::{
+                models::{Role, User},
+                view_models::{CreateUserViewModel, UpdateUserViewModel, UserViewModel},
+            },
+        },
+        errors::AppError,
+        repositories::{in_memory_user_repository::InMemoryUserRepository, user_store::UserStore},
+        services::user_service::UserService,
+        utils::{dynamodb_helpers::PageCursorCodec, validation::validate_schema},
     };
 
+    async fn get_user_service() -> UserService {
+        let user_repository = InMemoryUserRepository::new();
+        user_repository
+            .put_user(&User::new(
+                "ppId123".to_string(),
+                "pp@gmail.com".to_string(),
+                "pplogin".to_string(),
+                "I love to eat".to_string(),
+                Some("https://www.pexels.com/photo/selective-focus-photography-of-orange-tabby-cat-1170986".to_string()),
+            ))
+            .await
+            .unwrap();
+
+        UserService::new(user_repository, PageCursorCodec::new("secret"))
+    }
+
     #[tokio::test]
     async fn get_current_user_service() {
         // Arrange
-        let app_config = get_app_config();
-        let shared_config = get_aws_shared_config(app_config).await;
-        let user_repository = UserRepository::new(&shared_config, None).await;
-        let user_service = UserService::new(user_repository);
+        let user_service = get_user_service().await;
 
         // Act
         let res = user_service
@@ -94,8 +378,415 @@ mod test {
                 email: "pp@gmail.com".to_string(),
                 username: "pplogin".to_string(),
                 bio: "I love to eat".to_string(),
-                image: Some("https://www.pexels.com/photo/selective-focus-photography-of-orange-tabby-cat-1170986".to_string())
+                image: Some("https://www.pexels.com/photo/selective-focus-photography-of-orange-tabby-cat-1170986".to_string()),
+                version: 0,
+                role: Role::User,
             }
         )
     }
+
+    #[tokio::test]
+    async fn create_user_rejects_duplicate_email() {
+        // Arrange
+        let user_service = get_user_service().await;
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let request = |username: String| CreateUserViewModel {
+            email: format!("{}@gmail.com", suffix),
+            username,
+            bio: "I love to eat".to_string(),
+            image: None,
+            password: None,
+        };
+
+        // Act
+        let created = user_service
+            .create_user(request(format!("first{}", suffix)))
+            .await
+            .unwrap();
+        let duplicate = user_service
+            .create_user(request(format!("second{}", suffix)))
+            .await;
+
+        // Assert
+        assert_eq!(created.email, format!("{}@gmail.com", suffix));
+        assert!(matches!(duplicate, Err(AppError::ObjectConflict(_))));
+    }
+
+    #[tokio::test]
+    async fn update_user_rejects_stale_version() {
+        // Arrange
+        let user_service = get_user_service().await;
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let created = user_service
+            .create_user(CreateUserViewModel {
+                email: format!("{}@gmail.com", suffix),
+                username: suffix.clone(),
+                bio: "I love to eat".to_string(),
+                image: None,
+                password: None,
+            })
+            .await
+            .unwrap();
+        let update = |bio: &str| UpdateUserViewModel {
+            bio: Some(bio.to_string()),
+            ..Default::default()
+        };
+
+        // Act
+        let updated = user_service
+            .update_user(created.id.clone(), update("I love to cook"), Some(created.version))
+            .await
+            .unwrap();
+        let stale = user_service
+            .update_user(created.id.clone(), update("I love to sleep"), Some(created.version))
+            .await;
+
+        // Assert
+        assert_eq!(updated.bio, "I love to cook");
+        assert_eq!(updated.version, created.version + 1);
+        assert!(matches!(stale, Err(AppError::PreconditionFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn update_user_removes_the_image_on_an_explicit_null() {
+        // Arrange
+        let user_service = get_user_service().await;
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let created = user_service
+            .create_user(CreateUserViewModel {
+                email: format!("{}@gmail.com", suffix),
+                username: suffix.clone(),
+                bio: "I love to eat".to_string(),
+                image: Some("https://example.com/cat.png".to_string()),
+                password: None,
+            })
+            .await
+            .unwrap();
+        let without_image: UpdateUserViewModel =
+            serde_json::from_value(serde_json::json!({ "bio": "I love to cook" })).unwrap();
+        let null_image: UpdateUserViewModel =
+            serde_json::from_value(serde_json::json!({ "image": null })).unwrap();
+
+        // Act
+        let missing = user_service
+            .update_user(created.id.clone(), without_image, None)
+            .await
+            .unwrap();
+        let null = user_service
+            .update_user(created.id.clone(), null_image, None)
+            .await
+            .unwrap();
+        let found = user_service.clone().get_current_user(created.id).await.unwrap();
+
+        // Assert
+        assert_eq!(missing.image.as_deref(), Some("https://example.com/cat.png"));
+        assert_eq!(null.image, None);
+        assert_eq!(found.image, None);
+    }
+
+    #[tokio::test]
+    async fn soft_deleted_user_is_not_found_until_restored() {
+        // Arrange
+        let user_service = get_user_service().await;
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let created = user_service
+            .create_user(CreateUserViewModel {
+                email: format!("{}@gmail.com", suffix),
+                username: suffix.clone(),
+                bio: "I love to eat".to_string(),
+                image: None,
+                password: None,
+            })
+            .await
+            .unwrap();
+
+        // Act
+        user_service.delete_user(created.id.clone(), false).await.unwrap();
+        let deleted = user_service.clone().get_current_user(created.id.clone()).await;
+        let restored = user_service.restore_user(created.id.clone()).await.unwrap();
+        user_service.delete_user(created.id.clone(), true).await.unwrap();
+        let hard_deleted = user_service.restore_user(created.id.clone()).await;
+
+        // Assert
+        assert!(matches!(deleted, Err(AppError::NotFound(_))));
+        assert_eq!(restored.id, created.id);
+        assert!(matches!(hard_deleted, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn list_users_rejects_tampered_cursor() {
+        // Arrange
+        let user_service = get_user_service().await;
+        let forged_cursor = PageCursorCodec::new("not the secret").encode(
+            &[("id".to_string(), AttributeValue::S("ppId123".to_string()))].into(),
+        );
+
+        // Act
+        let res = user_service
+            .list_users(PageQuery {
+                limit: Some(10),
+                cursor: Some(forged_cursor),
+            })
+            .await;
+
+        // Assert
+        assert!(matches!(res, Err(AppError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn link_or_create_user_reuses_existing_email() {
+        // Arrange
+        let user_service = get_user_service().await;
+
+        // Act
+        let linked = user_service
+            .link_or_create_user("pp@gmail.com".to_string(), None)
+            .await
+            .unwrap();
+        let created = user_service
+            .link_or_create_user("other@gmail.com".to_string(), Some("pplogin".to_string()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(linked.id, "ppId123");
+        assert_ne!(created.id, "ppId123");
+        assert!(created.username.starts_with("pplogin-"));
+    }
+
+    #[tokio::test]
+    async fn link_or_create_user_only_creates_valid_usernames() {
+        // Arrange
+        let user_service = get_user_service().await;
+        let long_name = "a".repeat(40);
+        let cases = [
+            ("jd@gmail.com", Some("J D")),
+            ("@gmail.com", None),
+            ("jurgen@gmail.com", Some("Jürgen Müller")),
+            ("long@gmail.com", Some(long_name.as_str())),
+            ("longer@gmail.com", Some(long_name.as_str())),
+        ];
+
+        for (email, preferred_username) in cases {
+            // Act
+            let created = user_service
+                .link_or_create_user(email.to_string(), preferred_username.map(str::to_string))
+                .await
+                .unwrap();
+
+            // Assert
+            let request = serde_json::json!({ "email": "valid@gmail.com", "username": created.username });
+            let errors = validate_schema(&request, &CreateUserViewModel::schema().1);
+            assert!(errors.is_empty(), "{}: {:?}", created.username, errors);
+            match preferred_username {
+                Some("Jürgen Müller") => assert_eq!(created.username, "JrgenMller"),
+                Some("J D") | None => assert!(created.username.starts_with("user-")),
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn get_users_by_ids_keeps_order_and_reports_missing_ids() {
+        // Arrange
+        let user_service = get_user_service().await;
+        let deleted = user_service
+            .create_user(CreateUserViewModel {
+                email: "gone@gmail.com".to_string(),
+                username: "gone".to_string(),
+                bio: "".to_string(),
+                image: None,
+                password: None,
+            })
+            .await
+            .unwrap();
+        user_service
+            .delete_user(deleted.id.clone(), false)
+            .await
+            .unwrap();
+        let second = user_service
+            .create_user(CreateUserViewModel {
+                email: "second@gmail.com".to_string(),
+                username: "second".to_string(),
+                bio: "".to_string(),
+                image: None,
+                password: None,
+            })
+            .await
+            .unwrap();
+
+        // Act
+        let result = user_service
+            .get_users_by_ids(vec![
+                second.id.clone(),
+                "missingId".to_string(),
+                "ppId123".to_string(),
+                deleted.id.clone(),
+                second.id.clone(),
+            ])
+            .await
+            .unwrap();
+
+        // Assert
+        let ids: Vec<&str> = result.users.iter().map(|user| user.id.as_str()).collect();
+        assert_eq!(ids, vec![second.id.as_str(), "ppId123"]);
+        assert_eq!(result.missing_ids, vec!["missingId".to_string(), deleted.id]);
+    }
+
+    #[tokio::test]
+    async fn emails_are_stored_and_matched_lowercased() {
+        // Arrange
+        let user_service = get_user_service().await;
+
+        // Act
+        let created = user_service
+            .create_user(CreateUserViewModel {
+                email: "Mixed.Case@Gmail.com".to_string(),
+                username: "mixedcase".to_string(),
+                bio: "".to_string(),
+                image: None,
+                password: None,
+            })
+            .await
+            .unwrap();
+        let found = user_service
+            .get_user_by_email("MIXED.CASE@gmail.COM".to_string())
+            .await
+            .unwrap();
+        let updated = user_service
+            .update_user(
+                created.id.clone(),
+                UpdateUserViewModel {
+                    email: Some("New.Case@Gmail.com".to_string()),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(created.email, "mixed.case@gmail.com");
+        assert_eq!(found, created);
+        assert_eq!(updated.email, "new.case@gmail.com");
+    }
+
+    #[tokio::test]
+    async fn get_user_by_email_not_found() {
+        // Arrange
+        let user_service = get_user_service().await;
+
+        // Act
+        let res = user_service
+            .get_user_by_email(format!("{}@gmail.com", uuid::Uuid::new_v4().simple()))
+            .await;
+
+        // Assert
+        assert!(matches!(res, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn list_users_pages_through_active_users() {
+        // Arrange
+        let user_service = get_user_service().await;
+        for i in 0..4 {
+            let created = user_service
+                .create_user(CreateUserViewModel {
+                    email: format!("user{}@gmail.com", i),
+                    username: format!("user{}", i),
+                    bio: "I love to eat".to_string(),
+                    image: None,
+                    password: None,
+                })
+                .await
+                .unwrap();
+            if i == 0 {
+                user_service.delete_user(created.id, false).await.unwrap();
+            }
+        }
+
+        // Act
+        let first = user_service
+            .list_users(PageQuery {
+                limit: Some(2),
+                cursor: None,
+            })
+            .await
+            .unwrap();
+        let second = user_service
+            .list_users(PageQuery {
+                limit: Some(2),
+                cursor: first.next_cursor.clone(),
+            })
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(first.items.len(), 2);
+        assert_eq!(second.items.len(), 2);
+        assert_eq!(second.next_cursor, None);
+        assert!(first
+            .items
+            .iter()
+            .chain(second.items.iter())
+            .all(|user| user.username != "user0"));
+    }
+
+    // Run with cargo test --features sqlite
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sql_user_repository_enforces_uniqueness_and_versions() {
+        // Arrange
+        let user_repository =
+            crate::repositories::sql_user_repository::SqlUserRepository::new("sqlite::memory:")
+                .await
+                .unwrap();
+        let user_service = UserService::new(user_repository, PageCursorCodec::new("secret"));
+        let request = |email: &str, username: &str| CreateUserViewModel {
+            email: email.to_string(),
+            username: username.to_string(),
+            bio: "I love to eat".to_string(),
+            image: None,
+            password: None,
+        };
+        let created = user_service
+            .create_user(request("pp@gmail.com", "pplogin"))
+            .await
+            .unwrap();
+
+        // Act
+        let duplicate_email = user_service
+            .create_user(request("PP@gmail.com", "other"))
+            .await;
+        let duplicate_username = user_service
+            .create_user(request("other@gmail.com", "PPLOGIN"))
+            .await;
+        let updated = user_service
+            .update_user(
+                created.id.clone(),
+                UpdateUserViewModel {
+                    bio: Some("I love to cook".to_string()),
+                    ..Default::default()
+                },
+                Some(created.version),
+            )
+            .await
+            .unwrap();
+        let stale = user_service
+            .update_user(created.id.clone(), UpdateUserViewModel::default(), Some(created.version))
+            .await;
+        let found = user_service
+            .get_user_by_email("PP@Gmail.com".to_string())
+            .await
+            .unwrap();
+        user_service.delete_user(created.id.clone(), true).await.unwrap();
+        let deleted = user_service.clone().get_current_user(created.id).await;
+
+        // Assert
+        assert!(matches!(duplicate_email, Err(AppError::ObjectConflict(message)) if message == "Email is already registered"));
+        assert!(matches!(duplicate_username, Err(AppError::ObjectConflict(message)) if message == "Username is already taken"));
+        assert_eq!(found, updated);
+        assert!(matches!(stale, Err(AppError::PreconditionFailed(_))));
+        assert!(matches!(deleted, Err(AppError::NotFound(_))));
+    }
 }
@@ -1,34 +1,216 @@
 use aws_sdk_dynamodb::error::SdkError::{
     ConstructionFailure, DispatchFailure, ResponseError, ServiceError, TimeoutError,
 };
-use aws_sdk_dynamodb::{error::SdkError, primitives::Blob, types::AttributeValue};
-use std::collections::HashMap;
+use aws_sdk_dynamodb::types::{
+    DeleteRequest, KeysAndAttributes, PutRequest, ReturnValue, WriteRequest,
+};
+use aws_sdk_dynamodb::{error::SdkError, primitives::Blob, types::AttributeValue, Client};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::time::Duration;
 use tracing::log::error;
 
+use crate::errors::{AppError, AppResult};
+use crate::repositories::repository_error::RepositoryError;
+use crate::utils::metrics::record_dynamodb_call;
+use crate::utils::signer::Signer;
+
 pub type DynamoItem = HashMap<String, AttributeValue>;
 
-/// Custom helpers for dynamodb to quickly convert primitives into AttributeValue
-/// Only a couple of types are implemented here, but you can add more as you need
+/// Custom helpers for dynamodb to quickly convert values into AttributeValue
+/// Numbers become N, strings S, Vec<u8> and Blob B, Option::None NULL, List<T> L, HashMap<String, T> M,
+/// HashSet<String> SS and sets of integers NS, timestamps are written as RFC 3339 strings,
+/// or with the expanded year of ISO 8601 for the years RFC 3339 cannot hold
+/// DynamoDB rejects empty sets as well as NaN and infinite numbers
 pub trait IntoAttributeValue {
     fn into_av(self) -> AttributeValue;
 }
 
-impl IntoAttributeValue for u64 {
-    fn into_av(self) -> AttributeValue {
-        AttributeValue::N(self.to_string())
+/// The other way around, with an error telling what was expected and what was found on a mismatch
+/// Use get_attribute to read an attribute of an item
+pub trait FromAttributeValue: Sized {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError>;
+
+    /// Value of a missing attribute, only optional values can be missing
+    fn from_missing() -> Option<Self> {
+        None
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum AttributeValueError {
+    #[error("Expected {expected} but found {found}")]
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("Expected {expected} but found {value:?}: {reason}")]
+    InvalidValue {
+        expected: &'static str,
+        value: String,
+        reason: String,
+    },
+    #[error("Attribute {0} is missing")]
+    MissingAttribute(String),
+    #[error("Attribute {name}: {error}")]
+    InvalidAttribute {
+        name: String,
+        error: Box<AttributeValueError>,
+    },
+}
+
+impl AttributeValueError {
+    fn mismatch(expected: &'static str, found: &AttributeValue) -> Self {
+        AttributeValueError::TypeMismatch {
+            expected,
+            found: type_name(found),
+        }
     }
+
+    fn invalid(expected: &'static str, value: &str, reason: impl ToString) -> Self {
+        AttributeValueError::InvalidValue {
+            expected,
+            value: value.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// Reads and converts an attribute of an item, the error names the attribute
+pub fn get_attribute<T: FromAttributeValue>(
+    item: &DynamoItem,
+    name: &str,
+) -> Result<T, AttributeValueError> {
+    match item.get(name) {
+        Some(value) => T::from_av(value).map_err(|error| AttributeValueError::InvalidAttribute {
+            name: name.to_string(),
+            error: Box::new(error),
+        }),
+        None => {
+            T::from_missing().ok_or_else(|| AttributeValueError::MissingAttribute(name.to_string()))
+        }
+    }
+}
+
+const STRING: &str = "a string (S)";
+const NUMBER: &str = "a number (N)";
+const BINARY: &str = "a binary (B)";
+const BOOLEAN: &str = "a boolean (BOOL)";
+const LIST: &str = "a list (L)";
+const MAP: &str = "a map (M)";
+const STRING_SET: &str = "a string set (SS)";
+const NUMBER_SET: &str = "a number set (NS)";
+
+fn type_name(value: &AttributeValue) -> &'static str {
+    match value {
+        AttributeValue::S(_) => STRING,
+        AttributeValue::N(_) => NUMBER,
+        AttributeValue::B(_) => BINARY,
+        AttributeValue::Bool(_) => BOOLEAN,
+        AttributeValue::Null(_) => "null (NULL)",
+        AttributeValue::L(_) => LIST,
+        AttributeValue::M(_) => MAP,
+        AttributeValue::Ss(_) => STRING_SET,
+        AttributeValue::Ns(_) => NUMBER_SET,
+        AttributeValue::Bs(_) => "a binary set (BS)",
+        _ => "an unknown type",
+    }
+}
+
+/// Numbers are sent as strings so that no precision is lost
+macro_rules! impl_number_attribute_value {
+    ($($number:ty),*) => {
+        $(
+            impl IntoAttributeValue for $number {
+                fn into_av(self) -> AttributeValue {
+                    AttributeValue::N(self.to_string())
+                }
+            }
+
+            impl FromAttributeValue for $number {
+                fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+                    let number = value.as_n().map_err(|_| AttributeValueError::mismatch(NUMBER, value))?;
+                    number
+                        .parse()
+                        .map_err(|e| AttributeValueError::invalid(stringify!($number), number, e))
+                }
+            }
+        )*
+    };
+}
+
+impl_number_attribute_value!(
+    i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64
+);
+
+/// Floats cannot be hashed, so only sets of integers are supported
+macro_rules! impl_number_set_attribute_value {
+    ($($number:ty),*) => {
+        $(
+            impl IntoAttributeValue for HashSet<$number> {
+                fn into_av(self) -> AttributeValue {
+                    AttributeValue::Ns(self.iter().map(|number| number.to_string()).collect())
+                }
+            }
+
+            impl FromAttributeValue for HashSet<$number> {
+                fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+                    let numbers = value.as_ns().map_err(|_| AttributeValueError::mismatch(NUMBER_SET, value))?;
+                    numbers
+                        .iter()
+                        .map(|number| {
+                            number
+                                .parse()
+                                .map_err(|e| AttributeValueError::invalid(stringify!($number), number, e))
+                        })
+                        .collect()
+                }
+            }
+        )*
+    };
 }
 
+impl_number_set_attribute_value!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
 impl IntoAttributeValue for String {
     fn into_av(self) -> AttributeValue {
         AttributeValue::S(self)
     }
 }
 
-impl IntoAttributeValue for Vec<u8> {
+impl FromAttributeValue for String {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        value
+            .as_s()
+            .cloned()
+            .map_err(|_| AttributeValueError::mismatch(STRING, value))
+    }
+}
+
+impl IntoAttributeValue for &str {
     fn into_av(self) -> AttributeValue {
-        AttributeValue::B(Blob::new(self))
+        AttributeValue::S(self.to_string())
+    }
+}
+
+impl IntoAttributeValue for Blob {
+    fn into_av(self) -> AttributeValue {
+        AttributeValue::B(self)
+    }
+}
+
+impl FromAttributeValue for Blob {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        value
+            .as_b()
+            .cloned()
+            .map_err(|_| AttributeValueError::mismatch(BINARY, value))
     }
 }
 
@@ -38,10 +220,1158 @@ impl IntoAttributeValue for bool {
     }
 }
 
+impl FromAttributeValue for bool {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        value
+            .as_bool()
+            .copied()
+            .map_err(|_| AttributeValueError::mismatch(BOOLEAN, value))
+    }
+}
+
+impl IntoAttributeValue for AttributeValue {
+    fn into_av(self) -> AttributeValue {
+        self
+    }
+}
+
+impl FromAttributeValue for AttributeValue {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        Ok(value.clone())
+    }
+}
+
+/// None is written as NULL, and read back from either NULL or a missing attribute
+impl<T: IntoAttributeValue> IntoAttributeValue for Option<T> {
+    fn into_av(self) -> AttributeValue {
+        match self {
+            Some(value) => value.into_av(),
+            None => AttributeValue::Null(true),
+        }
+    }
+}
+
+impl<T: FromAttributeValue> FromAttributeValue for Option<T> {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        match value {
+            AttributeValue::Null(_) => Ok(None),
+            value => T::from_av(value).map(Some),
+        }
+    }
+
+    fn from_missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+impl IntoAttributeValue for Vec<u8> {
+    fn into_av(self) -> AttributeValue {
+        AttributeValue::B(Blob::new(self))
+    }
+}
+
+impl FromAttributeValue for Vec<u8> {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        Blob::from_av(value).map(Blob::into_inner)
+    }
+}
+
+/// A list (L) of values of the same type
+/// Vec<u8> is already a binary (B), so lists are written through this wrapper e.g List(vec![1u8, 2])
+/// Lists can mix types, but a List<T> can only be read back if every element is a T
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct List<T>(pub Vec<T>);
+
+impl<T> From<Vec<T>> for List<T> {
+    fn from(items: Vec<T>) -> Self {
+        List(items)
+    }
+}
+
+impl<T: IntoAttributeValue> IntoAttributeValue for List<T> {
+    fn into_av(self) -> AttributeValue {
+        AttributeValue::L(
+            self.0
+                .into_iter()
+                .map(IntoAttributeValue::into_av)
+                .collect(),
+        )
+    }
+}
+
+impl<T: FromAttributeValue> FromAttributeValue for List<T> {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        value
+            .as_l()
+            .map_err(|_| AttributeValueError::mismatch(LIST, value))?
+            .iter()
+            .map(T::from_av)
+            .collect::<Result<_, _>>()
+            .map(List)
+    }
+}
+
+impl<T: IntoAttributeValue> IntoAttributeValue for HashMap<String, T> {
+    fn into_av(self) -> AttributeValue {
+        AttributeValue::M(
+            self.into_iter()
+                .map(|(name, value)| (name, value.into_av()))
+                .collect(),
+        )
+    }
+}
+
+impl<T: FromAttributeValue> FromAttributeValue for HashMap<String, T> {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        value
+            .as_m()
+            .map_err(|_| AttributeValueError::mismatch(MAP, value))?
+            .iter()
+            .map(|(name, value)| {
+                T::from_av(value)
+                    .map(|value| (name.clone(), value))
+                    .map_err(|error| AttributeValueError::InvalidAttribute {
+                        name: name.clone(),
+                        error: Box::new(error),
+                    })
+            })
+            .collect()
+    }
+}
+
+impl IntoAttributeValue for HashSet<String> {
+    fn into_av(self) -> AttributeValue {
+        AttributeValue::Ss(self.into_iter().collect())
+    }
+}
+
+impl FromAttributeValue for HashSet<String> {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        value
+            .as_ss()
+            .map(|strings| strings.iter().cloned().collect())
+            .map_err(|_| AttributeValueError::mismatch(STRING_SET, value))
+    }
+}
+
+impl IntoAttributeValue for chrono::DateTime<chrono::Utc> {
+    fn into_av(self) -> AttributeValue {
+        AttributeValue::S(self.to_rfc3339())
+    }
+}
+
+impl FromAttributeValue for chrono::DateTime<chrono::Utc> {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        let text = String::from_av(value)?;
+        chrono::DateTime::parse_from_rfc3339(&text)
+            .map(|timestamp| timestamp.with_timezone(&chrono::Utc))
+            .map_err(|e| AttributeValueError::invalid("an RFC 3339 timestamp", &text, e))
+    }
+}
+
+/// RFC 3339 only covers the years 0 to 9999, the others are written with the expanded year
+/// of ISO 8601 instead e.g +010000-01-01T00:00:00.000000000Z
+const EXPANDED_YEAR: time::format_description::well_known::iso8601::EncodedConfig =
+    time::format_description::well_known::iso8601::Config::DEFAULT
+        .set_year_is_six_digits(true)
+        .encode();
+
+impl IntoAttributeValue for time::OffsetDateTime {
+    fn into_av(self) -> AttributeValue {
+        use time::format_description::well_known::{Iso8601, Rfc3339};
+
+        let text = match self.year() {
+            0..=9999 => self.format(&Rfc3339),
+            _ => self.format(&Iso8601::<EXPANDED_YEAR>),
+        };
+        AttributeValue::S(text.expect("Every OffsetDateTime can be formatted with an expanded year"))
+    }
+}
+
+impl FromAttributeValue for time::OffsetDateTime {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        use time::format_description::well_known::{Iso8601, Rfc3339};
+
+        let text = String::from_av(value)?;
+        time::OffsetDateTime::parse(&text, &Rfc3339)
+            .or_else(|_| time::OffsetDateTime::parse(&text, &Iso8601::DEFAULT))
+            .map_err(|e| AttributeValueError::invalid("an RFC 3339 timestamp", &text, e))
+    }
+}
+
+impl IntoAttributeValue for uuid::Uuid {
+    fn into_av(self) -> AttributeValue {
+        AttributeValue::S(self.to_string())
+    }
+}
+
+impl FromAttributeValue for uuid::Uuid {
+    fn from_av(value: &AttributeValue) -> Result<Self, AttributeValueError> {
+        let text = String::from_av(value)?;
+        uuid::Uuid::parse_str(&text).map_err(|e| AttributeValueError::invalid("a uuid", &text, e))
+    }
+}
+
+/// A single page of items returned by a Query or Scan
+/// `last_evaluated_key` is None when there are no more items to read
+/// Repositories can map the raw items into their models with map_items
+#[derive(Debug, Default)]
+pub struct DynamoPage<T = DynamoItem> {
+    pub items: Vec<T>,
+    pub last_evaluated_key: Option<DynamoItem>,
+}
+
+impl<T> DynamoPage<T> {
+    pub fn map_items<U, E>(self, f: impl FnMut(T) -> Result<U, E>) -> Result<DynamoPage<U>, E> {
+        Ok(DynamoPage {
+            items: self.items.into_iter().map(f).collect::<Result<_, _>>()?,
+            last_evaluated_key: self.last_evaluated_key,
+        })
+    }
+}
+
+/// Names of the attributes making up the primary key of a table
+#[derive(Debug, Clone)]
+pub struct KeySchema {
+    pub partition_key: String,
+    pub sort_key: Option<String>,
+}
+
+impl KeySchema {
+    pub fn new(partition_key: &str) -> Self {
+        Self {
+            partition_key: partition_key.to_string(),
+            sort_key: None,
+        }
+    }
+
+    pub fn with_sort_key(mut self, sort_key: &str) -> Self {
+        self.sort_key = Some(sort_key.to_string());
+        self
+    }
+
+    /// Key of an item in a table without a sort key
+    pub fn key(&self, partition: impl IntoAttributeValue) -> DynamoItem {
+        DynamoItem::from([(self.partition_key.clone(), partition.into_av())])
+    }
+
+    /// Key of an item in a table with a sort key
+    pub fn composite_key(
+        &self,
+        partition: impl IntoAttributeValue,
+        sort: impl IntoAttributeValue,
+    ) -> DynamoItem {
+        let sort_key = self.sort_key.clone().expect("The table has a sort key");
+        let mut key = self.key(partition);
+        key.insert(sort_key, sort.into_av());
+        key
+    }
+
+    /// Copies the key attributes out of a whole item
+    pub fn key_of(&self, item: &DynamoItem) -> DynamoItem {
+        item.iter()
+            .filter(|(name, _)| {
+                **name == self.partition_key || Some(*name) == self.sort_key.as_ref()
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// A condition, update, filter or projection expression with the placeholders it references
+/// Prefer building them with Condition, KeyCondition, UpdateExpression or Projection,
+/// which generate the placeholders, over writing them by hand
+/// e.g Expression::new("#version = :version").with_name("#version", "version").with_value(":version", 3u64)
+#[derive(Debug, Default, Clone)]
+pub struct Expression {
+    pub expression: String,
+    pub names: HashMap<String, String>,
+    pub values: DynamoItem,
+}
+
+impl Expression {
+    pub fn new(expression: &str) -> Self {
+        Self {
+            expression: expression.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_name(mut self, placeholder: &str, name: &str) -> Self {
+        self.names.insert(placeholder.to_string(), name.to_string());
+        self
+    }
+
+    pub fn with_value(mut self, placeholder: &str, value: impl IntoAttributeValue) -> Self {
+        self.values.insert(placeholder.to_string(), value.into_av());
+        self
+    }
+}
+
+/// Placeholders of every expression of a request, DynamoDB rejects the empty maps
+/// Expressions built with Condition, KeyCondition, UpdateExpression and Projection can share a request,
+/// their value placeholders never clash and the same attribute always gets the same name placeholder
+#[derive(Default)]
+pub struct Placeholders {
+    names: HashMap<String, String>,
+    values: DynamoItem,
+}
+
+impl Placeholders {
+    /// Collects the placeholders of the expression and hands back the expression itself
+    pub fn add(&mut self, expression: Option<Expression>) -> Option<String> {
+        let expression = expression?;
+        self.names.extend(expression.names);
+        self.values.extend(expression.values);
+        Some(expression.expression)
+    }
+
+    pub fn names(&mut self) -> Option<HashMap<String, String>> {
+        Some(std::mem::take(&mut self.names)).filter(|names| !names.is_empty())
+    }
+
+    pub fn values(&mut self) -> Option<DynamoItem> {
+        Some(std::mem::take(&mut self.values)).filter(|values| !values.is_empty())
+    }
+}
+
+/// Collects the placeholders while an expression is rendered
+/// Values are numbered within the expression and prefixed by its kind e.g :c0 in a condition, :u0 in an update
+struct ExpressionWriter {
+    prefix: &'static str,
+    expression: Expression,
+}
+
+impl ExpressionWriter {
+    fn new(prefix: &'static str) -> Self {
+        Self {
+            prefix,
+            expression: Expression::default(),
+        }
+    }
+
+    /// Attribute names are always replaced by placeholders so that reserved words such as name can be used
+    fn name(&mut self, name: &str) -> String {
+        let placeholder = name_placeholder(name);
+        self.expression
+            .names
+            .insert(placeholder.clone(), name.to_string());
+        placeholder
+    }
+
+    fn value(&mut self, value: AttributeValue) -> String {
+        let placeholder = format!(":{}{}", self.prefix, self.expression.values.len());
+        self.expression.values.insert(placeholder.clone(), value);
+        placeholder
+    }
+
+    fn finish(mut self, expression: String) -> Expression {
+        self.expression.expression = expression;
+        self.expression
+    }
+}
+
+/// #name for plain names, any other character is replaced by its code so that placeholders stay unique
+fn name_placeholder(name: &str) -> String {
+    name.chars().fold("#".to_string(), |mut placeholder, c| {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            placeholder.push(c);
+        } else {
+            placeholder.push_str(&format!("_{:x}_", c as u32));
+        }
+        placeholder
+    })
+}
+
+/// Comparison operators of a Condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparator {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Comparator::Eq => "=",
+            Comparator::Ne => "<>",
+            Comparator::Lt => "<",
+            Comparator::Le => "<=",
+            Comparator::Gt => ">",
+            Comparator::Ge => ">=",
+        }
+    }
+}
+
+/// A condition or filter expression
+/// e.g Condition::attribute_exists("id").and(Condition::eq("version", 3u64)), negate one with !condition
+#[derive(Debug, Clone)]
+pub enum Condition {
+    AttributeExists(String),
+    AttributeNotExists(String),
+    BeginsWith(String, AttributeValue),
+    Compare(String, Comparator, AttributeValue),
+    Between(String, AttributeValue, AttributeValue),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    pub fn attribute_exists(name: &str) -> Self {
+        Condition::AttributeExists(name.to_string())
+    }
+
+    pub fn attribute_not_exists(name: &str) -> Self {
+        Condition::AttributeNotExists(name.to_string())
+    }
+
+    pub fn begins_with(name: &str, prefix: impl IntoAttributeValue) -> Self {
+        Condition::BeginsWith(name.to_string(), prefix.into_av())
+    }
+
+    pub fn compare(name: &str, comparator: Comparator, value: impl IntoAttributeValue) -> Self {
+        Condition::Compare(name.to_string(), comparator, value.into_av())
+    }
+
+    pub fn eq(name: &str, value: impl IntoAttributeValue) -> Self {
+        Condition::compare(name, Comparator::Eq, value)
+    }
+
+    pub fn ne(name: &str, value: impl IntoAttributeValue) -> Self {
+        Condition::compare(name, Comparator::Ne, value)
+    }
+
+    pub fn lt(name: &str, value: impl IntoAttributeValue) -> Self {
+        Condition::compare(name, Comparator::Lt, value)
+    }
+
+    pub fn le(name: &str, value: impl IntoAttributeValue) -> Self {
+        Condition::compare(name, Comparator::Le, value)
+    }
+
+    pub fn gt(name: &str, value: impl IntoAttributeValue) -> Self {
+        Condition::compare(name, Comparator::Gt, value)
+    }
+
+    pub fn ge(name: &str, value: impl IntoAttributeValue) -> Self {
+        Condition::compare(name, Comparator::Ge, value)
+    }
+
+    /// Inclusive on both ends
+    pub fn between(
+        name: &str,
+        low: impl IntoAttributeValue,
+        high: impl IntoAttributeValue,
+    ) -> Self {
+        Condition::Between(name.to_string(), low.into_av(), high.into_av())
+    }
+
+    pub fn and(self, other: Condition) -> Self {
+        Condition::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Condition) -> Self {
+        Condition::Or(Box::new(self), Box::new(other))
+    }
+
+    pub fn build(self) -> Expression {
+        self.build_with_prefix("c")
+    }
+
+    fn build_with_prefix(self, prefix: &'static str) -> Expression {
+        let mut writer = ExpressionWriter::new(prefix);
+        let expression = self.render(&mut writer);
+        writer.finish(expression)
+    }
+
+    fn render(self, writer: &mut ExpressionWriter) -> String {
+        match self {
+            Condition::AttributeExists(name) => format!("attribute_exists({})", writer.name(&name)),
+            Condition::AttributeNotExists(name) => {
+                format!("attribute_not_exists({})", writer.name(&name))
+            }
+            Condition::BeginsWith(name, prefix) => {
+                format!(
+                    "begins_with({}, {})",
+                    writer.name(&name),
+                    writer.value(prefix)
+                )
+            }
+            Condition::Compare(name, comparator, value) => format!(
+                "{} {} {}",
+                writer.name(&name),
+                comparator.as_str(),
+                writer.value(value)
+            ),
+            Condition::Between(name, low, high) => format!(
+                "{} BETWEEN {} AND {}",
+                writer.name(&name),
+                writer.value(low),
+                writer.value(high)
+            ),
+            // Operands are always parenthesized so that precedence never has to be thought about
+            Condition::And(left, right) => {
+                format!("({}) AND ({})", left.render(writer), right.render(writer))
+            }
+            Condition::Or(left, right) => {
+                format!("({}) OR ({})", left.render(writer), right.render(writer))
+            }
+            Condition::Not(condition) => format!("NOT ({})", condition.render(writer)),
+        }
+    }
+}
+
+impl std::ops::Not for Condition {
+    type Output = Condition;
+
+    fn not(self) -> Self::Output {
+        Condition::Not(Box::new(self))
+    }
+}
+
+/// The key condition of a Query, an equality on the partition key and an optional condition on the sort key
+/// The sort key condition can only be a comparison other than <>, a between or a begins_with
+#[derive(Debug, Clone)]
+pub struct KeyCondition {
+    partition_key: String,
+    partition: AttributeValue,
+    sort: Option<Condition>,
+}
+
+impl KeyCondition {
+    pub fn partition(name: &str, value: impl IntoAttributeValue) -> Self {
+        Self {
+            partition_key: name.to_string(),
+            partition: value.into_av(),
+            sort: None,
+        }
+    }
+
+    pub fn with_sort(mut self, condition: Condition) -> Self {
+        self.sort = Some(condition);
+        self
+    }
+
+    pub fn build(self) -> Expression {
+        let condition = Condition::eq(&self.partition_key, self.partition);
+        match self.sort {
+            Some(sort) => condition.and(sort),
+            None => condition,
+        }
+        .build_with_prefix("k")
+    }
+}
+
+/// An update expression made of SET, REMOVE and ADD clauses
+/// e.g UpdateExpression::new().set("bio", "I love to eat".to_string()).remove("image")
+#[derive(Debug, Default, Clone)]
+pub struct UpdateExpression {
+    set: Vec<(String, AttributeValue, bool)>,
+    remove: Vec<String>,
+    add: Vec<(String, AttributeValue)>,
+}
+
+impl UpdateExpression {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(mut self, name: &str, value: impl IntoAttributeValue) -> Self {
+        self.set.push((name.to_string(), value.into_av(), false));
+        self
+    }
+
+    /// Only sets the attribute if it does not have a value yet
+    pub fn set_if_not_exists(mut self, name: &str, value: impl IntoAttributeValue) -> Self {
+        self.set.push((name.to_string(), value.into_av(), true));
+        self
+    }
+
+    pub fn remove(mut self, name: &str) -> Self {
+        self.remove.push(name.to_string());
+        self
+    }
+
+    /// Adds to a number, or adds the elements to a set, creating the attribute if needed
+    pub fn add(mut self, name: &str, value: impl IntoAttributeValue) -> Self {
+        self.add.push((name.to_string(), value.into_av()));
+        self
+    }
+
+    pub fn build(self) -> Expression {
+        let mut writer = ExpressionWriter::new("u");
+        let mut clauses = vec![];
+
+        if !self.set.is_empty() {
+            let actions: Vec<String> = self
+                .set
+                .into_iter()
+                .map(|(name, value, if_not_exists)| {
+                    let name = writer.name(&name);
+                    let value = writer.value(value);
+                    if if_not_exists {
+                        format!("{} = if_not_exists({}, {})", name, name, value)
+                    } else {
+                        format!("{} = {}", name, value)
+                    }
+                })
+                .collect();
+            clauses.push(format!("SET {}", actions.join(", ")));
+        }
+
+        if !self.remove.is_empty() {
+            let names: Vec<String> = self.remove.iter().map(|name| writer.name(name)).collect();
+            clauses.push(format!("REMOVE {}", names.join(", ")));
+        }
+
+        if !self.add.is_empty() {
+            let actions: Vec<String> = self
+                .add
+                .into_iter()
+                .map(|(name, value)| format!("{} {}", writer.name(&name), writer.value(value)))
+                .collect();
+            clauses.push(format!("ADD {}", actions.join(", ")));
+        }
+
+        writer.finish(clauses.join(" "))
+    }
+}
+
+/// The attributes to read e.g Projection::of(&["id", "email"])
+#[derive(Debug, Clone)]
+pub struct Projection {
+    names: Vec<String>,
+}
+
+impl Projection {
+    pub fn of(names: &[&str]) -> Self {
+        Self {
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    pub fn build(self) -> Expression {
+        let mut writer = ExpressionWriter::new("p");
+        let names: Vec<String> = self.names.iter().map(|name| writer.name(name)).collect();
+        writer.finish(names.join(", "))
+    }
+}
+
+/// Optional parts of a Query or a Scan
+#[derive(Debug, Default, Clone)]
+pub struct ReadOptions {
+    /// Reads a global or local secondary index instead of the table
+    pub index_name: Option<String>,
+    /// Applied after the items are read, so a page can come back short
+    pub filter: Option<Expression>,
+    /// Attributes to read, T must be able to deserialize from them
+    pub projection: Option<Expression>,
+    pub limit: Option<i32>,
+    pub exclusive_start_key: Option<DynamoItem>,
+}
+
+/// Most keys a single BatchGetItem can read
+pub const BATCH_GET_LIMIT: usize = 100;
+/// Most puts and deletes a single BatchWriteItem can write
+pub const BATCH_WRITE_LIMIT: usize = 25;
+/// Requests sent for a chunk before its unprocessed keys or items are reported as throttled
+const BATCH_MAX_ATTEMPTS: u32 = 8;
+const BATCH_BASE_DELAY: Duration = Duration::from_millis(50);
+const BATCH_MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// A DynamoDB table holding items of type T
+/// Items are (de)serialized with serde_dynamo and sdk errors are converted with RepositoryError,
+/// so that repositories only deal with their models
+/// Several tables can share the same DynamoDB table e.g to read a projection of an index into a smaller type
+pub struct DynamoTable<T> {
+    client: Client,
+    table_name: String,
+    key_schema: KeySchema,
+    consistent_reads: bool,
+    model: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for DynamoTable<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            table_name: self.table_name.clone(),
+            key_schema: self.key_schema.clone(),
+            consistent_reads: self.consistent_reads,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<T> DynamoTable<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(client: Client, table_name: &str, key_schema: KeySchema) -> Self {
+        Self {
+            client,
+            table_name: table_name.to_string(),
+            key_schema,
+            consistent_reads: false,
+            model: PhantomData,
+        }
+    }
+
+    /// Makes gets, queries and scans of the table strongly consistent, reads of an index never are
+    pub fn with_consistent_reads(mut self, consistent_reads: bool) -> Self {
+        self.consistent_reads = consistent_reads;
+        self
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    pub fn key_schema(&self) -> &KeySchema {
+        &self.key_schema
+    }
+
+    /// Shortcut for key_schema().key(), for tables without a sort key
+    pub fn key(&self, partition: impl IntoAttributeValue) -> DynamoItem {
+        self.key_schema.key(partition)
+    }
+
+    pub async fn get(&self, key: DynamoItem) -> AppResult<Option<T>> {
+        let res = record_dynamodb_call(
+            "GetItem",
+            &self.table_name,
+            self.client
+                .get_item()
+                .table_name(&self.table_name)
+                .set_key(Some(key))
+                .consistent_read(self.consistent_reads)
+                .send(),
+        )
+        .await
+        .map_err(|e| self.sdk_error(e))?;
+
+        res.item.map(|item| self.from_item(item)).transpose()
+    }
+
+    /// Fails with RepositoryError::ConditionalCheckFailed if the condition is not met
+    pub async fn put(&self, item: &T, condition: Option<Expression>) -> AppResult<()> {
+        let mut placeholders = Placeholders::default();
+        let condition = placeholders.add(condition);
+
+        record_dynamodb_call(
+            "PutItem",
+            &self.table_name,
+            self.client
+                .put_item()
+                .table_name(&self.table_name)
+                .set_item(Some(self.to_item(item)?))
+                .set_condition_expression(condition)
+                .set_expression_attribute_names(placeholders.names())
+                .set_expression_attribute_values(placeholders.values())
+                .send(),
+        )
+        .await
+        .map_err(|e| self.sdk_error(e))?;
+
+        Ok(())
+    }
+
+    /// Fails with RepositoryError::ConditionalCheckFailed if the condition is not met
+    /// Without a condition on the key, updating a missing item creates it
+    pub async fn update(
+        &self,
+        key: DynamoItem,
+        update: Expression,
+        condition: Option<Expression>,
+    ) -> AppResult<()> {
+        let mut placeholders = Placeholders::default();
+        let update = placeholders.add(Some(update));
+        let condition = placeholders.add(condition);
+
+        record_dynamodb_call(
+            "UpdateItem",
+            &self.table_name,
+            self.client
+                .update_item()
+                .table_name(&self.table_name)
+                .set_key(Some(key))
+                .set_update_expression(update)
+                .set_condition_expression(condition)
+                .set_expression_attribute_names(placeholders.names())
+                .set_expression_attribute_values(placeholders.values())
+                .send(),
+        )
+        .await
+        .map_err(|e| self.sdk_error(e))?;
+
+        Ok(())
+    }
+
+    /// Returns the deleted item, None if there was nothing to delete
+    /// Fails with RepositoryError::ConditionalCheckFailed if the condition is not met
+    pub async fn delete(
+        &self,
+        key: DynamoItem,
+        condition: Option<Expression>,
+    ) -> AppResult<Option<T>> {
+        let mut placeholders = Placeholders::default();
+        let condition = placeholders.add(condition);
+
+        let res = record_dynamodb_call(
+            "DeleteItem",
+            &self.table_name,
+            self.client
+                .delete_item()
+                .table_name(&self.table_name)
+                .set_key(Some(key))
+                .set_condition_expression(condition)
+                .set_expression_attribute_names(placeholders.names())
+                .set_expression_attribute_values(placeholders.values())
+                .return_values(ReturnValue::AllOld)
+                .send(),
+        )
+        .await
+        .map_err(|e| self.sdk_error(e))?;
+
+        res.attributes.map(|item| self.from_item(item)).transpose()
+    }
+
+    /// Reads a single page of the items matching the key condition
+    pub async fn query(
+        &self,
+        key_condition: Expression,
+        options: ReadOptions,
+    ) -> AppResult<DynamoPage<T>> {
+        let mut placeholders = Placeholders::default();
+        let key_condition = placeholders.add(Some(key_condition));
+        let filter = placeholders.add(options.filter);
+        let projection = placeholders.add(options.projection);
+        // Secondary indexes are only ever eventually consistent
+        let consistent_read = self.consistent_reads && options.index_name.is_none();
+
+        let res = record_dynamodb_call(
+            "Query",
+            &self.table_name,
+            self.client
+                .query()
+                .table_name(&self.table_name)
+                .set_index_name(options.index_name)
+                .set_key_condition_expression(key_condition)
+                .set_filter_expression(filter)
+                .set_projection_expression(projection)
+                .set_expression_attribute_names(placeholders.names())
+                .set_expression_attribute_values(placeholders.values())
+                .set_limit(options.limit)
+                .set_exclusive_start_key(options.exclusive_start_key)
+                .consistent_read(consistent_read)
+                .send(),
+        )
+        .await
+        .map_err(|e| self.sdk_error(e))?;
+
+        DynamoPage {
+            items: res.items.unwrap_or_default(),
+            last_evaluated_key: res.last_evaluated_key,
+        }
+        .map_items(|item| self.from_item(item))
+    }
+
+    /// Reads a single page of the table, items are not in any particular order
+    pub async fn scan(&self, options: ReadOptions) -> AppResult<DynamoPage<T>> {
+        let mut placeholders = Placeholders::default();
+        let filter = placeholders.add(options.filter);
+        let projection = placeholders.add(options.projection);
+        let consistent_read = self.consistent_reads && options.index_name.is_none();
+
+        let res = record_dynamodb_call(
+            "Scan",
+            &self.table_name,
+            self.client
+                .scan()
+                .table_name(&self.table_name)
+                .set_index_name(options.index_name)
+                .set_filter_expression(filter)
+                .set_projection_expression(projection)
+                .set_expression_attribute_names(placeholders.names())
+                .set_expression_attribute_values(placeholders.values())
+                .set_limit(options.limit)
+                .set_exclusive_start_key(options.exclusive_start_key)
+                .consistent_read(consistent_read)
+                .send(),
+        )
+        .await
+        .map_err(|e| self.sdk_error(e))?;
+
+        DynamoPage {
+            items: res.items.unwrap_or_default(),
+            last_evaluated_key: res.last_evaluated_key,
+        }
+        .map_items(|item| self.from_item(item))
+    }
+
+    /// Reads the items with the given keys, in chunks of BATCH_GET_LIMIT keys
+    /// The result is in the same order as the keys, with None for the keys that do not exist
+    /// Keys DynamoDB leaves unprocessed, e.g because the table is throttled, are retried with
+    /// jittered exponential backoff, see backoff_delay
+    pub async fn batch_get(&self, keys: Vec<DynamoItem>) -> AppResult<Vec<Option<T>>>
+    where
+        T: Clone,
+    {
+        // A request cannot ask for the same key twice
+        let mut unique_keys = vec![];
+        let mut seen = HashSet::new();
+        for key in &keys {
+            if seen.insert(key_to_json(key).to_string()) {
+                unique_keys.push(key.clone());
+            }
+        }
+
+        let mut found = HashMap::new();
+        for chunk in unique_keys.chunks(BATCH_GET_LIMIT) {
+            let mut pending = KeysAndAttributes::builder()
+                .set_keys(Some(chunk.to_vec()))
+                .consistent_read(self.consistent_reads)
+                .build();
+
+            for attempt in 0.. {
+                let res = record_dynamodb_call(
+                    "BatchGetItem",
+                    &self.table_name,
+                    self.client
+                        .batch_get_item()
+                        .request_items(&self.table_name, pending)
+                        .send(),
+                )
+                .await
+                .map_err(|e| self.sdk_error(e))?;
+
+                let items = res
+                    .responses
+                    .and_then(|mut responses| responses.remove(&self.table_name))
+                    .unwrap_or_default();
+                for item in items {
+                    let key = key_to_json(&self.key_schema.key_of(&item)).to_string();
+                    found.insert(key, self.from_item(item)?);
+                }
+
+                match res
+                    .unprocessed_keys
+                    .and_then(|mut unprocessed| unprocessed.remove(&self.table_name))
+                {
+                    Some(unprocessed)
+                        if unprocessed.keys().is_some_and(|keys| !keys.is_empty()) =>
+                    {
+                        self.backoff("BatchGetItem", attempt).await?;
+                        pending = unprocessed;
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        Ok(keys
+            .iter()
+            .map(|key| found.get(&key_to_json(key).to_string()).cloned())
+            .collect())
+    }
+
+    /// Writes the items in chunks of BATCH_WRITE_LIMIT, replacing any existing item with the same key
+    /// Batch writes cannot have conditions, use put or a transaction when the write must be checked
+    /// A chunk cannot write the same key twice, and chunks are not written atomically
+    pub async fn batch_put(&self, items: &[T]) -> AppResult<()> {
+        let writes = items
+            .iter()
+            .map(|item| {
+                let put = PutRequest::builder()
+                    .set_item(Some(self.to_item(item)?))
+                    .build();
+                Ok(WriteRequest::builder().put_request(put).build())
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+
+        self.batch_write(writes).await
+    }
+
+    /// Deletes the items with the given keys in chunks of BATCH_WRITE_LIMIT, missing items are ignored
+    pub async fn batch_delete(&self, keys: Vec<DynamoItem>) -> AppResult<()> {
+        let writes = keys
+            .into_iter()
+            .map(|key| {
+                let delete = DeleteRequest::builder().set_key(Some(key)).build();
+                WriteRequest::builder().delete_request(delete).build()
+            })
+            .collect();
+
+        self.batch_write(writes).await
+    }
+
+    /// Unprocessed items are retried the same way as the unprocessed keys of batch_get
+    async fn batch_write(&self, writes: Vec<WriteRequest>) -> AppResult<()> {
+        for chunk in writes.chunks(BATCH_WRITE_LIMIT) {
+            let mut pending = chunk.to_vec();
+
+            for attempt in 0.. {
+                let res = record_dynamodb_call(
+                    "BatchWriteItem",
+                    &self.table_name,
+                    self.client
+                        .batch_write_item()
+                        .request_items(&self.table_name, pending)
+                        .send(),
+                )
+                .await
+                .map_err(|e| self.sdk_error(e))?;
+
+                match res
+                    .unprocessed_items
+                    .and_then(|mut unprocessed| unprocessed.remove(&self.table_name))
+                {
+                    Some(unprocessed) if !unprocessed.is_empty() => {
+                        self.backoff("BatchWriteItem", attempt).await?;
+                        pending = unprocessed;
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits before retrying what a batch left unprocessed, or gives up once the attempts are exhausted
+    async fn backoff(&self, operation: &str, attempt: u32) -> AppResult<()> {
+        if attempt + 1 >= BATCH_MAX_ATTEMPTS {
+            error!(
+                "{} on {} still has unprocessed requests after {} attempts",
+                operation, self.table_name, BATCH_MAX_ATTEMPTS
+            );
+            return Err(RepositoryError::Throttled.into());
+        }
+
+        tokio::time::sleep(backoff_delay(attempt)).await;
+        Ok(())
+    }
+
+    /// Serializes a model e.g to write it as part of a transaction
+    pub fn to_item(&self, item: &T) -> AppResult<DynamoItem> {
+        Ok(serde_dynamo::to_item(item)?)
+    }
+
+    pub fn from_item(&self, item: DynamoItem) -> AppResult<T> {
+        serde_dynamo::from_item(item).map_err(|e| {
+            error!(
+                "Error while converting dynamo item into {} model: {}",
+                model_name::<T>(),
+                e
+            );
+            AppError::SerdeDynamoError(e)
+        })
+    }
+
+    fn sdk_error<E, R>(&self, error: SdkError<E, R>) -> AppError
+    where
+        E: aws_sdk_dynamodb::error::ProvideErrorMetadata + Debug,
+        R: Debug,
+    {
+        RepositoryError::from_sdk_error(error, &self.table_name).into()
+    }
+}
+
+/// Full jitter exponential backoff, a random delay of up to BATCH_BASE_DELAY * 2^attempt,
+/// capped at BATCH_MAX_DELAY, so that throttled clients do not all retry at once
+fn backoff_delay(attempt: u32) -> Duration {
+    let ceiling = BATCH_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(BATCH_MAX_DELAY);
+
+    rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+}
+
+/// Name of the model without its module path e.g User
+fn model_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Turns a LastEvaluatedKey into a cursor that can be handed to clients and back
+/// The cursor is the key signed with Signer, i.e its base64 encoding followed by an HMAC-SHA256 signature,
+/// so it is tamper-proof but not confidential: clients cannot forge a key of their own,
+/// but anyone can decode the key, so only use it on tables whose keys are not secret
+/// Every instance serving the same API must be given the same secret
+#[derive(Clone)]
+pub struct PageCursorCodec {
+    signer: Signer,
+}
+
+impl PageCursorCodec {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            signer: Signer::new(secret),
+        }
+    }
+
+    pub fn encode(&self, key: &DynamoItem) -> String {
+        self.signer.sign(&key_to_json(key))
+    }
+
+    pub fn decode(&self, cursor: &str) -> AppResult<DynamoItem> {
+        self.signer
+            .verify(cursor)
+            .and_then(|json| key_from_json(&json))
+            .ok_or_else(|| AppError::BadRequest("Invalid cursor".to_string()))
+    }
+}
+
+/// Keys can only be made of strings, numbers or binaries, so we only need to support those
+/// They are written in DynamoDB's own JSON format e.g {"id":{"S":"ppId123"}} to keep their types
+fn key_to_json(key: &DynamoItem) -> Value {
+    let attributes = key
+        .iter()
+        .filter_map(|(name, value)| {
+            let value = match value {
+                AttributeValue::S(s) => json!({ "S": s }),
+                AttributeValue::N(n) => json!({ "N": n }),
+                AttributeValue::B(b) => json!({ "B": URL_SAFE_NO_PAD.encode(b.as_ref()) }),
+                _ => return None,
+            };
+            Some((name.clone(), value))
+        })
+        .collect::<Map<_, _>>();
+
+    Value::Object(attributes)
+}
+
+fn key_from_json(json: &Value) -> Option<DynamoItem> {
+    json.as_object()?
+        .iter()
+        .map(|(name, value)| {
+            let (data_type, value) = value.as_object()?.iter().next()?;
+            let value = value.as_str()?;
+            let value = match data_type.as_str() {
+                "S" => AttributeValue::S(value.to_string()),
+                "N" => AttributeValue::N(value.to_string()),
+                "B" => AttributeValue::B(Blob::new(URL_SAFE_NO_PAD.decode(value).ok()?)),
+                _ => return None,
+            };
+            Some((name.clone(), value))
+        })
+        .collect()
+}
+
 /// Helper function to help log the errors from dynamodb sdk
-pub fn log_sdk_error<T>(error: SdkError<T>)
+pub fn log_sdk_error<T, R>(error: SdkError<T, R>)
 where
     T: Debug,
+    R: Debug,
 {
     match error {
         // The request failed during construction. It was not dispatched over the network.
@@ -70,4 +1400,425 @@ where
             error!("Error while ingesting: {:?}", error);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+
+    use aws_sdk_dynamodb::config::{retry::RetryConfig, Credentials, Region};
+    use aws_sdk_dynamodb::{primitives::Blob, types::AttributeValue, Client};
+    use aws_smithy_http::{body::SdkBody, result::ConnectorError};
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::errors::AppError;
+    use crate::repositories::repository_error::RepositoryError;
+    use crate::utils::dynamodb_helpers::{
+        backoff_delay, get_attribute, AttributeValueError, Condition, DynamoItem, DynamoTable,
+        Expression, FromAttributeValue, IntoAttributeValue, KeyCondition, KeySchema, List,
+        Placeholders, Projection, UpdateExpression,
+    };
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        id: String,
+    }
+
+    /// Bodies of the requests sent to the stubbed client, in order
+    type Requests = Arc<Mutex<Vec<Value>>>;
+
+    /// A users table whose client answers each request with `respond(request, number of the request)`
+    /// instead of calling DynamoDB, requests and responses are DynamoDB's JSON bodies
+    fn stub_table(
+        respond: impl Fn(&Value, usize) -> Value + Send + Sync + 'static,
+    ) -> (DynamoTable<Item>, Requests) {
+        let requests = Requests::default();
+        let recorded = requests.clone();
+        let respond = Arc::new(respond);
+        let connector = tower::service_fn(move |request: http::Request<SdkBody>| {
+            let body: Value = serde_json::from_slice(request.body().bytes().unwrap()).unwrap();
+            let mut requests = recorded.lock().unwrap();
+            let response = respond(&body, requests.len());
+            requests.push(body);
+
+            let response = http::Response::new(SdkBody::from(response.to_string()));
+            std::future::ready(Ok::<_, ConnectorError>(response))
+        });
+        let config = aws_sdk_dynamodb::Config::builder()
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("key", "secret", None, None, "test"))
+            .retry_config(RetryConfig::disabled())
+            .http_connector(connector)
+            .build();
+
+        let table = DynamoTable::new(Client::from_conf(config), "users", KeySchema::new("id"));
+        (table, requests)
+    }
+
+    fn request_items(request: &Value, operation: &str) -> Vec<Value> {
+        let items = match operation {
+            "get" => &request["RequestItems"]["users"]["Keys"],
+            _ => &request["RequestItems"]["users"],
+        };
+        items.as_array().unwrap().clone()
+    }
+
+    fn round_trip<T>(value: T)
+    where
+        T: IntoAttributeValue + FromAttributeValue + Clone + PartialEq + std::fmt::Debug,
+    {
+        assert_eq!(T::from_av(&value.clone().into_av()), Ok(value));
+    }
+
+    #[test]
+    fn key_schema_builds_and_extracts_keys() {
+        // Arrange
+        let key_schema = KeySchema::new("user_id").with_sort_key("created_at");
+        let mut item = key_schema.composite_key("ppId123".to_string(), 1700000000u64);
+        item.insert("bio".to_string(), "I love to eat".to_string().into_av());
+
+        // Act
+        let key = key_schema.key_of(&item);
+
+        // Assert
+        assert_eq!(
+            key,
+            DynamoItem::from([
+                ("user_id".to_string(), "ppId123".to_string().into_av()),
+                ("created_at".to_string(), 1700000000u64.into_av()),
+            ])
+        );
+    }
+
+    #[test]
+    fn placeholders_of_every_expression_are_merged() {
+        // Arrange
+        let mut placeholders = Placeholders::default();
+        let update = Expression::new("SET #bio = :bio")
+            .with_name("#bio", "bio")
+            .with_value(":bio", "I love to eat".to_string());
+        let condition = Expression::new("#version = :version")
+            .with_name("#version", "version")
+            .with_value(":version", 3u64);
+
+        // Act
+        let update = placeholders.add(Some(update));
+        let condition = placeholders.add(Some(condition));
+        let filter = placeholders.add(None);
+
+        // Assert
+        assert_eq!(update.as_deref(), Some("SET #bio = :bio"));
+        assert_eq!(condition.as_deref(), Some("#version = :version"));
+        assert_eq!(filter, None);
+        assert_eq!(placeholders.names().unwrap().len(), 2);
+        assert_eq!(placeholders.values().unwrap().len(), 2);
+        // DynamoDB rejects empty maps, they are left out instead
+        assert_eq!(placeholders.names(), None);
+    }
+
+    #[test]
+    fn condition_renders_nested_expressions_with_placeholders() {
+        // Arrange
+        let condition = Condition::attribute_exists("id").and(
+            Condition::begins_with("name", "pp".to_string()).or(!Condition::gt("version", 3u64)),
+        );
+
+        // Act
+        let expression = condition.build();
+
+        // Assert
+        assert_eq!(
+            expression.expression,
+            "(attribute_exists(#id)) AND ((begins_with(#name, :c0)) OR (NOT (#version > :c1)))"
+        );
+        assert_eq!(
+            expression.names,
+            HashMap::from([
+                ("#id".to_string(), "id".to_string()),
+                ("#name".to_string(), "name".to_string()),
+                ("#version".to_string(), "version".to_string()),
+            ])
+        );
+        assert_eq!(
+            expression.values,
+            DynamoItem::from([
+                (":c0".to_string(), "pp".to_string().into_av()),
+                (":c1".to_string(), 3u64.into_av()),
+            ])
+        );
+    }
+
+    #[test]
+    fn update_expression_renders_every_clause() {
+        // Arrange
+        let update = UpdateExpression::new()
+            .set("bio", "I love to eat".to_string())
+            .set_if_not_exists("revoked_at", "2024-01-01T00:00:00Z".to_string())
+            .remove("image")
+            .remove("deleted_at")
+            .add("login_count", 1u64);
+
+        // Act
+        let expression = update.build();
+
+        // Assert
+        assert_eq!(
+            expression.expression,
+            "SET #bio = :u0, #revoked_at = if_not_exists(#revoked_at, :u1) \
+             REMOVE #image, #deleted_at ADD #login_count :u2"
+        );
+        assert_eq!(expression.values.len(), 3);
+    }
+
+    #[test]
+    fn expressions_of_a_request_do_not_clash() {
+        // Arrange
+        let mut placeholders = Placeholders::default();
+        let update = UpdateExpression::new().set("version", 4u64).build();
+        let condition = Condition::eq("version", 3u64).build();
+        let key_condition = KeyCondition::partition("email", "pp@gmail.com".to_string())
+            .with_sort(Condition::between("created_at", 1u64, 2u64))
+            .build();
+        let projection = Projection::of(&["id", "user-name"]).build();
+
+        // Act
+        placeholders.add(Some(update));
+        placeholders.add(Some(condition));
+        let key_condition = placeholders.add(Some(key_condition));
+        let projection = placeholders.add(Some(projection));
+        let values = placeholders.values().unwrap();
+
+        // Assert
+        assert_eq!(
+            key_condition.as_deref(),
+            Some("(#email = :k0) AND (#created_at BETWEEN :k1 AND :k2)")
+        );
+        assert_eq!(projection.as_deref(), Some("#id, #user_2d_name"));
+        assert_eq!(values[":u0"], 4u64.into_av());
+        assert_eq!(values[":c0"], 3u64.into_av());
+        assert_eq!(values.len(), 5);
+    }
+
+    #[test]
+    fn attribute_values_round_trip() {
+        // Arrange, Act and Assert
+        round_trip(-12i8);
+        round_trip(i128::MIN);
+        round_trip(u64::MAX);
+        round_trip(usize::MAX);
+        round_trip(1.5f32);
+        round_trip(-0.1f64);
+        round_trip(true);
+        round_trip("pp".to_string());
+        round_trip(Blob::new(vec![0, 255]));
+        round_trip(Some(3u8));
+        round_trip(None::<String>);
+        round_trip(vec![0u8, 255]);
+        round_trip(List(vec![Some(1u32), None]));
+        round_trip(HashMap::from([("a".to_string(), List(vec![true]))]));
+        round_trip(HashSet::from(["a".to_string(), "b".to_string()]));
+        round_trip(HashSet::from([1i64, -1]));
+        round_trip(uuid::Uuid::new_v4());
+        round_trip(chrono::DateTime::<chrono::Utc>::from_timestamp(1_700_000_000, 5).unwrap());
+        round_trip(time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap());
+    }
+
+    #[test]
+    fn timestamps_outside_of_rfc_3339_years_are_written_with_an_expanded_year() {
+        // Arrange
+        let timestamp = time::Date::from_calendar_date(10000, time::Month::January, 1)
+            .unwrap()
+            .midnight()
+            .assume_utc();
+
+        // Act
+        let value = timestamp.into_av();
+
+        // Assert
+        assert_eq!(value, AttributeValue::S("+010000-01-01T00:00:00.000000000Z".to_string()));
+        assert_eq!(time::OffsetDateTime::from_av(&value), Ok(timestamp));
+        round_trip(timestamp.replace_year(-1).unwrap());
+    }
+
+    #[test]
+    fn attribute_values_are_written_as_dynamodb_types() {
+        // Arrange, Act and Assert
+        assert_eq!(7u16.into_av(), AttributeValue::N("7".to_string()));
+        assert_eq!("pp".into_av(), AttributeValue::S("pp".to_string()));
+        assert_eq!(None::<u8>.into_av(), AttributeValue::Null(true));
+        assert_eq!(vec![1u8].into_av(), AttributeValue::B(Blob::new(vec![1])));
+        assert_eq!(
+            List(vec![1u8]).into_av(),
+            AttributeValue::L(vec![AttributeValue::N("1".to_string())])
+        );
+        assert_eq!(
+            HashSet::from([2u8]).into_av(),
+            AttributeValue::Ns(vec!["2".to_string()])
+        );
+        assert_eq!(
+            chrono::DateTime::<chrono::Utc>::from_timestamp(0, 0)
+                .unwrap()
+                .into_av(),
+            AttributeValue::S("1970-01-01T00:00:00+00:00".to_string())
+        );
+    }
+
+    #[test]
+    fn mismatched_attribute_values_are_described() {
+        // Arrange
+        let item: DynamoItem = HashMap::from([
+            ("age".to_string(), "old".into_av()),
+            ("tags".to_string(), List(vec!["a"]).into_av()),
+            ("nickname".to_string(), None::<String>.into_av()),
+        ]);
+
+        // Act
+        let age = get_attribute::<u8>(&item, "age").unwrap_err();
+        let tags = get_attribute::<List<u8>>(&item, "tags").unwrap_err();
+        let id = get_attribute::<String>(&item, "id").unwrap_err();
+        let nickname = get_attribute::<Option<String>>(&item, "nickname");
+        let bio = get_attribute::<Option<String>>(&item, "bio");
+        let overflow = u8::from_av(&300u16.into_av()).unwrap_err();
+
+        // Assert
+        assert_eq!(
+            age.to_string(),
+            "Attribute age: Expected a number (N) but found a string (S)"
+        );
+        assert_eq!(
+            tags.to_string(),
+            "Attribute tags: Expected a number (N) but found a string (S)"
+        );
+        assert_eq!(id, AttributeValueError::MissingAttribute("id".to_string()));
+        assert_eq!(nickname, Ok(None));
+        assert_eq!(bio, Ok(None));
+        assert_eq!(
+            overflow.to_string(),
+            "Expected u8 but found \"300\": number too large to fit in target type"
+        );
+    }
+
+    #[test]
+    fn backoff_delay_grows_exponentially_up_to_a_cap() {
+        // Arrange
+        let attempts = [(0, 50), (1, 100), (3, 400), (10, 2000), (40, 2000)];
+
+        for (attempt, ceiling) in attempts {
+            // Act
+            let delays: Vec<u128> = (0..100)
+                .map(|_| backoff_delay(attempt).as_millis())
+                .collect();
+
+            // Assert
+            assert!(delays.iter().all(|delay| *delay <= ceiling), "{}", attempt);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_get_chunks_retries_and_keeps_the_order_of_the_keys() {
+        // Arrange
+        // The first request leaves its first key unprocessed, items come back in reverse order
+        // and the item with the id "missing" does not exist
+        let (table, requests) = stub_table(|request, number| {
+            let mut keys = request_items(request, "get");
+            keys.retain(|key| key["id"]["S"] != "missing");
+            let unprocessed = match number {
+                0 => vec![keys.remove(0)],
+                _ => vec![],
+            };
+            keys.reverse();
+            json!({
+                "Responses": { "users": keys },
+                "UnprocessedKeys": { "users": { "Keys": unprocessed } },
+            })
+        });
+        let mut ids: Vec<String> = (0..150).map(|index| format!("id{}", index)).collect();
+        ids.push("missing".to_string());
+        ids.push("id0".to_string());
+        let keys = ids.iter().map(|id| table.key(id.clone())).collect();
+
+        // Act
+        let items = table.batch_get(keys).await.unwrap();
+
+        // Assert
+        let sizes: Vec<usize> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request_items(request, "get").len())
+            .collect();
+        assert_eq!(sizes, vec![100, 1, 51]);
+        let expected: Vec<Option<Item>> = ids
+            .iter()
+            .map(|id| (id != "missing").then(|| Item { id: id.clone() }))
+            .collect();
+        assert_eq!(items, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_put_chunks_and_retries_unprocessed_items() {
+        // Arrange
+        // The first request leaves its last two puts unprocessed
+        let (table, requests) = stub_table(|request, number| {
+            let mut writes = request_items(request, "put");
+            let unprocessed = match number {
+                0 => writes.split_off(writes.len() - 2),
+                _ => vec![],
+            };
+            json!({ "UnprocessedItems": { "users": unprocessed } })
+        });
+        let items: Vec<Item> = (0..30)
+            .map(|index| Item {
+                id: format!("id{}", index),
+            })
+            .collect();
+
+        // Act
+        table.batch_put(&items).await.unwrap();
+
+        // Assert
+        let requests = requests.lock().unwrap();
+        let written: Vec<Vec<&str>> = requests
+            .iter()
+            .map(|request| {
+                request["RequestItems"]["users"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|write| write["PutRequest"]["Item"]["id"]["S"].as_str().unwrap())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            written.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![25, 2, 5]
+        );
+        assert_eq!(written[1], vec!["id23", "id24"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_delete_gives_up_when_items_stay_unprocessed() {
+        // Arrange
+        let (table, requests) = stub_table(|request, _| {
+            let unprocessed = request_items(request, "delete");
+            json!({ "UnprocessedItems": { "users": unprocessed } })
+        });
+
+        // Act
+        let res = table.batch_delete(vec![table.key("id0".to_string())]).await;
+
+        // Assert
+        let requests = requests.lock().unwrap();
+        assert!(matches!(
+            res,
+            Err(AppError::RepositoryError(RepositoryError::Throttled))
+        ));
+        assert_eq!(requests.len(), 8);
+        assert_eq!(
+            requests[0]["RequestItems"]["users"][0]["DeleteRequest"]["Key"]["id"]["S"],
+            "id0"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+// Prometheus metrics, exposed in the text format on GET /metrics, see controllers/metrics.rs
+// Requests are labeled by the route template they matched e.g /user/:id rather than the raw path,
+// so that the number of series does not grow with the number of users
+// DynamoDB calls are labeled by operation and by outcome, see RepositoryError::outcome
+// and are traced at the same time, see telemetry::dynamodb_span
+
+use std::{future::Future, sync::LazyLock, time::Instant};
+
+use aws_sdk_dynamodb::error::{ProvideErrorMetadata, SdkError};
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+use tracing::Instrument;
+
+use crate::{repositories::repository_error::RepositoryError, utils::telemetry::dynamodb_span};
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+static HTTP_REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("http_requests_total", "Number of HTTP requests handled"),
+        &["method", "route", "status"],
+    ))
+});
+
+static HTTP_REQUEST_DURATION_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "Time taken to handle HTTP requests"),
+        &["method", "route", "status"],
+    ))
+});
+
+static DYNAMODB_REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("dynamodb_requests_total", "Number of calls made to DynamoDB"),
+        &["operation", "outcome"],
+    ))
+});
+
+static DYNAMODB_REQUEST_DURATION_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("dynamodb_request_duration_seconds", "Time taken by calls to DynamoDB"),
+        &["operation", "outcome"],
+    ))
+});
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("Metric options are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric is only registered once");
+    metric
+}
+
+/// Marks the responses track_metrics has recorded, see track_unmatched_metrics
+#[derive(Clone)]
+struct Recorded;
+
+/// Middleware recording the RED metrics of every request
+/// Must be added with route_layer so that the matched route is known, see track_unmatched_metrics
+/// for the requests that match no route
+pub async fn track_metrics(request: Request, next: Next) -> Response {
+    let started_at = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let mut response = next.run(request).await;
+
+    record_request(&method, &route, &response, started_at);
+    response.extensions_mut().insert(Recorded);
+    response
+}
+
+/// Middleware recording the requests track_metrics never sees because they match no route e.g 404s
+/// Must be added with layer so that it wraps the fallback, they are labeled with route="unmatched"
+pub async fn track_unmatched_metrics(request: Request, next: Next) -> Response {
+    let started_at = Instant::now();
+    let method = request.method().to_string();
+
+    let response = next.run(request).await;
+
+    if response.extensions().get::<Recorded>().is_none() {
+        record_request(&method, "unmatched", &response, started_at);
+    }
+    response
+}
+
+fn record_request(method: &str, route: &str, response: &Response, started_at: Instant) {
+    let status = response.status().as_u16().to_string();
+    let labels = [method, route, status.as_str()];
+    HTTP_REQUESTS_TOTAL.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&labels)
+        .observe(started_at.elapsed().as_secs_f64());
+}
+
+/// Runs a DynamoDB call in its own span and records its outcome and latency
+/// `operation` is the name of the DynamoDB API e.g GetItem, `table` the table it targets
+pub async fn record_dynamodb_call<T, E>(
+    operation: &str,
+    table: &str,
+    call: impl Future<Output = Result<T, SdkError<E>>>,
+) -> Result<T, SdkError<E>>
+where
+    E: ProvideErrorMetadata,
+{
+    let span = dynamodb_span(operation, table);
+    let started_at = Instant::now();
+    let res = call.instrument(span.clone()).await;
+
+    let outcome = match &res {
+        Ok(_) => "success",
+        Err(e) => {
+            let outcome = RepositoryError::classify(e, table).outcome();
+            span.record("otel.status_code", "ERROR");
+            span.record("error.type", outcome);
+            outcome
+        }
+    };
+    DYNAMODB_REQUESTS_TOTAL
+        .with_label_values(&[operation, outcome])
+        .inc();
+    DYNAMODB_REQUEST_DURATION_SECONDS
+        .with_label_values(&[operation, outcome])
+        .observe(started_at.elapsed().as_secs_f64());
+
+    res
+}
+
+/// Renders every metric in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Metrics can be encoded");
+
+    String::from_utf8(buffer).expect("Metrics are valid utf-8")
+}
@@ -1,2 +1,9 @@
 pub mod dynamodb_helpers;
-pub mod openapi_generator;
\ No newline at end of file
+pub mod metrics;
+pub mod openapi_generator;
+pub mod password;
+pub mod request_id;
+pub mod signer;
+pub mod telemetry;
+pub mod tls;
+pub mod validation;
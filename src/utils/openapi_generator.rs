@@ -6,11 +6,34 @@
 
 // For paths, we have to use __path as a prefix to import the handlers
 // see https://github.com/juhaku/utoipa/blob/cea4c50112c6cc0883767a43ff611db367cd13b5/README.md?plain=1#L171
-use crate::controllers::health::__path_get_health_check;
-use crate::controllers::user_controller::__path_get_current_user;
-use crate::domain::user::view_models::UserViewModel;
-use utoipa::openapi::{OpenApiBuilder, ServerBuilder};
-use utoipa::OpenApi;
+use crate::controllers::api_key_controller::{
+    __path_create_api_key, __path_list_api_keys, __path_revoke_api_key,
+};
+use crate::controllers::auth_controller::{__path_login, __path_logout, __path_refresh};
+use crate::controllers::health::{__path_get_health_check, __path_get_liveness, __path_get_readiness};
+use crate::controllers::metrics::__path_get_metrics;
+use crate::controllers::oidc_controller::{__path_oidc_callback, __path_oidc_login};
+use crate::controllers::user_controller::{
+    __path_batch_get_users, __path_create_user, __path_delete_user, __path_get_current_user, __path_get_user,
+    __path_get_user_by_email, __path_get_user_by_username, __path_list_users, __path_restore_user, __path_update_user,
+};
+use crate::domain::api_key::view_models::{
+    ApiKeyViewModel, CreateApiKeyViewModel, CreatedApiKeyViewModel,
+};
+use crate::domain::auth::models::Permission;
+use crate::domain::auth::view_models::{LoginViewModel, RefreshTokenViewModel, TokenViewModel};
+use crate::domain::health::view_models::{
+    ComponentHealthViewModel, ComponentStatus, HealthStatus, HealthViewModel,
+};
+use crate::domain::user::models::Role;
+use crate::domain::user::view_models::{
+    BatchGetUsersResultViewModel, BatchGetUsersViewModel, CreateUserViewModel,
+    UpdateUserViewModel, UserPage, UserViewModel,
+};
+use crate::errors::{ApiError, ErrorCode, FieldError, ProblemDetails, PROBLEM_JSON};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{Content, OpenApiBuilder, Ref, RefOr, Response, ResponseBuilder, ServerBuilder};
+use utoipa::{Modify, OpenApi};
 
 // We use the OpenApi macro to generate the openapi documentation
 // For our example spec to fulfill and use the usage of postman-contract-test-generator
@@ -18,18 +41,106 @@ use utoipa::OpenApi;
 // servers, components, info description, paths, tags
 #[derive(OpenApi)]
 #[openapi(
-    components(schemas(UserViewModel)),
+    components(schemas(
+        UserViewModel, Role, UserPage, CreateUserViewModel, UpdateUserViewModel,
+        BatchGetUsersViewModel, BatchGetUsersResultViewModel,
+        LoginViewModel, RefreshTokenViewModel, TokenViewModel,
+        Permission, ApiKeyViewModel, CreateApiKeyViewModel, CreatedApiKeyViewModel,
+        HealthViewModel, HealthStatus, ComponentHealthViewModel, ComponentStatus,
+        ApiError, ProblemDetails, ErrorCode, FieldError,
+    )),
+    modifiers(&SecurityAddon, &ErrorResponsesAddon),
     info(description = "This is a sample generated openapi documentation for reference"),
     paths(
-       get_health_check, get_current_user,
+       get_health_check, get_liveness, get_readiness,
+       get_metrics,
+       get_current_user, get_user, get_user_by_email, get_user_by_username, list_users,
+       batch_get_users,
+       create_user, update_user, delete_user, restore_user,
+       login, refresh, logout, oidc_login, oidc_callback,
+       create_api_key, list_api_keys, revoke_api_key,
     ),
     tags(
-        (name = "health", description = "Basic health check to see if the server is up"),
-        (name = "user", description = "Operations about use")
+        (name = "health", description = "Liveness and readiness probes"),
+        (name = "metrics", description = "Prometheus metrics"),
+        (name = "user", description = "Operations about use"),
+        (name = "auth", description = "Log in and out, and refresh tokens, directly or through an OIDC provider"),
+        (name = "api-key", description = "Api keys for service to service callers")
     )
 )]
 pub struct ApiDoc;
 
+// Protected paths reference the bearer_auth and api_key schemes through
+// security(("bearer_auth" = ["users:read"]), ("api_key" = ["users:read"])), either of them is accepted
+// The scopes list the permission the path requires, see domain::auth::models::Permission
+// The schemes themselves have to be registered on the components, which the derive macro cannot do for us
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))),
+        );
+    }
+}
+
+// Error responses are declared on the paths with a status and a description only, e.g
+// (status = 404, description = "User not found"), this fills in their body, which is
+// ApiError or ProblemDetails depending on the Accept header, see errors::negotiate_error_format
+// A reusable response is also registered per error code for clients generating their own specs
+struct ErrorResponsesAddon;
+
+impl ErrorResponsesAddon {
+    fn error_response(description: &str) -> Response {
+        ResponseBuilder::new()
+            .description(description)
+            .content("application/json", Content::new(Ref::from_schema_name("ApiError")))
+            .content(PROBLEM_JSON, Content::new(Ref::from_schema_name("ProblemDetails")))
+            .build()
+    }
+}
+
+impl Modify for ErrorResponsesAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for path_item in openapi.paths.paths.values_mut() {
+            for operation in path_item.operations.values_mut() {
+                for (status, response) in operation.responses.responses.iter_mut() {
+                    let is_error = status.starts_with('4') || status.starts_with('5');
+                    // Some error responses have a body of their own e.g the health checks
+                    if let RefOr::T(response) = response {
+                        if is_error && response.content.is_empty() {
+                            *response = Self::error_response(&response.description);
+                        }
+                    }
+                }
+            }
+        }
+
+        let components = openapi.components.get_or_insert_with(Default::default);
+        for error_code in ErrorCode::ALL {
+            let description = error_code
+                .status_code()
+                .canonical_reason()
+                .unwrap_or_default();
+            components.responses.insert(
+                format!("{:?}", error_code),
+                Self::error_response(description).into(),
+            );
+        }
+    }
+}
+
 pub fn generate_openapi_json(address: String) -> utoipa::openapi::OpenApi {
     // This is the equivalent of the following snippet annotation:
     // However we wannt grab the data from our env to generate the openapi.json file
@@ -46,8 +157,37 @@ pub fn generate_openapi_json(address: String) -> utoipa::openapi::OpenApi {
     let builder: OpenApiBuilder = ApiDoc::openapi().into();
     let openapi = builder.servers(Some(servers)).build();
 
+    // Commit the regenerated file together with the changes to the api, see the test below
     std::fs::write("openapi.json", openapi.to_pretty_json().unwrap())
         .expect("Unable to create file");
 
     openapi
 }
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+    use utoipa::OpenApi;
+
+    use crate::utils::openapi_generator::ApiDoc;
+
+    // openapi.json is only regenerated when the server starts, this catches changes to the api
+    // that were committed without running it, the servers depend on the environment so are left out
+    #[test]
+    fn committed_openapi_json_is_up_to_date() {
+        // Arrange
+        let committed =
+            std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json")).unwrap();
+        let mut committed: Value = serde_json::from_str(&committed).unwrap();
+        committed.as_object_mut().unwrap().remove("servers");
+
+        // Act
+        let generated = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        // Assert
+        assert!(
+            committed == generated,
+            "openapi.json is out of date, run the server to regenerate it"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+// Password hashing with Argon2id
+// Hashes are stored as PHC strings e.g $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+// so the algorithm and its parameters travel with the hash and can be tuned later without a migration
+// Hashing is deliberately slow, so it runs on the blocking thread pool instead of stalling the runtime
+
+use std::sync::OnceLock;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+use crate::errors::AppResult;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Hashes the password with Argon2id and a random salt
+pub async fn hash_password(password: String) -> AppResult<String> {
+    tokio::task::spawn_blocking(move || hash(&password))
+        .await
+        .map_err(anyhow::Error::from)?
+}
+
+/// Checks the password against a PHC string produced by hash_password
+/// Users without a password are checked against a dummy hash so that the response time
+/// does not tell whether the account exists or has a password
+pub async fn verify_password(password: String, password_hash: Option<String>) -> AppResult<bool> {
+    tokio::task::spawn_blocking(move || {
+        let Some(password_hash) = password_hash else {
+            verify(&password, dummy_hash());
+            return false;
+        };
+
+        verify(&password, &password_hash)
+    })
+    .await
+    .map_err(|e| anyhow::Error::from(e).into())
+}
+
+fn hash(password: &str) -> AppResult<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow::anyhow!("Error while hashing password: {}", e).into())
+}
+
+fn verify(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    DUMMY_HASH.get_or_init(|| hash("dummy password").expect("Argon2 accepts any password"))
+}
@@ -0,0 +1,169 @@
+// Request ids let us find every log line of a request, and let support match a customer report to them
+// The id is taken from the X-Request-Id header when the caller sends a sane one, so that it can be followed
+// across services, otherwise a new one is generated. It is echoed in the response and in ApiError bodies
+// Every log line written while handling the request is inside the request span, which carries the id
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{info_span, Instrument};
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longer ids are replaced, they are copied into every log line
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request being handled, None outside of a request
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|request_id| request_id.clone()).ok()
+}
+
+/// Middleware assigning an id to every request
+/// Must be the outermost layer so that errors returned by other middlewares carry the id as well
+pub async fn propagate_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| is_valid(value))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let span = info_span!("request", request_id = %request_id);
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+
+    let value = HeaderValue::from_str(&request_id).expect("Request ids are visible ascii");
+    response.headers_mut().insert(REQUEST_ID_HEADER.clone(), value);
+
+    response
+}
+
+/// Only visible ascii is accepted so that the id cannot break the log format or the response header
+fn is_valid(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LENGTH
+        && request_id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{body::Body, http::Request, middleware, routing::get, Router};
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::{
+        errors::AppError,
+        utils::{
+            request_id::{propagate_request_id, REQUEST_ID_HEADER},
+            telemetry::{log_layer, LogFormat},
+        },
+    };
+
+    /// Collects the log lines written by the subscriber
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn get_router() -> Router {
+        Router::new()
+            .route(
+                "/fail",
+                get(|| async {
+                    tracing::warn!("User lookup failed");
+                    Err::<(), _>(AppError::NotFound("User not found".to_string()))
+                }),
+            )
+            .layer(middleware::from_fn(propagate_request_id))
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed_in_header_and_error_body() {
+        // Arrange
+        let request = Request::builder()
+            .uri("/fail")
+            .header(&REQUEST_ID_HEADER, "support-ticket-1234")
+            .body(Body::empty())
+            .unwrap();
+
+        // Act
+        let response = get_router().oneshot(request).await.unwrap();
+        let header = response.headers()[&REQUEST_ID_HEADER].clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        // Assert
+        assert_eq!(header, "support-ticket-1234");
+        assert_eq!(error["request_id"], "support-ticket-1234");
+    }
+
+    #[tokio::test]
+    async fn json_log_lines_carry_the_request_id() {
+        // Arrange
+        let buffer = SharedBuffer::default();
+        let writer = buffer.clone();
+        let _subscriber = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(log_layer(LogFormat::Json, move || writer.clone())),
+        );
+        let request = Request::builder()
+            .uri("/fail")
+            .header(&REQUEST_ID_HEADER, "support-ticket-1234")
+            .body(Body::empty())
+            .unwrap();
+
+        // Act
+        get_router().oneshot(request).await.unwrap();
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(logs.lines().next().unwrap()).unwrap();
+
+        // Assert
+        assert_eq!(line["fields"]["message"], "User lookup failed");
+        assert_eq!(line["span"]["request_id"], "support-ticket-1234");
+    }
+
+    #[tokio::test]
+    async fn request_id_is_generated_when_missing_or_invalid() {
+        // Arrange
+        let missing = Request::builder().uri("/fail").body(Body::empty()).unwrap();
+        let invalid = Request::builder()
+            .uri("/fail")
+            .header(&REQUEST_ID_HEADER, "a".repeat(129))
+            .body(Body::empty())
+            .unwrap();
+
+        // Act
+        let missing = get_router().oneshot(missing).await.unwrap();
+        let invalid = get_router().oneshot(invalid).await.unwrap();
+
+        // Assert
+        for response in [missing, invalid] {
+            let request_id = response.headers()[&REQUEST_ID_HEADER].to_str().unwrap();
+            assert!(uuid::Uuid::parse_str(request_id).is_ok());
+        }
+    }
+}
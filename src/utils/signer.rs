@@ -0,0 +1,76 @@
+// Signs values so that they can be handed to clients and trusted when they come back
+// e.g the pagination cursors, see dynamodb_helpers::PageCursorCodec, and the OIDC state cookie
+// A signed value is the base64 encoded JSON of the value followed by an HMAC-SHA256 signature of it,
+// `payload.signature`. The signature only prevents forgery, anyone can decode the payload
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::Sha256;
+
+/// Every instance that has to verify the values of another must be given the same secret
+#[derive(Clone)]
+pub struct Signer {
+    secret: Vec<u8>,
+}
+
+impl Signer {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    pub fn sign<T: Serialize>(&self, value: &T) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(value).unwrap_or_default());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(payload.as_bytes()).finalize().into_bytes());
+
+        format!("{}.{}", payload, signature)
+    }
+
+    /// None when the value is malformed or was not signed with our secret
+    pub fn verify<T: DeserializeOwned>(&self, signed: &str) -> Option<T> {
+        let (payload, signature) = signed.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+        // verify_slice compares in constant time
+        self.mac(payload.as_bytes()).verify_slice(&signature).ok()?;
+
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(payload);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use serde_json::{json, Value};
+
+    use crate::utils::signer::Signer;
+
+    #[test]
+    fn verify_rejects_values_signed_with_another_secret_or_tampered_with() {
+        // Arrange
+        let signer = Signer::new("secret");
+        let signed = signer.sign(&json!({ "id": "ppId123" }));
+        let (_, signature) = signed.split_once('.').unwrap();
+        let tampered = format!("{}.{}", URL_SAFE_NO_PAD.encode(r#"{"id":"admin"}"#), signature);
+
+        // Act
+        let verified = signer.verify::<Value>(&signed);
+        let other_secret = Signer::new("not the secret").verify::<Value>(&signed);
+        let tampered = signer.verify::<Value>(&tampered);
+
+        // Assert
+        assert_eq!(verified, Some(json!({ "id": "ppId123" })));
+        assert_eq!(other_secret, None);
+        assert_eq!(tampered, None);
+    }
+}
@@ -0,0 +1,407 @@
+// Logging and OpenTelemetry tracing, wired into the tracing crate through tracing-opentelemetry
+// Log lines are written to stdout as text or as JSON, filtered by RUST_LOG style directives
+// Every request gets a server span which continues the trace of the caller when it sends
+// W3C traceparent/tracestate headers, service and repository methods are instrumented as child spans
+// and each DynamoDB call gets a client span named after its operation, see metrics::record_dynamodb_call
+// Spans are exported over OTLP/HTTP, or written as JSON lines to stdout or a file for local debugging and tests
+
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use futures_util::future::BoxFuture;
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    export::trace::{ExportResult, SpanData, SpanExporter},
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Sampler, TracerProvider},
+    Resource,
+};
+use tracing::{field::Empty, info_span, level_filters::LevelFilter, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    filter::Targets, fmt::MakeWriter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+use crate::{config::AppConfig, utils::request_id};
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// A JSON object per line, with the fields of the spans the line was written in e.g request_id
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_config(app_config: &AppConfig) -> anyhow::Result<Self> {
+        match app_config.log_format.as_deref() {
+            None | Some("text") => Ok(Self::Text),
+            Some("json") => Ok(Self::Json),
+            Some(other) => anyhow::bail!("Unknown LOG_FORMAT {}, expected text or json", other),
+        }
+    }
+}
+
+/// Where spans are sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceExporter {
+    /// To an OTLP/HTTP collector, the endpoint is its base url e.g http://localhost:4318
+    Otlp { endpoint: Option<String> },
+    /// As JSON lines on stdout
+    Stdout,
+    /// As JSON lines appended to a file
+    File(PathBuf),
+}
+
+impl TraceExporter {
+    /// Returns None when tracing is disabled, which is the default
+    pub fn from_config(app_config: &AppConfig) -> anyhow::Result<Option<Self>> {
+        let exporter = match app_config.otel_traces_exporter.as_deref() {
+            None | Some("none") => return Ok(None),
+            Some("otlp") => Self::Otlp {
+                endpoint: app_config.otel_exporter_otlp_endpoint.clone(),
+            },
+            Some("stdout") => Self::Stdout,
+            Some("file") => Self::File(
+                app_config
+                    .otel_traces_file_path
+                    .clone()
+                    .context("OTEL_TRACES_FILE_PATH is required when OTEL_TRACES_EXPORTER is file")?
+                    .into(),
+            ),
+            Some(other) => anyhow::bail!("Unknown OTEL_TRACES_EXPORTER {}, expected none, otlp, stdout or file", other),
+        };
+
+        Ok(Some(exporter))
+    }
+}
+
+/// Keeps the tracer provider around so that buffered spans can be flushed on exit
+pub struct Telemetry {
+    provider: Option<TracerProvider>,
+}
+
+impl Telemetry {
+    pub const DEFAULT_SERVICE_NAME: &'static str = "rust-axum-scaffold";
+    pub const DEFAULT_SAMPLER_RATIO: f64 = 1.0;
+
+    /// Installs the global subscriber, logging to stdout and exporting spans when an exporter is configured
+    /// Lines written with the log crate macros, as most of our code does, are forwarded to the subscriber as well
+    pub fn init(app_config: &AppConfig) -> anyhow::Result<Self> {
+        let log_format = LogFormat::from_config(app_config)?;
+        let log_filter = EnvFilter::try_new(app_config.rust_log.as_deref().unwrap_or("info"))
+            .context("RUST_LOG is not a valid filter")?;
+        let provider = match TraceExporter::from_config(app_config)? {
+            Some(exporter) => Some(tracer_provider(
+                exporter,
+                app_config
+                    .otel_traces_sampler_ratio
+                    .unwrap_or(Self::DEFAULT_SAMPLER_RATIO),
+                app_config
+                    .otel_service_name
+                    .clone()
+                    .unwrap_or_else(|| Self::DEFAULT_SERVICE_NAME.to_string()),
+            )?),
+            None => None,
+        };
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        tracing_subscriber::registry()
+            .with(log_layer(log_format, io::stdout).with_filter(log_filter))
+            .with(provider.as_ref().map(layer))
+            .try_init()?;
+
+        Ok(Self { provider })
+    }
+
+    /// Exports the spans that are still buffered, must be called from the tokio runtime
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Error while shutting down the tracer provider: {}", e);
+            }
+        }
+    }
+}
+
+/// Builds a tracer provider sampling `sampler_ratio` of the traces we start
+/// Traces started by a caller are sampled the way the caller decided, as told by the traceparent flags
+pub fn tracer_provider(
+    exporter: TraceExporter,
+    sampler_ratio: f64,
+    service_name: String,
+) -> anyhow::Result<TracerProvider> {
+    let builder = TracerProvider::builder()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            sampler_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new("service.name", service_name)]));
+
+    let builder = match exporter {
+        TraceExporter::Otlp { endpoint } => {
+            let mut otlp = opentelemetry_otlp::SpanExporter::builder().with_http();
+            if let Some(endpoint) = endpoint {
+                otlp = otlp.with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')));
+            }
+            builder.with_batch_exporter(otlp.build()?, runtime::Tokio)
+        }
+        // Written as soon as they end, so that they can be read back straight away
+        TraceExporter::Stdout => builder.with_simple_exporter(JsonLinesExporter::new(io::stdout())),
+        TraceExporter::File(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open traces file {}", path.display()))?;
+            builder.with_simple_exporter(JsonLinesExporter::new(file))
+        }
+    };
+
+    Ok(builder.build())
+}
+
+/// The layer writing log lines to `writer`
+pub fn log_layer<S, W>(log_format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match log_format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// The layer forwarding our spans to the provider
+/// Only spans of this crate are exported, the AWS SDK and hyper spans would drown them out
+/// The request span is left out too, its id is recorded on the server span instead
+pub fn layer<S>(provider: &TracerProvider) -> impl Layer<S>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(env!("CARGO_CRATE_NAME")))
+        .with_filter(
+            Targets::new()
+                .with_target(env!("CARGO_CRATE_NAME"), LevelFilter::INFO)
+                .with_target(concat!(env!("CARGO_CRATE_NAME"), "::utils::request_id"), LevelFilter::OFF),
+        )
+}
+
+/// Middleware opening the server span of every request
+/// Must be added with route_layer so that the span can be named after the matched route
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let span = info_span!(
+        "http.server",
+        otel.name = %format!("{} {}", method, route),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.request.method = %method,
+        http.route = %route,
+        url.path = %request.uri().path(),
+        http.response.status_code = Empty,
+        request_id = request_id::current(),
+    );
+    let parent_context = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent_context);
+
+    let response = next.run(request).instrument(span.clone()).await;
+
+    span.record("http.response.status_code", response.status().as_u16());
+    if response.status().is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+
+    response
+}
+
+/// Client span of a call to DynamoDB, following the OpenTelemetry semantic conventions for AWS SDK calls
+/// `otel.status_code` and `error.type` are recorded once the call fails
+pub fn dynamodb_span(operation: &str, table: &str) -> Span {
+    info_span!(
+        "dynamodb",
+        otel.name = %format!("DynamoDB.{}", operation),
+        otel.kind = "client",
+        otel.status_code = Empty,
+        error.type = Empty,
+        rpc.system = "aws-api",
+        rpc.service = "DynamoDB",
+        rpc.method = operation,
+        db.system = "dynamodb",
+        db.operation = operation,
+        aws.dynamodb.table_names = table,
+    )
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Writes each span as a line of JSON
+struct JsonLinesExporter {
+    writer: Box<dyn Write + Send + Sync>,
+}
+
+impl JsonLinesExporter {
+    fn new(writer: impl Write + Send + Sync + 'static) -> Self {
+        Self {
+            writer: Box::new(writer),
+        }
+    }
+}
+
+impl fmt::Debug for JsonLinesExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JsonLinesExporter")
+    }
+}
+
+impl SpanExporter for JsonLinesExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let res = batch.iter().try_for_each(|span| {
+            let line = span_to_json(span).to_string();
+            writeln!(self.writer, "{}", line)
+        });
+        let res = res
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| TraceError::from(e.to_string()));
+
+        Box::pin(std::future::ready(res))
+    }
+}
+
+fn span_to_json(span: &SpanData) -> serde_json::Value {
+    let attributes: serde_json::Map<String, serde_json::Value> = span
+        .attributes
+        .iter()
+        .map(|attribute| (attribute.key.to_string(), attribute.value.to_string().into()))
+        .collect();
+    let unix_nanos = |time: std::time::SystemTime| {
+        time.duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64
+    };
+
+    serde_json::json!({
+        "trace_id": span.span_context.trace_id().to_string(),
+        "span_id": span.span_context.span_id().to_string(),
+        "parent_span_id": span.parent_span_id.to_string(),
+        "name": span.name,
+        "kind": format!("{:?}", span.span_kind),
+        "status": format!("{:?}", span.status),
+        "start_time_unix_nano": unix_nanos(span.start_time),
+        "end_time_unix_nano": unix_nanos(span.end_time),
+        "attributes": attributes,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use aws_sdk_dynamodb::{error::SdkError, operation::get_item::GetItemError};
+    use axum::{
+        body::Body, http::Request, middleware, routing::get, Router,
+    };
+    use opentelemetry::global;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::utils::{
+        metrics::record_dynamodb_call,
+        telemetry::{layer, trace_request, tracer_provider, TraceExporter},
+    };
+
+    #[tracing::instrument(skip_all)]
+    async fn get_user(id: String) -> String {
+        record_dynamodb_call("GetItem", "users", async { Ok::<_, SdkError<GetItemError>>(()) })
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn request_spans_continue_the_caller_trace() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("traces-{}.jsonl", uuid::Uuid::new_v4()));
+        let provider = tracer_provider(TraceExporter::File(path.clone()), 1.0, "test".to_string()).unwrap();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer(&provider)));
+        let router = Router::new()
+            .route(
+                "/user/:id",
+                get(|axum::extract::Path(id): axum::extract::Path<String>| get_user(id)),
+            )
+            .route_layer(middleware::from_fn(trace_request));
+        let request = Request::builder()
+            .uri("/user/42")
+            .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .body(Body::empty())
+            .unwrap();
+
+        // Act
+        let response = router.oneshot(request).await.unwrap();
+        let spans: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        let span = |name: &str| spans.iter().find(|span| span["name"] == name).unwrap();
+
+        // Assert
+        assert_eq!(response.status(), 200);
+        let server = span("GET /user/:id");
+        assert_eq!(server["trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(server["parent_span_id"], "00f067aa0ba902b7");
+        assert_eq!(server["kind"], "Server");
+        assert_eq!(server["attributes"]["http.route"], "/user/:id");
+        assert_eq!(server["attributes"]["http.response.status_code"], "200");
+
+        let service = span("get_user");
+        assert_eq!(service["trace_id"], server["trace_id"]);
+        assert_eq!(service["parent_span_id"], server["span_id"]);
+
+        let dynamodb = span("DynamoDB.GetItem");
+        assert_eq!(dynamodb["trace_id"], server["trace_id"]);
+        assert_eq!(dynamodb["parent_span_id"], service["span_id"]);
+        assert_eq!(dynamodb["kind"], "Client");
+        assert_eq!(dynamodb["attributes"]["db.operation"], "GetItem");
+        assert_eq!(dynamodb["attributes"]["aws.dynamodb.table_names"], "users");
+    }
+}
@@ -0,0 +1,343 @@
+// Native TLS termination for instances that run without a fronting proxy
+// The certificate and key are read from TLS_CERT_PATH and TLS_KEY_PATH, and reloaded whenever the files change
+// so that rotated certificates are picked up without a restart, connections that are already open keep the old one
+// When TLS_CLIENT_CA_PATH is set clients must present a certificate signed by one of its CAs,
+// the subject of that certificate is handed to handlers as a ClientCertificate, see extractors/client_certificate.rs
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use axum::http::Request;
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use futures_util::future::BoxFuture;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use rustls::{server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tower::Service;
+
+use crate::{config::AppConfig, extractors::client_certificate::ClientCertificate};
+
+/// Rotations usually touch the certificate and key one after the other, wait for both before reloading
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Paths of the PEM files TLS is configured from
+#[derive(Debug, Clone)]
+pub struct TlsPaths {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl TlsPaths {
+    /// Returns None when TLS_CERT_PATH is not set, in which case the server speaks plain HTTP
+    pub fn from_config(app_config: &AppConfig) -> anyhow::Result<Option<Self>> {
+        let Some(cert_path) = app_config.tls_cert_path.clone() else {
+            return Ok(None);
+        };
+        let key_path = app_config
+            .tls_key_path
+            .clone()
+            .context("TLS_KEY_PATH is required when TLS_CERT_PATH is set")?;
+
+        Ok(Some(Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: app_config.tls_client_ca_path.clone().map(PathBuf::from),
+        }))
+    }
+
+    /// Reads the PEM files into a rustls config
+    pub fn load(&self) -> anyhow::Result<ServerConfig> {
+        let certs = read_certs(&self.cert_path)?;
+        let key = rustls_pemfile::private_key(&mut open(&self.key_path)?)
+            .with_context(|| format!("Unable to read {}", self.key_path.display()))?
+            .with_context(|| format!("No private key found in {}", self.key_path.display()))?;
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_ca_path {
+            Some(client_ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(client_ca_path)? {
+                    roots.add(&cert)?;
+                }
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder.with_single_cert(certs, PrivateKey(key.secret_der().to_vec()))?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+
+    /// Reloads the config whenever one of the files changes, for as long as the returned watcher is kept alive
+    /// A broken rotation is logged and the previous certificate keeps being served
+    pub fn watch(&self, rustls_config: RustlsConfig) -> anyhow::Result<RecommendedWatcher> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if res.is_ok_and(|event| !event.kind.is_access()) {
+                // A reload is already pending if the channel is full
+                let _ = tx.try_send(());
+            }
+        })?;
+
+        // Watch the directories rather than the files, rotations often swap the files out
+        // e.g kubernetes updates mounted secrets by swapping a symlink
+        for path in self.paths() {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+
+        let paths = self.clone();
+        tokio::spawn(async move {
+            while rx.recv().await.is_some() {
+                tokio::time::sleep(RELOAD_DEBOUNCE).await;
+                let _ = rx.try_recv();
+
+                match paths.load() {
+                    Ok(config) => {
+                        rustls_config.reload_from_config(Arc::new(config));
+                        tracing::info!("Reloaded TLS certificate from {}", paths.cert_path.display());
+                    }
+                    Err(e) => tracing::error!("Unable to reload TLS certificate, keeping the current one: {:#}", e),
+                }
+            }
+        });
+
+        Ok(watcher)
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.cert_path, &self.key_path]
+            .into_iter()
+            .chain(self.client_ca_path.as_ref())
+    }
+}
+
+fn open(path: &Path) -> anyhow::Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+
+    Ok(BufReader::new(file))
+}
+
+fn read_certs(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .map(|cert| cert.map(|cert| Certificate(cert.to_vec())))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("No certificate found in {}", path.display());
+    }
+
+    Ok(certs)
+}
+
+/// Terminates TLS and hands the client certificate, if any, to the connection's requests
+#[derive(Clone)]
+pub struct ClientCertificateAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertificateAcceptor {
+    pub fn new(rustls_config: RustlsConfig) -> Self {
+        Self {
+            inner: RustlsAcceptor::new(rustls_config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertificateAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = WithClientCertificate<S>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            // The leaf certificate comes first, it has already been verified against the client CAs
+            let client_certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| ClientCertificate::from_der(&cert.0));
+
+            Ok((
+                stream,
+                WithClientCertificate {
+                    inner: service,
+                    client_certificate,
+                },
+            ))
+        })
+    }
+}
+
+/// Inserts the client certificate into the extensions of every request of the connection
+#[derive(Clone)]
+pub struct WithClientCertificate<S> {
+    inner: S,
+    client_certificate: Option<ClientCertificate>,
+}
+
+impl<S, B> Service<Request<B>> for WithClientCertificate<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        if let Some(client_certificate) = &self.client_certificate {
+            request.extensions_mut().insert(client_certificate.clone());
+        }
+
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{routing::get, Router};
+    use axum_server::tls_rustls::RustlsConfig;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, IsCa};
+
+    use crate::{
+        extractors::client_certificate::ClientCertificate,
+        utils::tls::{ClientCertificateAcceptor, TlsPaths},
+    };
+
+    fn certificate(common_name: &str, is_ca: bool) -> Certificate {
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, common_name);
+        if is_ca {
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        }
+
+        Certificate::from_params(params).unwrap()
+    }
+
+    /// Writes a CA, a server certificate and a client certificate signed by the CA to a fresh directory
+    fn write_certificates() -> (TlsPaths, Certificate, Certificate) {
+        let dir = std::env::temp_dir().join(format!("scaffold-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let ca = certificate("Scaffold CA", true);
+        let server = certificate("localhost", false);
+        let client = certificate("nightly-export", false);
+        let paths = TlsPaths {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            client_ca_path: Some(dir.join("ca.pem")),
+        };
+        std::fs::write(&paths.cert_path, server.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+        std::fs::write(&paths.key_path, server.serialize_private_key_pem()).unwrap();
+        std::fs::write(paths.client_ca_path.as_ref().unwrap(), ca.serialize_pem().unwrap()).unwrap();
+
+        (paths, ca, client)
+    }
+
+    // Test success path, a client with a certificate signed by the CA reaches the handler with its subject
+    #[tokio::test]
+    async fn mutual_tls_exposes_client_certificate_subject() {
+        // Arrange
+        let (paths, ca, client) = write_certificates();
+        let rustls_config = RustlsConfig::from_config(Arc::new(paths.load().unwrap()));
+        let router = Router::new().route(
+            "/whoami",
+            get(|client_certificate: ClientCertificate| async move { client_certificate.subject }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum_server::from_tcp(listener)
+                .acceptor(ClientCertificateAcceptor::new(rustls_config))
+                .serve(router.into_make_service()),
+        );
+
+        let identity = format!(
+            "{}{}",
+            client.serialize_private_key_pem(),
+            client.serialize_pem_with_signer(&ca).unwrap()
+        );
+        let ca_certificate = reqwest::Certificate::from_pem(ca.serialize_pem().unwrap().as_bytes()).unwrap();
+        let client_with_certificate = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(ca_certificate.clone())
+            .identity(reqwest::Identity::from_pem(identity.as_bytes()).unwrap())
+            .build()
+            .unwrap();
+        let client_without_certificate = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(ca_certificate)
+            .build()
+            .unwrap();
+        let url = format!("https://localhost:{}/whoami", address.port());
+
+        // Act
+        let subject = client_with_certificate.get(&url).send().await.unwrap().text().await.unwrap();
+        let rejected = client_without_certificate.get(&url).send().await;
+
+        // Assert
+        assert_eq!(subject, "CN=nightly-export");
+        assert!(rejected.is_err());
+        let _ = std::fs::remove_dir_all(paths.cert_path.parent().unwrap());
+    }
+
+    // Rotated certificates are picked up without a restart
+    #[tokio::test]
+    async fn watch_reloads_rotated_certificate() {
+        // Arrange
+        let (paths, ca, _) = write_certificates();
+        let rustls_config = RustlsConfig::from_config(Arc::new(paths.load().unwrap()));
+        let original = rustls_config.get_inner();
+        let _watcher = paths.watch(rustls_config.clone()).unwrap();
+
+        // Act
+        let rotated = certificate("localhost", false);
+        std::fs::write(&paths.key_path, rotated.serialize_private_key_pem()).unwrap();
+        std::fs::write(&paths.cert_path, rotated.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+
+        // Assert
+        let mut reloaded = false;
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if !Arc::ptr_eq(&original, &rustls_config.get_inner()) {
+                reloaded = true;
+                break;
+            }
+        }
+        assert!(reloaded);
+        let _ = std::fs::remove_dir_all(paths.cert_path.parent().unwrap());
+    }
+}
@@ -0,0 +1,297 @@
+// Declarative validation of request view models
+// Constraints are declared with the utoipa schema attributes that document them, e.g
+// #[schema(min_length = 3, max_length = 32, pattern = "^[a-z]+$")] or #[schema(format = "email")],
+// and enforced by checking the request against the generated schema, so the openapi documentation
+// can never drift from what is actually accepted, see extractors/validated.rs
+// Supported: required, type, min/max_length, pattern, email/uri formats, minimum/maximum, min/max_items and enums
+// Anything else goes in a Validate impl
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use regex::Regex;
+use serde_json::Value;
+use tracing::log::error;
+use utoipa::{
+    openapi::{path::Parameter, schema::SchemaType, Array, KnownFormat, Object, RefOr, Schema, SchemaFormat},
+    OpenApi,
+};
+
+use crate::{errors::FieldError, utils::openapi_generator::ApiDoc};
+
+/// Checks that cannot be expressed as schema constraints e.g comparing two fields
+/// They only run once the request has been deserialized
+pub trait Validate {
+    fn validate(&self) -> Vec<FieldError> {
+        Vec::new()
+    }
+}
+
+/// Schemas registered in ApiDoc, to follow the references from one view model to another
+static COMPONENTS: LazyLock<HashMap<String, RefOr<Schema>>> = LazyLock::new(|| {
+    ApiDoc::openapi()
+        .components
+        .map(|components| components.schemas.into_iter().collect())
+        .unwrap_or_default()
+});
+
+/// Compiled patterns, a handful of them are declared so the cache does not need to be bounded
+static PATTERNS: LazyLock<Mutex<HashMap<String, Regex>>> = LazyLock::new(Default::default);
+
+/// Checks a request body against the schema of its view model
+pub fn validate_schema(value: &Value, schema: &RefOr<Schema>) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    check(value, schema, "", &mut errors);
+    errors
+}
+
+/// Checks query or path parameters, `value` holds the parameters serialized back into an object
+pub fn validate_params(value: &Value, params: &[Parameter]) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    for param in params {
+        if let (Some(value), Some(schema)) = (value.get(&param.name), &param.schema) {
+            check(value, schema, &param.name, &mut errors);
+        }
+    }
+    errors
+}
+
+fn check(value: &Value, schema: &RefOr<Schema>, field: &str, errors: &mut Vec<FieldError>) {
+    let schema = match schema {
+        RefOr::T(schema) => schema,
+        RefOr::Ref(reference) => {
+            let name = reference.ref_location.rsplit('/').next().unwrap_or_default();
+            match COMPONENTS.get(name) {
+                Some(schema) => return check(value, schema, field, errors),
+                // Not registered in ApiDoc, serde still checks it is well formed
+                None => return,
+            }
+        }
+    };
+
+    match schema {
+        Schema::Object(object) => check_object(value, object, field, errors),
+        Schema::Array(array) => check_array(value, array, field, errors),
+        // Option<T> of a referenced schema is documented as allOf [T] with nullable
+        Schema::AllOf(all_of) if !value.is_null() => {
+            for item in &all_of.items {
+                check(value, item, field, errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_object(value: &Value, object: &Object, field: &str, errors: &mut Vec<FieldError>) {
+    if value.is_null() {
+        if !object.nullable {
+            errors.push(FieldError::new(field, "must not be null"));
+        }
+        return;
+    }
+
+    let mut fail = |message: String| errors.push(FieldError::new(field, &message));
+    let type_matches = match object.schema_type {
+        SchemaType::Object => value.is_object(),
+        SchemaType::String => value.is_string(),
+        SchemaType::Integer => value.is_i64() || value.is_u64(),
+        SchemaType::Number => value.is_number(),
+        SchemaType::Boolean => value.is_boolean(),
+        SchemaType::Array => value.is_array(),
+        _ => true,
+    };
+    if !type_matches {
+        let type_name = serde_json::to_value(&object.schema_type).unwrap_or_default();
+        return fail(format!("must be of type {}", type_name.as_str().unwrap_or_default()));
+    }
+
+    if let Some(enum_values) = &object.enum_values {
+        if !enum_values.contains(value) {
+            let allowed: Vec<String> = enum_values.iter().map(Value::to_string).collect();
+            return fail(format!("must be one of {}", allowed.join(", ")));
+        }
+    }
+
+    if let Some(text) = value.as_str() {
+        let length = text.chars().count();
+        if let Some(min_length) = object.min_length.filter(|min_length| length < *min_length) {
+            fail(format!("must be at least {} characters long", min_length));
+        }
+        if let Some(max_length) = object.max_length.filter(|max_length| length > *max_length) {
+            fail(format!("must be at most {} characters long", max_length));
+        }
+        if let Some(pattern) = &object.pattern {
+            if !matches_pattern(text, pattern) {
+                fail(format!("must match the pattern {}", pattern));
+            }
+        }
+        match &object.format {
+            Some(SchemaFormat::Custom(format)) if format == "email" && !is_email(text) => {
+                fail("must be a valid email address".to_string())
+            }
+            Some(SchemaFormat::Custom(format)) if (format == "uri" || format == "url") && !is_url(text) => {
+                fail("must be a valid url".to_string())
+            }
+            Some(SchemaFormat::KnownFormat(KnownFormat::Date))
+                if chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d").is_err() =>
+            {
+                fail("must be a date formatted as YYYY-MM-DD".to_string())
+            }
+            Some(SchemaFormat::KnownFormat(KnownFormat::DateTime))
+                if chrono::DateTime::parse_from_rfc3339(text).is_err() =>
+            {
+                fail("must be an RFC 3339 date time".to_string())
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = object.minimum.filter(|minimum| number < *minimum) {
+            fail(format!("must be at least {}", minimum));
+        }
+        if let Some(maximum) = object.maximum.filter(|maximum| number > *maximum) {
+            fail(format!("must be at most {}", maximum));
+        }
+        if let Some(minimum) = object.exclusive_minimum.filter(|minimum| number <= *minimum) {
+            fail(format!("must be greater than {}", minimum));
+        }
+        if let Some(maximum) = object.exclusive_maximum.filter(|maximum| number >= *maximum) {
+            fail(format!("must be less than {}", maximum));
+        }
+    }
+
+    if let Some(properties) = value.as_object() {
+        for required in &object.required {
+            if !properties.contains_key(required) {
+                errors.push(FieldError::new(&join(field, required), "is required"));
+            }
+        }
+        for (name, schema) in &object.properties {
+            if let Some(property) = properties.get(name) {
+                check(property, schema, &join(field, name), errors);
+            }
+        }
+    }
+}
+
+fn check_array(value: &Value, array: &Array, field: &str, errors: &mut Vec<FieldError>) {
+    let Some(items) = value.as_array() else {
+        if !value.is_null() {
+            errors.push(FieldError::new(field, "must be of type array"));
+        }
+        return;
+    };
+
+    if let Some(min_items) = array.min_items.filter(|min_items| items.len() < *min_items) {
+        errors.push(FieldError::new(field, &format!("must have at least {} items", min_items)));
+    }
+    if let Some(max_items) = array.max_items.filter(|max_items| items.len() > *max_items) {
+        errors.push(FieldError::new(field, &format!("must have at most {} items", max_items)));
+    }
+    for (index, item) in items.iter().enumerate() {
+        check(item, &array.items, &format!("{}[{}]", field, index), errors);
+    }
+}
+
+fn join(field: &str, name: &str) -> String {
+    if field.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", field, name)
+    }
+}
+
+fn matches_pattern(text: &str, pattern: &str) -> bool {
+    let mut patterns = PATTERNS.lock().unwrap();
+    if !patterns.contains_key(pattern) {
+        match Regex::new(pattern) {
+            Ok(regex) => patterns.insert(pattern.to_string(), regex),
+            Err(e) => {
+                // A programming error, the request is not the one at fault
+                error!("Invalid validation pattern {}: {}", pattern, e);
+                return true;
+            }
+        };
+    }
+
+    patterns[pattern].is_match(text)
+}
+
+/// Deliberately loose, whether the address exists can only be told by sending it an email
+fn is_email(text: &str) -> bool {
+    match text.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !text.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+fn is_url(text: &str) -> bool {
+    reqwest::Url::parse(text).is_ok_and(|url| url.has_host())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use utoipa::ToSchema;
+
+    use crate::{
+        domain::user::view_models::CreateUserViewModel,
+        errors::FieldError,
+        utils::validation::validate_schema,
+    };
+
+    #[test]
+    fn validate_schema_reports_every_failing_field() {
+        // Arrange
+        let (_, schema) = CreateUserViewModel::schema();
+        let request = json!({
+            "email": "not-an-email",
+            "username": "a!",
+            "image": "nowhere",
+            "password": 12345678,
+        });
+
+        // Act
+        let errors = validate_schema(&request, &schema);
+
+        // Assert
+        assert_eq!(
+            errors,
+            vec![
+                FieldError::new("email", "must be a valid email address"),
+                FieldError::new("image", "must be a valid url"),
+                FieldError::new("password", "must be of type string"),
+                FieldError::new("username", "must be at least 3 characters long"),
+                FieldError::new("username", "must match the pattern ^[A-Za-z0-9_.-]+$"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_schema_accepts_valid_request() {
+        // Arrange
+        let (_, schema) = CreateUserViewModel::schema();
+        let request = json!({
+            "email": "pp@gmail.com",
+            "username": "pplogin",
+            "image": null,
+            "password": "correct horse battery staple",
+        });
+
+        // Act
+        let errors = validate_schema(&request, &schema);
+
+        // Assert
+        assert!(errors.is_empty());
+    }
+}